@@ -0,0 +1,381 @@
+use std::collections::VecDeque;
+
+use crate::*;
+use chrono_util::*;
+use exchange::Exchange;
+use quote::QuoteEvent;
+use series::SeriesEvent;
+use stored::InferStored;
+
+/// Input to the backtest, must be in time order.
+#[derive(Debug)]
+pub enum BacktestEvent {
+    Quote(QuoteEvent),
+    Prediction(InferStored),
+}
+
+impl BacktestEvent {
+    pub fn timestamp(&self) -> Timestamp {
+        match self {
+            BacktestEvent::Quote(q) => q.timestamp(),
+            BacktestEvent::Prediction(p) => p.timestamp,
+        }
+    }
+}
+
+/// Market order for a signed quantity, positive to buy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Order {
+    pub quantity: f64,
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Fill {
+    pub timestamp: Timestamp,
+    pub quantity: f64,
+    pub price: f64,
+    pub fee: f64,
+}
+
+/// State visible to the strategy.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Portfolio {
+    pub position: f64,
+    pub cash: f64,
+    pub last_bid: f64,
+    pub last_ask: f64,
+}
+
+impl Portfolio {
+    pub fn mid(&self) -> f64 {
+        (self.last_bid + self.last_ask) / 2.0
+    }
+
+    /// Marked at mid.
+    pub fn equity(&self) -> f64 {
+        self.cash + self.position * self.mid()
+    }
+}
+
+pub trait Strategy {
+    fn on_quote(&mut self, _quote: &QuoteEvent, _portfolio: &Portfolio) -> Vec<Order> {
+        Vec::new()
+    }
+
+    fn on_prediction(&mut self, _prediction: &InferStored, _portfolio: &Portfolio) -> Vec<Order> {
+        Vec::new()
+    }
+
+    /// Called when the market date changes, after pending orders were cancelled.
+    fn on_session_reset(&mut self) {
+        // default do nothing
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BacktestConfig {
+    pub initial_cash: f64,
+    /// Millis from order to the earliest quote it can fill against.
+    pub latency: Timestamp,
+    pub fee_per_share: f64,
+    pub fee_bps: f64,
+    /// Close the position at the last quote of each session.
+    pub flatten_at_close: bool,
+    /// Minimum millis between points of the equity curve.
+    pub equity_interval: Timestamp,
+    /// Only events in its regular session on trading days are used. US equities if missing.
+    #[serde(default)]
+    pub exchange: Exchange,
+}
+
+impl Default for BacktestConfig {
+    fn default() -> Self {
+        Self { initial_cash: 100_000.0, latency: 50, fee_per_share: 0.0, fee_bps: 0.0, flatten_at_close: true, equity_interval: 60_000, exchange: Exchange::default() }
+    }
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BacktestStats {
+    pub total_return: f64,
+    /// Annualized from daily returns with 252 trading days.
+    pub sharpe: f64,
+    /// Largest peak to trough drop of equity as a fraction of the peak.
+    pub max_drawdown: f64,
+    /// Traded notional divided by initial cash.
+    pub turnover: f64,
+    pub fills: usize,
+    pub fees: f64,
+    pub days: usize,
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BacktestResult {
+    pub equity_curve: Vec<(Timestamp, f64)>,
+    pub fills: Vec<Fill>,
+    pub stats: BacktestStats,
+}
+
+pub struct Backtest<S: Strategy> {
+    pub config: BacktestConfig,
+    pub strategy: S,
+    portfolio: Portfolio,
+    pending: VecDeque<(Timestamp, Order)>,
+    date: NaiveDate,
+    last_quote_time: Timestamp,
+    daily_equity: Vec<f64>,
+    traded_notional: f64,
+    result: BacktestResult,
+}
+
+impl<S: Strategy> Backtest<S> {
+    pub fn new(config: BacktestConfig, strategy: S) -> Self {
+        let portfolio = Portfolio { cash: config.initial_cash, ..Default::default() };
+        Self {
+            config, strategy, portfolio, pending: VecDeque::new(), date: INVALID_DATE, last_quote_time: 0,
+            daily_equity: Vec::new(), traded_notional: 0.0, result: BacktestResult::default(),
+        }
+    }
+
+    pub fn portfolio(&self) -> &Portfolio {
+        &self.portfolio
+    }
+
+    pub fn run(mut self, events: impl IntoIterator<Item = BacktestEvent>) -> BacktestResult {
+        for event in events {
+            self.handle(event);
+        }
+        self.finish()
+    }
+
+    pub fn handle(&mut self, event: BacktestEvent) {
+        match event {
+            BacktestEvent::Quote(quote) => self.handle_quote(quote),
+            BacktestEvent::Prediction(prediction) => {
+                // Nothing to act on before the first quote
+                if self.date != INVALID_DATE && self.config.exchange.in_regular_session(prediction.timestamp) {
+                    // A prediction before the first quote of a new date belongs to the new session
+                    self.roll_date(prediction.timestamp);
+                    let orders = self.strategy.on_prediction(&prediction, &self.portfolio);
+                    self.submit(prediction.timestamp, orders);
+                }
+            },
+        }
+    }
+
+    /// Ends the current session if ts is on a new session date.
+    fn roll_date(&mut self, ts: Timestamp) {
+        let date = self.config.exchange.session_date(ts);
+        if date != self.date {
+            self.end_session();
+            self.date = date;
+        }
+    }
+
+    fn handle_quote(&mut self, quote: QuoteEvent) {
+        let ts = quote.timestamp();
+        if !self.config.exchange.in_regular_session(ts) {
+            return;
+        }
+        self.roll_date(ts);
+        self.portfolio.last_bid = quote.bid as f64;
+        self.portfolio.last_ask = quote.ask as f64;
+        self.last_quote_time = ts;
+
+        while let Some(&(at, order)) = self.pending.front() {
+            if at + self.config.latency > ts {
+                break;
+            }
+            self.pending.pop_front();
+            self.fill(ts, order.quantity);
+        }
+
+        let orders = self.strategy.on_quote(&quote, &self.portfolio);
+        self.submit(ts, orders);
+
+        let record = self.result.equity_curve.last().is_none_or(|(t, _)| ts - t >= self.config.equity_interval);
+        if record {
+            self.result.equity_curve.push((ts, self.portfolio.equity()));
+        }
+    }
+
+    fn submit(&mut self, ts: Timestamp, orders: Vec<Order>) {
+        self.pending.extend(orders.into_iter().filter(|o| o.quantity != 0.0).map(|o| (ts, o)));
+    }
+
+    /// Buys at the ask, sells at the bid.
+    fn fill(&mut self, ts: Timestamp, quantity: f64) {
+        let price = if quantity > 0.0 { self.portfolio.last_ask } else { self.portfolio.last_bid };
+        let notional = quantity.abs() * price;
+        let fee = quantity.abs() * self.config.fee_per_share + notional * self.config.fee_bps / 10_000.0;
+        self.portfolio.position += quantity;
+        self.portfolio.cash -= quantity * price + fee;
+        self.traded_notional += notional;
+        self.result.fills.push(Fill { timestamp: ts, quantity, price, fee });
+    }
+
+    fn end_session(&mut self) {
+        if self.date == INVALID_DATE {
+            return;
+        }
+        self.pending.clear();
+        if self.config.flatten_at_close && self.portfolio.position != 0.0 {
+            self.fill(self.last_quote_time, -self.portfolio.position);
+        }
+        let equity = self.portfolio.equity();
+        self.result.equity_curve.push((self.last_quote_time, equity));
+        self.daily_equity.push(equity);
+        self.strategy.on_session_reset();
+    }
+
+    pub fn finish(mut self) -> BacktestResult {
+        self.end_session();
+        let initial = self.config.initial_cash;
+        let final_equity = self.daily_equity.last().copied().unwrap_or(initial);
+
+        let mut prev = initial;
+        let returns: Vec<f64> = self.daily_equity.iter().map(|&e| {
+            let r = e / prev - 1.0;
+            prev = e;
+            r
+        }).collect();
+
+        let mut peak = initial;
+        let mut max_drawdown = 0f64;
+        for &(_, e) in &self.result.equity_curve {
+            peak = peak.max(e);
+            max_drawdown = max_drawdown.max((peak - e) / peak);
+        }
+
+        self.result.stats = BacktestStats {
+            total_return: final_equity / initial - 1.0,
+            sharpe: sharpe(&returns),
+            max_drawdown,
+            turnover: self.traded_notional / initial,
+            fills: self.result.fills.len(),
+            fees: self.result.fills.iter().map(|f| f.fee).sum(),
+            days: self.daily_equity.len(),
+        };
+        self.result
+    }
+}
+
+fn sharpe(returns: &[f64]) -> f64 {
+    if returns.len() < 2 {
+        return 0.0;
+    }
+    let n = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / n;
+    let std = (returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt();
+    if std == 0.0 { 0.0 } else { mean / std * 252f64.sqrt() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct BuyOnPrediction {
+        quantity: f64,
+        resets: usize,
+    }
+
+    impl Strategy for BuyOnPrediction {
+        fn on_prediction(&mut self, _prediction: &InferStored, _portfolio: &Portfolio) -> Vec<Order> {
+            vec![Order { quantity: self.quantity }]
+        }
+
+        fn on_session_reset(&mut self) {
+            self.resets += 1;
+        }
+    }
+
+    fn ny(date: (i32, u32, u32), hour: u32, minute: u32, millis: i64) -> Timestamp {
+        let local = NaiveDate::from_ymd_opt(date.0, date.1, date.2).unwrap().and_hms_opt(hour, minute, 0).unwrap();
+        local.and_local_timezone(chrono_tz::US::Eastern).unwrap().timestamp_millis() + millis
+    }
+
+    fn quote(ts: Timestamp, bid: f32, ask: f32) -> BacktestEvent {
+        BacktestEvent::Quote(QuoteEvent::at(ts, bid, ask))
+    }
+
+    fn prediction(ts: Timestamp) -> BacktestEvent {
+        BacktestEvent::Prediction(InferStored { timestamp: ts, ..Default::default() })
+    }
+
+    fn backtest(config: BacktestConfig) -> Backtest<BuyOnPrediction> {
+        Backtest::new(config, BuyOnPrediction { quantity: 10.0, resets: 0 })
+    }
+
+    #[test]
+    fn skips_holidays_and_extended_hours() {
+        let events = vec![
+            // Independence Day
+            quote(ny((2024, 7, 4), 10, 0, 0), 100.0, 100.1),
+            prediction(ny((2024, 7, 4), 10, 0, 1)),
+            // Pre-market the next day
+            quote(ny((2024, 7, 5), 8, 0, 0), 100.0, 100.1),
+            prediction(ny((2024, 7, 5), 8, 0, 1)),
+            quote(ny((2024, 7, 5), 8, 0, 100), 100.0, 100.1),
+        ];
+        let result = backtest(BacktestConfig::default()).run(events);
+        assert!(result.fills.is_empty());
+        assert!(result.equity_curve.is_empty());
+        assert_eq!(result.stats.days, 0);
+    }
+
+    #[test]
+    fn fills_after_latency_with_fees_and_flattens() {
+        let config = BacktestConfig { fee_per_share: 0.01, fee_bps: 10.0, ..BacktestConfig::default() };
+        let t0 = ny((2024, 1, 2), 10, 0, 0);
+        let events = vec![
+            quote(t0, 100.0, 100.1),
+            prediction(t0 + 10),
+            quote(t0 + 40, 100.5, 100.6),
+            quote(t0 + 60, 101.0, 101.1),
+        ];
+        let result = backtest(config).run(events);
+        assert_eq!(result.fills.len(), 2);
+        let (buy, sell) = (result.fills[0], result.fills[1]);
+        assert_eq!((buy.timestamp, buy.quantity), (t0 + 60, 10.0));
+        assert!((buy.price - 101.1).abs() < 1e-4);
+        assert!((buy.fee - (0.1 + 1011.0 * 0.001)).abs() < 1e-3);
+        // Flattened at the bid of the last quote
+        assert_eq!((sell.timestamp, sell.quantity), (t0 + 60, -10.0));
+        assert!((sell.price - 101.0).abs() < 1e-4);
+
+        let pnl = 10.0 * (sell.price - buy.price) - buy.fee - sell.fee;
+        assert!((result.stats.total_return - pnl / 100_000.0).abs() < 1e-12);
+        assert_eq!(result.stats.fills, 2);
+        assert_eq!(result.stats.days, 1);
+    }
+
+    #[test]
+    fn prediction_before_first_quote_of_new_date_acts_on_new_session() {
+        let day1 = ny((2024, 1, 2), 15, 0, 0);
+        let day2 = ny((2024, 1, 3), 9, 30, 0);
+        let mut bt = backtest(BacktestConfig { latency: 0, ..BacktestConfig::default() });
+        bt.handle(quote(day1, 100.0, 100.1));
+        bt.handle(prediction(day2 + 10));
+        // The previous session was closed before the strategy saw the prediction.
+        assert_eq!(bt.strategy.resets, 1);
+        bt.handle(quote(day2 + 1_000, 102.0, 102.1));
+        assert_eq!(bt.portfolio().position, 10.0);
+
+        let result = bt.finish();
+        assert_eq!(result.fills[0].timestamp, day2 + 1_000);
+        assert!((result.fills[0].price - 102.1).abs() < 1e-4);
+        assert_eq!(result.stats.days, 2);
+    }
+
+    #[test]
+    fn sharpe_of_daily_returns() {
+        assert_eq!(sharpe(&[0.01]), 0.0);
+        assert_eq!(sharpe(&[0.01, 0.01]), 0.0);
+        let s = sharpe(&[0.01, -0.01, 0.02]);
+        let (mean, std) = (0.02 / 3.0, (((0.01f64 - 0.02 / 3.0).powi(2) + (-0.01f64 - 0.02 / 3.0).powi(2) + (0.02f64 - 0.02 / 3.0).powi(2)) / 2.0).sqrt());
+        assert!((s - mean / std * 252f64.sqrt()).abs() < 1e-12);
+    }
+}
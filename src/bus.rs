@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::*;
+use data_info::StreamSpec;
+
+/// Storage of topics as ordered logs of serialized messages, with committed offsets per consumer group.
+/// Offsets start at 0 for each topic.
+pub trait Broker: Clone {
+    fn append(&self, topic: &str, payload: Vec<u8>) -> anyhow::Result<OffsetId>;
+    /// Up to max messages starting at offset `from`.
+    fn read(&self, topic: &str, from: OffsetId, max: usize) -> anyhow::Result<Vec<(OffsetId, Vec<u8>)>>;
+    /// The next offset to be written.
+    fn end_offset(&self, topic: &str) -> anyhow::Result<OffsetId>;
+    fn committed(&self, group: &str, topic: &str) -> anyhow::Result<Option<OffsetId>>;
+    fn commit(&self, group: &str, topic: &str, offset: OffsetId) -> anyhow::Result<()>;
+}
+
+pub trait EventPublisher<T> {
+    fn publish(&mut self, event: &T) -> anyhow::Result<OffsetId>;
+}
+
+pub trait EventSubscriber<T> {
+    /// Next events after the current position, with their offsets.
+    fn poll(&mut self, max: usize) -> anyhow::Result<Vec<(OffsetId, T)>>;
+    /// Commits the current position for the consumer group.
+    fn commit(&mut self) -> anyhow::Result<()>;
+    fn seek(&mut self, offset: OffsetId);
+    fn position(&self) -> OffsetId;
+}
+
+// ---- Typed publisher and subscriber ---- //
+
+pub struct Publisher<B: Broker, T> {
+    broker: B,
+    topic: String,
+    _event: PhantomData<T>,
+}
+
+impl<B: Broker, T: Serialize> Publisher<B, T> {
+    pub fn new(broker: B, topic: &str) -> Self {
+        Self { broker, topic: topic.to_string(), _event: PhantomData }
+    }
+
+    pub fn for_stream(broker: B, spec: &impl StreamSpec) -> Self {
+        Self::new(broker, spec.topic_name())
+    }
+}
+
+impl<B: Broker, T: Serialize> EventPublisher<T> for Publisher<B, T> {
+    fn publish(&mut self, event: &T) -> anyhow::Result<OffsetId> {
+        self.broker.append(&self.topic, serde_json::to_vec(event)?)
+    }
+}
+
+pub struct Subscriber<B: Broker, T> {
+    broker: B,
+    topic: String,
+    group: String,
+    position: OffsetId,
+    _event: PhantomData<T>,
+}
+
+impl<B: Broker, T: DeserializeOwned> Subscriber<B, T> {
+    /// Starts after the group's committed offset, or at the beginning if there is none.
+    pub fn new(broker: B, topic: &str, group: &str) -> anyhow::Result<Self> {
+        let position = broker.committed(group, topic)?.map_or(0, |o| o + 1);
+        Ok(Self { broker, topic: topic.to_string(), group: group.to_string(), position, _event: PhantomData })
+    }
+
+    pub fn for_stream(broker: B, spec: &impl StreamSpec, group: &str) -> anyhow::Result<Self> {
+        Self::new(broker, spec.topic_name(), group)
+    }
+}
+
+impl<B: Broker, T: DeserializeOwned> EventSubscriber<T> for Subscriber<B, T> {
+    fn poll(&mut self, max: usize) -> anyhow::Result<Vec<(OffsetId, T)>> {
+        let messages = self.broker.read(&self.topic, self.position, max)?;
+        let mut result = Vec::with_capacity(messages.len());
+        for (offset, payload) in messages {
+            let event = serde_json::from_slice(&payload).with_context(|| format!("Invalid message at {}:{}", self.topic, offset))?;
+            result.push((offset, event));
+            self.position = offset + 1;
+        }
+        Ok(result)
+    }
+
+    fn commit(&mut self) -> anyhow::Result<()> {
+        if self.position > 0 {
+            self.broker.commit(&self.group, &self.topic, self.position - 1)?;
+        }
+        Ok(())
+    }
+
+    fn seek(&mut self, offset: OffsetId) {
+        self.position = offset;
+    }
+
+    fn position(&self) -> OffsetId {
+        self.position
+    }
+}
+
+// ---- In memory ---- //
+
+#[derive(Default)]
+struct MemoryState {
+    topics: HashMap<String, Vec<Vec<u8>>>,
+    committed: HashMap<(String, String), OffsetId>,
+}
+
+/// Broker for tests, clones share the same topics.
+#[derive(Clone, Default)]
+pub struct InMemoryBroker {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl InMemoryBroker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Broker for InMemoryBroker {
+    fn append(&self, topic: &str, payload: Vec<u8>) -> anyhow::Result<OffsetId> {
+        let mut state = self.state.lock().unwrap();
+        let log = state.topics.entry(topic.to_string()).or_default();
+        log.push(payload);
+        Ok(log.len() as OffsetId - 1)
+    }
+
+    fn read(&self, topic: &str, from: OffsetId, max: usize) -> anyhow::Result<Vec<(OffsetId, Vec<u8>)>> {
+        let state = self.state.lock().unwrap();
+        let Some(log) = state.topics.get(topic) else {
+            return Ok(Vec::new());
+        };
+        let start = from.max(0) as usize;
+        Ok(log.iter().enumerate().skip(start).take(max).map(|(i, p)| (i as OffsetId, p.clone())).collect())
+    }
+
+    fn end_offset(&self, topic: &str) -> anyhow::Result<OffsetId> {
+        let state = self.state.lock().unwrap();
+        Ok(state.topics.get(topic).map_or(0, |log| log.len() as OffsetId))
+    }
+
+    fn committed(&self, group: &str, topic: &str) -> anyhow::Result<Option<OffsetId>> {
+        let state = self.state.lock().unwrap();
+        Ok(state.committed.get(&(group.to_string(), topic.to_string())).copied())
+    }
+
+    fn commit(&self, group: &str, topic: &str, offset: OffsetId) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.committed.insert((group.to_string(), topic.to_string()), offset);
+        Ok(())
+    }
+}
+
+// ---- File backed log ---- //
+
+// Byte position of the start of each complete line and the end of the last one.
+#[derive(Default)]
+struct TopicIndex {
+    lines: Vec<u64>,
+    end: u64,
+}
+
+/// Each topic is a file of one json message per line in the directory, offsets are line numbers.
+/// Committed offsets are stored in {group}.{topic}.offset files.
+/// Each topic must have a single writing process, append drops a partial last line left by a crashed writer.
+#[derive(Clone)]
+pub struct FileLogBroker {
+    dir: PathBuf,
+    // Extended as the files grow.
+    index: Arc<Mutex<HashMap<String, TopicIndex>>>,
+}
+
+impl FileLogBroker {
+    pub fn new(dir: PathBuf) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir, index: Arc::new(Mutex::new(HashMap::new())) })
+    }
+
+    fn topic_path(&self, topic: &str) -> anyhow::Result<PathBuf> {
+        if topic.is_empty() || topic.contains(['/', '\\']) {
+            bail!("Invalid topic name {}", topic);
+        }
+        Ok(self.dir.join(format!("{}.log", topic)))
+    }
+
+    fn offset_path(&self, group: &str, topic: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}.offset", group, topic))
+    }
+
+    /// Line start positions of the topic file and the end of the last complete line,
+    /// after indexing anything appended since the last call.
+    fn with_index<R>(&self, topic: &str, f: impl FnOnce(&[u64], u64, &mut File) -> anyhow::Result<R>) -> anyhow::Result<R> {
+        let path = self.topic_path(topic)?;
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(&path)?;
+        let mut index = self.index.lock().unwrap();
+        let TopicIndex { lines, end } = index.entry(topic.to_string()).or_default();
+        let len = file.metadata()?.len();
+        if len > *end {
+            file.seek(SeekFrom::Start(*end))?;
+            let mut reader = BufReader::new(&mut file);
+            let mut pos = *end;
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                let n = reader.read_until(b'\n', &mut line)?;
+                // Only index complete lines, a partial one is still being written or was left by a crash.
+                if n == 0 || line.last() != Some(&b'\n') {
+                    break;
+                }
+                lines.push(pos);
+                pos += n as u64;
+            }
+            *end = pos;
+        }
+        f(lines, *end, &mut file)
+    }
+}
+
+impl Broker for FileLogBroker {
+    fn append(&self, topic: &str, payload: Vec<u8>) -> anyhow::Result<OffsetId> {
+        if payload.contains(&b'\n') {
+            bail!("Message for {} contains a newline", topic);
+        }
+        self.with_index(topic, |lines, end, file| {
+            // The message would be joined to a partial line from a crashed writer, so drop that first.
+            if file.metadata()?.len() > end {
+                file.set_len(end)?;
+            }
+            let mut line = payload;
+            line.push(b'\n');
+            file.write_all(&line)?;
+            Ok(lines.len() as OffsetId)
+        })
+    }
+
+    fn read(&self, topic: &str, from: OffsetId, max: usize) -> anyhow::Result<Vec<(OffsetId, Vec<u8>)>> {
+        self.with_index(topic, |lines, _, file| {
+            let start = from.max(0) as usize;
+            if start >= lines.len() {
+                return Ok(Vec::new());
+            }
+            file.seek(SeekFrom::Start(lines[start]))?;
+            let mut reader = BufReader::new(file);
+            let mut result = Vec::new();
+            for offset in start..lines.len().min(start.saturating_add(max)) {
+                let mut line = Vec::new();
+                reader.read_until(b'\n', &mut line)?;
+                line.pop();
+                result.push((offset as OffsetId, line));
+            }
+            Ok(result)
+        })
+    }
+
+    fn end_offset(&self, topic: &str) -> anyhow::Result<OffsetId> {
+        self.with_index(topic, |lines, _, _| Ok(lines.len() as OffsetId))
+    }
+
+    fn committed(&self, group: &str, topic: &str) -> anyhow::Result<Option<OffsetId>> {
+        let path = self.offset_path(group, topic);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = std::fs::read_to_string(&path)?;
+        Ok(Some(text.trim().parse().with_context(|| format!("Invalid offset file {:?}", path))?))
+    }
+
+    fn commit(&self, group: &str, topic: &str, offset: OffsetId) -> anyhow::Result<()> {
+        let path = self.offset_path(group, topic);
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, offset.to_string())?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use data_info::SERIES1_SIZE;
+    use label::{Horizon, LabelEvent};
+    use labeling::{DirectionConfig, DirectionLabeler, Labeler};
+    use quote::QuoteEvent;
+    use series::SeriesEvent;
+    use synthetic::{SyntheticConfig, SyntheticMarket};
+
+    const RAW: &str = "raw-SPY-quote";
+    const LABELS: &str = "label-SPY-quote";
+    const EXTRA: usize = 100;
+    const HORIZON: OffsetId = 10;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn ingest<B: Broker>(broker: &B) {
+        let mut publisher = Publisher::new(broker.clone(), RAW);
+        for (i, mut event) in SyntheticMarket::new(SyntheticConfig::default()).take(SERIES1_SIZE + EXTRA).enumerate() {
+            let offset = broker.end_offset(RAW).unwrap();
+            event.set_ids(i as EventId, offset);
+            assert_eq!(publisher.publish(&event).unwrap(), offset);
+        }
+    }
+
+    fn label<B: Broker>(broker: &B) {
+        let mut events = Subscriber::<B, QuoteEvent>::new(broker.clone(), RAW, "label").unwrap();
+        let mut publisher = Publisher::new(broker.clone(), LABELS);
+        let labeler = DirectionLabeler::new(DirectionConfig { horizon: Horizon::Events(HORIZON), ..DirectionConfig::default() }).unwrap();
+        let window: Vec<QuoteEvent> = events.poll(usize::MAX).unwrap().into_iter().map(|(_, e)| e).collect();
+        for base in 0..window.len() {
+            if let Some(label) = labeler.label_event(&window, base) {
+                publisher.publish(&label).unwrap();
+            }
+        }
+        events.commit().unwrap();
+    }
+
+    /// Joins the labels to full windows of events ending at the labeled event, like train does.
+    fn train<B: Broker>(broker: &B) -> usize {
+        let mut events = Subscriber::<B, QuoteEvent>::new(broker.clone(), RAW, "train").unwrap();
+        let mut labels = Subscriber::<B, LabelEvent>::new(broker.clone(), LABELS, "train").unwrap();
+        let events: Vec<QuoteEvent> = events.poll(usize::MAX).unwrap().into_iter().map(|(_, e)| e).collect();
+        let mut inputs = 0;
+        for (_, label) in labels.poll(usize::MAX).unwrap() {
+            let end = label.offset_from as usize;
+            assert_eq!(label.offset_to - label.offset_from, HORIZON - 1);
+            assert_eq!(events[end - 1].event_id, label.event_id);
+            if end >= SERIES1_SIZE {
+                let window: VecDeque<QuoteEvent> = events[end - SERIES1_SIZE..end].iter().cloned().collect();
+                convert::series_to_input(&window).unwrap();
+                inputs += 1;
+            }
+        }
+        labels.commit().unwrap();
+        inputs
+    }
+
+    fn pipeline<B: Broker>(broker: B) {
+        ingest(&broker);
+        label(&broker);
+        // The last events can't be labeled until an event after their horizon arrives.
+        assert_eq!(broker.end_offset(LABELS).unwrap() as usize, SERIES1_SIZE + EXTRA - HORIZON as usize - 1);
+        assert_eq!(train(&broker), EXTRA - HORIZON as usize);
+        // Committed groups continue after what they've seen.
+        assert!(Subscriber::<B, QuoteEvent>::new(broker.clone(), RAW, "label").unwrap().poll(10).unwrap().is_empty());
+        assert_eq!(Subscriber::<B, LabelEvent>::new(broker.clone(), LABELS, "train").unwrap().position(), broker.end_offset(LABELS).unwrap());
+        assert_eq!(Subscriber::<B, QuoteEvent>::new(broker, RAW, "other").unwrap().position(), 0);
+    }
+
+    #[test]
+    fn ingest_label_train_in_memory() {
+        pipeline(InMemoryBroker::new());
+    }
+
+    #[test]
+    fn ingest_label_train_file_log() {
+        let dir = temp_dir("bus-pipeline");
+        pipeline(FileLogBroker::new(dir.clone()).unwrap());
+        // A new broker on the same files sees the same topics and commits.
+        let broker = FileLogBroker::new(dir.clone()).unwrap();
+        assert_eq!(broker.end_offset(RAW).unwrap() as usize, SERIES1_SIZE + EXTRA);
+        assert_eq!(broker.committed("label", RAW).unwrap(), Some((SERIES1_SIZE + EXTRA) as OffsetId - 1));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn append_drops_partial_line_from_crashed_writer() {
+        let dir = temp_dir("bus-partial");
+        let broker = FileLogBroker::new(dir.clone()).unwrap();
+        assert_eq!(broker.append("t", b"{\"a\":1}".to_vec()).unwrap(), 0);
+        let mut file = OpenOptions::new().append(true).open(dir.join("t.log")).unwrap();
+        file.write_all(b"{\"a\":").unwrap();
+        // The partial line isn't a message yet.
+        assert_eq!(broker.end_offset("t").unwrap(), 1);
+        assert_eq!(broker.append("t", b"{\"a\":2}".to_vec()).unwrap(), 1);
+        let messages = FileLogBroker::new(dir.clone()).unwrap().read("t", 0, 10).unwrap();
+        assert_eq!(messages, vec![(0, b"{\"a\":1}".to_vec()), (1, b"{\"a\":2}".to_vec())]);
+        assert!(broker.append("t", b"a\nb".to_vec()).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,204 @@
+use chrono::{Datelike, Duration, NaiveTime, Weekday};
+
+use crate::*;
+
+// NYSE holiday and early close rules, so we don't need calendar data to compute session features.
+// TODO: could use calendar data acquired from tradier for special closures
+
+pub fn is_weekend(date: NaiveDate) -> bool {
+    date.weekday().num_days_from_monday() >= 5
+}
+
+pub fn is_holiday(date: NaiveDate) -> bool {
+    let (y, m, d) = (date.year(), date.month(), date.day());
+    let observed = |month: u32, day: u32| NaiveDate::from_ymd_opt(y, month, day).map(observed_date) == Some(date);
+    // New Year's day on a saturday is not observed on the previous friday, which this gets by only checking january.
+    (m == 1 && d <= 2 && observed(1, 1))
+        || (m == 1 && Some(date) == nth_weekday(y, 1, Weekday::Mon, 3))
+        || (m == 2 && Some(date) == nth_weekday(y, 2, Weekday::Mon, 3))
+        || Some(date) == good_friday(y)
+        || (m == 5 && Some(date) == last_weekday(y, 5, Weekday::Mon))
+        || (y >= 2022 && m == 6 && observed(6, 19))
+        || (m == 7 && observed(7, 4))
+        || (m == 9 && Some(date) == nth_weekday(y, 9, Weekday::Mon, 1))
+        || (m == 11 && Some(date) == thanksgiving(y))
+        || (m == 12 && observed(12, 25))
+}
+
+pub fn is_trading_day(date: NaiveDate) -> bool {
+    !is_weekend(date) && !is_holiday(date)
+}
+
+pub fn session_open_time() -> NaiveTime {
+    // unwrap ok because valid time
+    NaiveTime::from_hms_opt(9, 30, 0).unwrap()
+}
+
+/// The day after Thanksgiving, July 3rd and Christmas Eve.
+pub fn is_early_close(date: NaiveDate) -> bool {
+    (Some(date - Duration::days(1)) == thanksgiving(date.year()))
+        || (date.month() == 7 && date.day() == 3 && date.weekday() != Weekday::Fri)
+        || (date.month() == 12 && date.day() == 24 && date.weekday() != Weekday::Fri)
+}
+
+/// The first trading day on or after the date.
+pub fn next_trading_day(date: NaiveDate) -> NaiveDate {
+    let mut d = date;
+    while !is_trading_day(d) {
+        d += Duration::days(1);
+    }
+    d
+}
+
+/// 13:00 on early close days, otherwise 16:00.
+pub fn session_close_time(date: NaiveDate) -> NaiveTime {
+    // unwrap ok because valid times
+    if is_early_close(date) { NaiveTime::from_hms_opt(13, 0, 0).unwrap() } else { NaiveTime::from_hms_opt(16, 0, 0).unwrap() }
+}
+
+/// Trading days after `from` up to and including `to`.
+pub fn trading_days_between(from: NaiveDate, to: NaiveDate) -> i64 {
+    from.iter_days().skip(1).take_while(|d| *d <= to).filter(|d| is_trading_day(*d)).count() as i64
+}
+
+pub fn last_day_of_month(date: NaiveDate) -> NaiveDate {
+    let (y, m) = if date.month() == 12 { (date.year() + 1, 1) } else { (date.year(), date.month() + 1) };
+    // unwrap ok because valid date
+    NaiveDate::from_ymd_opt(y, m, 1).unwrap() - Duration::days(1)
+}
+
+pub fn last_day_of_quarter(date: NaiveDate) -> NaiveDate {
+    let quarter_end_month = (date.month() - 1) / 3 * 3 + 3;
+    // unwrap ok because valid date
+    last_day_of_month(NaiveDate::from_ymd_opt(date.year(), quarter_end_month, 1).unwrap())
+}
+
+/// Third friday of the month, or the thursday before if the friday is a holiday.
+pub fn monthly_expiration(year: i32, month: u32) -> Option<NaiveDate> {
+    let friday = nth_weekday(year, month, Weekday::Fri, 3)?;
+    Some(if is_holiday(friday) { friday - Duration::days(1) } else { friday })
+}
+
+/// The monthly expiration on or after the date.
+pub fn next_monthly_expiration(date: NaiveDate) -> Option<NaiveDate> {
+    let this_month = monthly_expiration(date.year(), date.month())?;
+    if this_month >= date {
+        return Some(this_month);
+    }
+    let (y, m) = if date.month() == 12 { (date.year() + 1, 1) } else { (date.year(), date.month() + 1) };
+    monthly_expiration(y, m)
+}
+
+pub fn nth_weekday(year: i32, month: u32, weekday: Weekday, n: u32) -> Option<NaiveDate> {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, n as u8)
+}
+
+pub fn last_weekday(year: i32, month: u32, weekday: Weekday) -> Option<NaiveDate> {
+    nth_weekday(year, month, weekday, 5).or_else(|| nth_weekday(year, month, weekday, 4))
+}
+
+fn thanksgiving(year: i32) -> Option<NaiveDate> {
+    nth_weekday(year, 11, Weekday::Thu, 4)
+}
+
+/// Saturday holidays are observed on friday, sunday holidays on monday.
+fn observed_date(date: NaiveDate) -> NaiveDate {
+    match date.weekday() {
+        Weekday::Sat => date - Duration::days(1),
+        Weekday::Sun => date + Duration::days(1),
+        _ => date,
+    }
+}
+
+/// Anonymous Gregorian algorithm for Easter sunday.
+fn good_friday(year: i32) -> Option<NaiveDate> {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+    NaiveDate::from_ymd_opt(year, month as u32, day as u32).map(|easter| easter - Duration::days(2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(y: i32, m: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, day).unwrap()
+    }
+
+    #[test]
+    fn fixed_and_floating_holidays() {
+        for date in [
+            d(2024, 1, 1), d(2024, 1, 15), d(2024, 2, 19), d(2024, 5, 27), d(2024, 6, 19),
+            d(2024, 7, 4), d(2024, 9, 2), d(2024, 11, 28), d(2024, 12, 25),
+        ] {
+            assert!(is_holiday(date), "{}", date);
+            assert!(!is_trading_day(date), "{}", date);
+        }
+        assert!(is_trading_day(d(2024, 1, 2)));
+        assert!(!is_trading_day(d(2024, 1, 6)));
+        // Columbus and Veterans day are bank holidays only
+        assert!(is_trading_day(d(2024, 10, 14)) && is_trading_day(d(2024, 11, 11)));
+    }
+
+    #[test]
+    fn good_friday() {
+        for date in [d(2023, 4, 7), d(2024, 3, 29), d(2025, 4, 18), d(2026, 4, 3)] {
+            assert!(is_holiday(date), "{}", date);
+            assert!(!is_holiday(date + Duration::days(3)), "{}", date);
+        }
+        // The April 2025 monthly expiration moves to thursday.
+        assert_eq!(monthly_expiration(2025, 4), Some(d(2025, 4, 17)));
+        assert_eq!(monthly_expiration(2024, 3), Some(d(2024, 3, 15)));
+    }
+
+    #[test]
+    fn observed_holidays() {
+        // Sunday holidays move to monday
+        assert!(is_holiday(d(2023, 1, 2)));
+        assert!(is_holiday(d(2022, 6, 20)));
+        assert!(is_holiday(d(2022, 12, 26)));
+        // Saturday holidays move to friday
+        assert!(is_holiday(d(2021, 12, 24)));
+        assert!(is_holiday(d(2026, 7, 3)));
+        // except New Year's day, which isn't observed in the previous year
+        assert!(is_trading_day(d(2021, 12, 31)));
+        // Juneteenth only from 2022
+        assert!(is_trading_day(d(2021, 6, 18)));
+    }
+
+    #[test]
+    fn early_closes() {
+        for date in [d(2023, 7, 3), d(2024, 7, 3), d(2024, 11, 29), d(2024, 12, 24)] {
+            assert!(is_early_close(date), "{}", date);
+            assert_eq!(session_close_time(date), NaiveTime::from_hms_opt(13, 0, 0).unwrap());
+        }
+        // Not when July 3rd or Christmas Eve is a friday, then it's the observed holiday or a full day.
+        assert!(!is_early_close(d(2026, 7, 3)));
+        assert!(!is_early_close(d(2021, 12, 24)));
+        assert!(!is_early_close(d(2024, 7, 2)));
+        assert_eq!(session_close_time(d(2024, 7, 2)), NaiveTime::from_hms_opt(16, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn trading_day_counts() {
+        assert_eq!(next_trading_day(d(2024, 3, 29)), d(2024, 4, 1));
+        assert_eq!(next_trading_day(d(2024, 4, 1)), d(2024, 4, 1));
+        // 2024-12-24 to 2024-12-31 skips Christmas and a weekend
+        assert_eq!(trading_days_between(d(2024, 12, 24), d(2024, 12, 31)), 4);
+        assert_eq!(last_day_of_quarter(d(2024, 5, 10)), d(2024, 6, 30));
+        assert_eq!(last_day_of_month(d(2024, 2, 10)), d(2024, 2, 29));
+        assert_eq!(next_monthly_expiration(d(2024, 6, 22)), Some(d(2024, 7, 19)));
+    }
+}
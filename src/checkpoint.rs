@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+
+use crate::*;
+use chrono_util::*;
+use gap::GapState;
+use series::EventType;
+use session::Session;
+use series_proc::{BaseHandler, BaseValues, Processor};
+
+/// Version of the checkpoint file format, bump when HandlerSnapshot changes.
+pub const CHECKPOINT_VERSION: VersionType = 2;
+
+/// Saved state of a BaseHandler so a restarted service can continue mid-session.
+#[derive(serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct HandlerSnapshot<T, S> {
+    pub version: VersionType,
+    /// Market date of the most recent event, restore only accepts the same date.
+    pub market_date: NaiveDate,
+    pub created: Timestamp,
+    pub events: VecDeque<T>,
+    /// (index in events, millis) of the events marked by GapPolicy::Marker, the marks aren't serialized with the events.
+    pub gap_marks: Vec<(usize, Timestamp)>,
+    pub start_values: S,
+    pub gaps: GapState,
+    /// SessionFilter's current session, so a session change right after restart still resets.
+    pub session: Option<Session>,
+    pub proc_state: serde_json::Value,
+}
+
+// Same layout as HandlerSnapshot, but borrows so saving doesn't need to clone the events.
+#[derive(serde::Serialize)]
+struct HandlerSnapshotRef<'a, T, S> {
+    version: VersionType,
+    market_date: NaiveDate,
+    created: Timestamp,
+    events: &'a VecDeque<T>,
+    gap_marks: Vec<(usize, Timestamp)>,
+    start_values: &'a S,
+    gaps: GapState,
+    session: Option<Session>,
+    proc_state: serde_json::Value,
+}
+
+pub fn checkpoint_path(name: &str) -> anyhow::Result<PathBuf> {
+    Ok(paths::checkpoint_dir()?.join(format!("{}.v{}.json", name, CHECKPOINT_VERSION)))
+}
+
+impl<S, T, P> BaseHandler<S, T, P>
+where
+    S: Default + BaseValues<T> + Serialize + serde::de::DeserializeOwned,
+    T: EventType + Serialize,
+    P: Processor<VecDeque<T>, S>,
+{
+    /// Writes the events, start values, gap and session state and processor state. Nothing is written if there are no events.
+    pub fn save_checkpoint(&self, path: &Path) -> anyhow::Result<bool> {
+        let Some(last) = self.events.back() else {
+            return Ok(false);
+        };
+        let snapshot = HandlerSnapshotRef {
+            version: CHECKPOINT_VERSION,
+            market_date: self.exchange.session_date(last.timestamp()),
+            created: now(),
+            events: &self.events,
+            gap_marks: self.events.iter().enumerate().filter(|(_, e)| e.gap_before() != 0).map(|(i, e)| (i, e.gap_before())).collect(),
+            start_values: &self.start_values,
+            gaps: self.gaps.state(),
+            session: self.sessions.current(),
+            proc_state: self.proc.snapshot()?,
+        };
+        // Write to a temp file and rename so a crash while writing doesn't leave a corrupt checkpoint.
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec(&snapshot)?).with_context(|| format!("Could not write checkpoint {:?}", tmp))?;
+        std::fs::rename(&tmp, path)?;
+        Ok(true)
+    }
+
+    /// Restores the state if the checkpoint is for the given market date. Returns false if there is no checkpoint file.
+    pub fn restore_checkpoint(&mut self, path: &Path, market_date: NaiveDate) -> anyhow::Result<bool> {
+        if !path.exists() {
+            return Ok(false);
+        }
+        let bytes = std::fs::read(path).with_context(|| format!("Could not read checkpoint {:?}", path))?;
+        let snapshot: HandlerSnapshot<T, S> = serde_json::from_slice(&bytes).with_context(|| format!("Invalid checkpoint {:?}", path))?;
+        if snapshot.version != CHECKPOINT_VERSION {
+            bail!("Checkpoint version {} does not match {}", snapshot.version, CHECKPOINT_VERSION);
+        }
+        if !same_date(snapshot.market_date, market_date) {
+            bail!("Checkpoint is for market date {} but current market date is {}", snapshot.market_date, market_date);
+        }
+        let mut events = snapshot.events;
+        for (i, gap) in snapshot.gap_marks {
+            let Some(event) = events.get_mut(i) else {
+                bail!("Checkpoint gap mark at {} but there are {} events", i, events.len());
+            };
+            event.mark_gap(gap);
+        }
+        self.proc.restore(snapshot.proc_state)?;
+        self.events = events;
+        self.start_values = snapshot.start_values;
+        self.gaps.restore(snapshot.gaps);
+        self.sessions.restore(snapshot.session);
+        Ok(true)
+    }
+
+    /// restore_checkpoint for the current session date on the handler's exchange.
+    pub fn restore_checkpoint_today(&mut self, path: &Path) -> anyhow::Result<bool> {
+        self.restore_checkpoint(path, self.exchange.session_date(now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gap::{GapConfig, GapDetector, GapPolicy};
+    use quote::{QuoteEvent, QuoteValues};
+    use series_proc::EventHandler;
+
+    struct Keep;
+
+    impl Processor<VecDeque<QuoteEvent>, QuoteValues> for Keep {
+        fn process(&mut self, _start_values: &QuoteValues, _events: &mut VecDeque<QuoteEvent>) -> bool {
+            true
+        }
+    }
+
+    type Handler = BaseHandler<QuoteValues, QuoteEvent, Keep>;
+
+    fn marker_handler() -> Handler {
+        let config = GapConfig { policy: GapPolicy::Marker, learn_alpha: 0.5, ..GapConfig::default() };
+        BaseHandler::new_with_gaps(Keep, GapDetector::new(config))
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{}-{}.json", name, std::process::id()))
+    }
+
+    // 2024-01-02 10:00 New York time
+    const OPEN: Timestamp = 1_704_207_600_000;
+
+    #[test]
+    fn restores_gap_marks_and_session() {
+        let mut handler = marker_handler();
+        assert!(!handler.save_checkpoint(&temp_path("empty-checkpoint")).unwrap());
+        for t in [OPEN, OPEN + 1_000, OPEN + 201_000, OPEN + 202_000] {
+            handler.handle(QuoteEvent::at(t, 100.0, 100.1));
+        }
+        assert_eq!(handler.events[2].gap_before, 200_000);
+
+        let path = temp_path("gap-checkpoint");
+        assert!(handler.save_checkpoint(&path).unwrap());
+        // The marks are only in gap_marks, not in the serialized events.
+        let json: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert!(json["events"][2].get("gap_before").is_none());
+        assert_eq!(json["gap_marks"], serde_json::json!([[2, 200_000]]));
+
+        let mut restored = marker_handler();
+        let date = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        assert!(restored.restore_checkpoint(&path, date).unwrap());
+        let gaps: Vec<Timestamp> = restored.events.iter().map(|e| e.gap_before).collect();
+        assert_eq!(gaps, vec![0, 0, 200_000, 0]);
+        assert_eq!(restored.sessions.current(), handler.sessions.current());
+        assert_eq!(restored.gaps.config.expected, handler.gaps.config.expected);
+        assert_eq!(restored.gaps.stats_for(date).unwrap().count, 1);
+        assert_eq!(restored.start_values.bid, 100.0);
+
+        assert!(restored.restore_checkpoint(&path, date.succ_opt().unwrap()).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(!restored.restore_checkpoint(&path, date).unwrap());
+    }
+}
@@ -4,7 +4,9 @@ use chrono::prelude::*;
 use num_traits::AsPrimitive;
 
 use crate::*;
+use exchange::{default_exchange, Exchange, HolidayCalendar};
 
+/// Timezone of the default exchange, streams on other exchanges use their QuoteStreamSpec::exchange.
 pub const MARKET_TIMEZONE: chrono_tz::Tz = chrono_tz::US::Eastern;
 pub const INVALID_DATE: NaiveDate = NaiveDate::MIN;
 
@@ -32,38 +34,220 @@ pub fn to_market_datetime(millis: Timestamp) -> DateTime<chrono_tz::Tz> {
 
 /// Currently just checks if it is a weekday within typical eastern tz trading hours.
 /// TODO: could use calendar data acquired from tradier
+#[deprecated(note = "9:30 to 16:00 on weekdays without holidays, use Exchange::in_regular_session")]
 pub fn dt_in_trading_time(dt: DateTime<chrono_tz::Tz>) -> bool {
     let istime = (NaiveTime::from_hms_opt(9,30,0)..NaiveTime::from_hms_opt(16,0,0)).contains(&Some(dt.time()));
     is_weekday(dt) && istime
 }
 
+#[deprecated(note = "9:30 to 16:00 on weekdays without holidays, use Exchange::in_regular_session")]
 pub fn ts_in_trading_time(ts: Timestamp) -> bool {
+    #[allow(deprecated)]
     dt_in_trading_time(to_market_datetime(ts))
     // let dt = to_market_datetime(ts);
 }
 
+pub fn to_exchange_datetime(exchange: &Exchange, millis: Timestamp) -> DateTime<chrono_tz::Tz> {
+    exchange.to_local(millis)
+}
+
+/// The session date on the exchange, see Exchange::session_date.
+pub fn to_exchange_date(exchange: &Exchange, millis: Timestamp) -> NaiveDate {
+    exchange.session_date(millis)
+}
+
+/// In the exchange's regular session, holiday and early close aware unlike ts_in_trading_time.
+pub fn ts_in_exchange_trading_time(exchange: &Exchange, ts: Timestamp) -> bool {
+    exchange.in_regular_session(ts)
+}
+
 fn is_weekday<Tz: TimeZone>(dt: DateTime<Tz>) -> bool {
     dt.weekday().num_days_from_monday() < 5
 }
 
-pub const CHRONO_FEATURES_SIZE: usize = 7;
+/// Features derived from the timestamp of the base event, see make_chrono_features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ChronoFeature {
+    Second,
+    Minute,
+    Hour,
+    DayOfWeek,
+    DayOfMonth,
+    DayOfQuarter,
+    Month,
+    /// Minutes since the regular open / regular session minutes, 390 for US equities.
+    MinutesSinceOpen,
+    /// Minutes until the close / regular session minutes, early close aware.
+    MinutesUntilClose,
+    /// Fraction of the session elapsed, early close aware.
+    SessionFraction,
+    /// Trading days after today until the end of the month / 23.
+    TradingDaysToMonthEnd,
+    /// Trading days after today until the end of the quarter / 66.
+    TradingDaysToQuarterEnd,
+    /// Calendar days until the next monthly options expiration / 35.
+    DaysToMonthlyExpiration,
+}
+
+impl ChronoFeature {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChronoFeature::Second => "second",
+            ChronoFeature::Minute => "minute",
+            ChronoFeature::Hour => "hour",
+            ChronoFeature::DayOfWeek => "day_of_week",
+            ChronoFeature::DayOfMonth => "day_of_month",
+            ChronoFeature::DayOfQuarter => "day_of_quarter",
+            ChronoFeature::Month => "month",
+            ChronoFeature::MinutesSinceOpen => "minutes_since_open",
+            ChronoFeature::MinutesUntilClose => "minutes_until_close",
+            ChronoFeature::SessionFraction => "session_fraction",
+            ChronoFeature::TradingDaysToMonthEnd => "trading_days_to_month_end",
+            ChronoFeature::TradingDaysToQuarterEnd => "trading_days_to_quarter_end",
+            ChronoFeature::DaysToMonthlyExpiration => "days_to_monthly_expiration",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ChronoFeature::Second => "(second + 1) / 60, UTC",
+            ChronoFeature::Minute => "(minute + 1) / 60, UTC",
+            ChronoFeature::Hour => "(hour + 1) / 24, UTC",
+            ChronoFeature::DayOfWeek => "(weekday from monday + 1) / 7, UTC",
+            ChronoFeature::DayOfMonth => "day / days in month, UTC",
+            ChronoFeature::DayOfQuarter => "days since quarter start / days in quarter, UTC",
+            ChronoFeature::Month => "month / 12, UTC",
+            ChronoFeature::MinutesSinceOpen => "minutes since the open / regular session minutes, exchange time",
+            ChronoFeature::MinutesUntilClose => "minutes until the close / regular session minutes, exchange time, early close aware",
+            ChronoFeature::SessionFraction => "fraction of the session elapsed, exchange time, early close aware",
+            ChronoFeature::TradingDaysToMonthEnd => "trading days on the exchange calendar until month end / 23",
+            ChronoFeature::TradingDaysToQuarterEnd => "trading days on the exchange calendar until quarter end / 66",
+            ChronoFeature::DaysToMonthlyExpiration => "calendar days until the monthly options expiration / 35",
+        }
+    }
+}
+
+/// The chrono features in ChronoFeatures, changing this changes the model input.
+/// It's fixed like SERIES1_SIZE because ChronoFeatures is an array in InputRaw and in the stored and dataset records,
+/// so a change needs a new CURRENT_VERSION. Models on another set use make_chrono_features_for with their own list.
+pub const CHRONO_FEATURE_SET: [ChronoFeature; 13] = [
+    ChronoFeature::Second, ChronoFeature::Minute, ChronoFeature::Hour, ChronoFeature::DayOfWeek,
+    ChronoFeature::DayOfMonth, ChronoFeature::DayOfQuarter, ChronoFeature::Month,
+    ChronoFeature::MinutesSinceOpen, ChronoFeature::MinutesUntilClose, ChronoFeature::SessionFraction,
+    ChronoFeature::TradingDaysToMonthEnd, ChronoFeature::TradingDaysToQuarterEnd, ChronoFeature::DaysToMonthlyExpiration,
+];
+pub const CHRONO_FEATURES_SIZE: usize = CHRONO_FEATURE_SET.len();
 pub type ChronoFeatures = [ModelFloat; CHRONO_FEATURES_SIZE];
 pub const CHRONO_BYTE_SIZE: usize = std::mem::size_of::<ChronoFeatures>();
 
+/// The timestamp in UTC and on the exchange, computed once for all the features of a timestamp.
+#[derive(Debug, Clone, Copy)]
+pub struct ChronoTime {
+    pub utc: DateTime<Utc>,
+    /// See Exchange::session_date.
+    pub session_date: NaiveDate,
+    pub calendar: HolidayCalendar,
+    pub minutes_since_open: f32,
+    /// Early close aware.
+    pub minutes_until_close: f32,
+    /// Length of a full regular session.
+    pub session_minutes: f32,
+}
+
+impl ChronoTime {
+    pub fn new(exchange: &Exchange, timestamp: Timestamp) -> Self {
+        let session_date = exchange.session_date(timestamp);
+        // Whole seconds like the wall clock difference, the minutes are fractional
+        let minutes = |millis: Timestamp| (millis / 1000) as f32 / 60.0;
+        Self {
+            // unwrap ok because timestamps are within the range chrono supports
+            utc: DateTime::<Utc>::from_timestamp_millis(timestamp).unwrap(),
+            session_date,
+            calendar: exchange.calendar,
+            minutes_since_open: minutes(timestamp - exchange.open_timestamp(session_date)),
+            minutes_until_close: minutes(exchange.close_timestamp(session_date) - timestamp),
+            session_minutes: (exchange.schedule.close - exchange.schedule.open) as f32,
+        }
+    }
+}
+
+/// Day counts from the calendar that are the same for every timestamp of a session date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CalendarDays {
+    pub to_month_end: i64,
+    pub to_quarter_end: i64,
+    pub to_monthly_expiration: i64,
+}
+
+thread_local! {
+    // Consecutive builds are almost always on the same date, so this skips walking the calendar each time.
+    static CALENDAR_DAYS: std::cell::Cell<Option<(HolidayCalendar, NaiveDate, CalendarDays)>> = const { std::cell::Cell::new(None) };
+}
+
+pub fn calendar_days(calendar: HolidayCalendar, date: NaiveDate) -> CalendarDays {
+    if let Some((cached_calendar, cached_date, days)) = CALENDAR_DAYS.get() {
+        if cached_calendar == calendar && cached_date == date {
+            return days;
+        }
+    }
+    let days = CalendarDays {
+        to_month_end: calendar.trading_days_between(date, calendar::last_day_of_month(date)),
+        to_quarter_end: calendar.trading_days_between(date, calendar::last_day_of_quarter(date)),
+        to_monthly_expiration: calendar::next_monthly_expiration(date).map_or(0, |exp| exp.signed_duration_since(date).num_days()),
+    };
+    CALENDAR_DAYS.set(Some((calendar, date, days)));
+    days
+}
+
+/// make_chrono_features_on the default exchange.
 // TODO: consider embedding time as a function of year with many sinusiods instead
 pub fn make_chrono_features(timestamp: Timestamp) -> ChronoFeatures {
-    let date_time = DateTime::<Utc>::from_timestamp_millis(timestamp).unwrap();
+    make_chrono_features_on(default_exchange(), timestamp)
+}
+
+/// The session features are relative to the exchange's sessions and calendar, the rest are UTC.
+pub fn make_chrono_features_on(exchange: &Exchange, timestamp: Timestamp) -> ChronoFeatures {
+    let time = ChronoTime::new(exchange, timestamp);
+    let mut result = [0.0; CHRONO_FEATURES_SIZE];
+    for (x, feature) in result.iter_mut().zip(CHRONO_FEATURE_SET) {
+        *x = chrono_feature_at(feature, &time);
+    }
+    result
+}
+
+/// Same as make_chrono_features_on for a configured set of features.
+pub fn make_chrono_features_for(exchange: &Exchange, features: &[ChronoFeature], timestamp: Timestamp) -> Vec<ModelFloat> {
+    let time = ChronoTime::new(exchange, timestamp);
+    features.iter().map(|f| chrono_feature_at(*f, &time)).collect()
+}
+
+pub fn chrono_feature(exchange: &Exchange, feature: ChronoFeature, timestamp: Timestamp) -> ModelFloat {
+    chrono_feature_at(feature, &ChronoTime::new(exchange, timestamp))
+}
+
+pub fn chrono_feature_at(feature: ChronoFeature, time: &ChronoTime) -> ModelFloat {
+    let date_time = &time.utc;
     let naive_date = date_time.naive_utc().date();
-    let second = embed(date_time.second() + 1, 60.0);
-    let minute = embed(date_time.minute() + 1, 60.0);
-    let hour = embed(date_time.hour() + 1, 24.0);
-    let day_of_week = embed(date_time.weekday() as u8 + 1, 7.0);
-    let day_of_month = embed(date_time.day(), num_days_in_month(date_time.year(), date_time.month()));
-    let day_of_quarter = embed(day_of_quarter(naive_date), num_days_in_quarter(naive_date));
-    // let day_of_year = embed(day_of_year(naive_date), num_days_in_year(naive_date));
-    let month = embed(date_time.month(), 12.0);
-    // embed(day_of_year(naive_date), num_days_in_year(naive_date));
-    [second, minute, hour, day_of_week, day_of_month, day_of_quarter, month]
+    match feature {
+        ChronoFeature::Second => embed(date_time.second() + 1, 60.0),
+        ChronoFeature::Minute => embed(date_time.minute() + 1, 60.0),
+        ChronoFeature::Hour => embed(date_time.hour() + 1, 24.0),
+        ChronoFeature::DayOfWeek => embed(date_time.weekday() as u8 + 1, 7.0),
+        ChronoFeature::DayOfMonth => embed(date_time.day(), num_days_in_month(date_time.year(), date_time.month())),
+        ChronoFeature::DayOfQuarter => embed(day_of_quarter(naive_date), num_days_in_quarter(naive_date)),
+        ChronoFeature::Month => embed(date_time.month(), 12.0),
+        ChronoFeature::MinutesSinceOpen => time.minutes_since_open / time.session_minutes,
+        ChronoFeature::MinutesUntilClose => time.minutes_until_close / time.session_minutes,
+        ChronoFeature::SessionFraction => {
+            let since = time.minutes_since_open;
+            (since / (since + time.minutes_until_close)).clamp(0.0, 1.0)
+        },
+        ChronoFeature::TradingDaysToMonthEnd => embed(calendar_days(time.calendar, time.session_date).to_month_end, 23.0),
+        ChronoFeature::TradingDaysToQuarterEnd => embed(calendar_days(time.calendar, time.session_date).to_quarter_end, 66.0),
+        ChronoFeature::DaysToMonthlyExpiration => embed(calendar_days(time.calendar, time.session_date).to_monthly_expiration, 35.0),
+    }
 }
 
 fn embed<N: AsPrimitive<f32>, M: AsPrimitive<f32>>(x: N, max_val: M) -> ModelFloat {
@@ -127,3 +311,100 @@ fn first_month_in_quarter(month: u32) -> u32 {
 // fn month_of_quarter(date: &NaiveDate) -> u32 {
 //     1 + 3 * ((date.month() - 1) / 3)
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn market(y: i32, m: u32, d: u32, hour: u32, minute: u32) -> Timestamp {
+        MARKET_TIMEZONE.with_ymd_and_hms(y, m, d, hour, minute, 0).unwrap().timestamp_millis()
+    }
+
+    fn feature(feature: ChronoFeature, ts: Timestamp) -> ModelFloat {
+        chrono_feature(default_exchange(), feature, ts)
+    }
+
+    #[test]
+    fn session_features_at_open_and_close() {
+        let open = market(2024, 1, 2, 9, 30);
+        assert_eq!(feature(ChronoFeature::MinutesSinceOpen, open), 0.0);
+        assert_eq!(feature(ChronoFeature::MinutesUntilClose, open), 1.0);
+        assert_eq!(feature(ChronoFeature::SessionFraction, open), 0.0);
+        let close = market(2024, 1, 2, 16, 0);
+        assert_eq!(feature(ChronoFeature::MinutesSinceOpen, close), 1.0);
+        assert_eq!(feature(ChronoFeature::MinutesUntilClose, close), 0.0);
+        assert_eq!(feature(ChronoFeature::SessionFraction, close), 1.0);
+        // Before the open the fraction is clamped, the minutes are not.
+        assert_eq!(feature(ChronoFeature::SessionFraction, market(2024, 1, 2, 8, 0)), 0.0);
+        assert_eq!(feature(ChronoFeature::MinutesSinceOpen, market(2024, 1, 2, 8, 0)), -90.0 / 390.0);
+    }
+
+    #[test]
+    fn session_fraction_on_early_close() {
+        // The day after Thanksgiving closes at 13:00, so 11:15 is halfway.
+        let ts = market(2024, 11, 29, 11, 15);
+        assert_eq!(feature(ChronoFeature::SessionFraction, ts), 0.5);
+        assert_eq!(feature(ChronoFeature::MinutesUntilClose, ts), 105.0 / 390.0);
+        assert_eq!(feature(ChronoFeature::SessionFraction, market(2024, 11, 29, 13, 0)), 1.0);
+        assert_eq!(feature(ChronoFeature::SessionFraction, market(2024, 11, 27, 12, 45)), 0.5);
+    }
+
+    #[test]
+    fn month_end_on_holiday() {
+        // May 31 2021 was Memorial Day, so friday the 28th is the last trading day of the month.
+        assert_eq!(feature(ChronoFeature::TradingDaysToMonthEnd, market(2021, 5, 28, 10, 0)), 0.0);
+        assert_eq!(feature(ChronoFeature::TradingDaysToMonthEnd, market(2021, 5, 27, 10, 0)), 1.0 / 23.0);
+        // The quarter of 2024 ended on a sunday after Good Friday.
+        assert_eq!(feature(ChronoFeature::TradingDaysToQuarterEnd, market(2024, 3, 28, 10, 0)), 0.0);
+        assert_eq!(feature(ChronoFeature::TradingDaysToQuarterEnd, market(2024, 3, 26, 10, 0)), 2.0 / 66.0);
+    }
+
+    #[test]
+    fn days_to_third_friday_expiration() {
+        assert_eq!(feature(ChronoFeature::DaysToMonthlyExpiration, market(2024, 6, 21, 10, 0)), 0.0);
+        assert_eq!(feature(ChronoFeature::DaysToMonthlyExpiration, market(2024, 6, 20, 10, 0)), 1.0 / 35.0);
+        // After the expiration it counts to the next month's, July 19.
+        assert_eq!(feature(ChronoFeature::DaysToMonthlyExpiration, market(2024, 6, 22, 10, 0)), 27.0 / 35.0);
+        // The third friday of April 2025 was Good Friday, so it expired on the thursday.
+        assert_eq!(feature(ChronoFeature::DaysToMonthlyExpiration, market(2025, 4, 14, 10, 0)), 3.0 / 35.0);
+    }
+
+    #[test]
+    fn make_chrono_features_matches_single_features() {
+        let ts = market(2024, 3, 27, 15, 59);
+        let all = make_chrono_features(ts);
+        for (i, f) in CHRONO_FEATURE_SET.iter().enumerate() {
+            assert_eq!(all[i], feature(*f, ts), "{}", f.name());
+        }
+        assert_eq!(make_chrono_features_for(default_exchange(), &[ChronoFeature::SessionFraction, ChronoFeature::Month], ts), vec![all[9], all[6]]);
+        // The cached day counts follow the date and calendar.
+        let good_friday = NaiveDate::from_ymd_opt(2024, 3, 29).unwrap();
+        assert_eq!(calendar_days(HolidayCalendar::Nyse, good_friday - chrono::Duration::days(1)).to_month_end, 0);
+        assert_eq!(calendar_days(HolidayCalendar::Nyse, good_friday - chrono::Duration::days(2)).to_month_end, 1);
+        assert_eq!(calendar_days(HolidayCalendar::WeekendsOnly, good_friday - chrono::Duration::days(2)).to_month_end, 2);
+    }
+
+    #[test]
+    fn session_features_follow_the_exchange() {
+        let xetra = Exchange::xetra();
+        let cme = Exchange::cme_equity_futures();
+        let local = |exchange: &Exchange, (y, m, d): (i32, u32, u32), hour: u32, minute: u32| {
+            exchange.timezone.with_ymd_and_hms(y, m, d, hour, minute, 0).unwrap().timestamp_millis()
+        };
+        // Xetra is open 9:00 to 17:30 Frankfurt time, 510 minutes.
+        let xetra_open = make_chrono_features_on(&xetra, local(&xetra, (2024, 1, 2), 9, 0));
+        assert_eq!((xetra_open[7], xetra_open[8], xetra_open[9]), (0.0, 1.0, 0.0));
+        let xetra_mid = make_chrono_features_on(&xetra, local(&xetra, (2024, 1, 2), 13, 15));
+        assert_eq!(xetra_mid[9], 0.5);
+        assert_eq!(xetra_mid[7], 255.0 / 510.0);
+        // CME opens 17:00 Chicago the evening before the session date and closes at 16:00.
+        let cme_open = make_chrono_features_on(&cme, local(&cme, (2024, 1, 1), 17, 0));
+        assert_eq!((cme_open[7], cme_open[9]), (0.0, 0.0));
+        let cme_close = make_chrono_features_on(&cme, local(&cme, (2024, 1, 2), 16, 0));
+        assert_eq!((cme_close[8], cme_close[9]), (0.0, 1.0));
+        // Good Friday is a trading day on the weekends only calendar.
+        let before_easter = local(&xetra, (2024, 3, 27), 10, 0);
+        assert_eq!(make_chrono_features_on(&xetra, before_easter)[10], 2.0 / 23.0);
+        assert_eq!(make_chrono_features(before_easter)[10], 1.0 / 23.0);
+    }
+}
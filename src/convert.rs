@@ -2,7 +2,8 @@ use std::{collections::VecDeque, iter::zip};
 
 use crate::*;
 use data_info::*;
-use chrono_util::make_chrono_features;
+use chrono_util::{make_chrono_features_on, ChronoFeatures};
+use exchange::{default_exchange, Exchange};
 use quote::QuoteEvent;
 use series::SeriesEvent;
 
@@ -20,12 +21,17 @@ pub fn serialize_timestamp(timestamp: &UtcDateTime) -> Timestamp {
 
 
 // TODO: use maybeuninit?
-fn new_series() -> Series {
+pub fn new_series() -> Series {
     [SeriesItem::default(); SERIES1_SIZE]
     // [[ModelFloat::default(); TIME_ENCODING_SIZE + FEATURES1_SIZE]; SERIES1_SIZE]
 }
 
 pub fn series_to_input(events: &VecDeque<QuoteEvent>) -> anyhow::Result<InputRaw> {
+    series_to_input_on(default_exchange(), events)
+}
+
+/// With the chrono features on the stream's exchange.
+pub fn series_to_input_on(exchange: &Exchange, events: &VecDeque<QuoteEvent>) -> anyhow::Result<InputRaw> {
     assert!(events.len() == SERIES1_SIZE); // this is also checked before call above
     let mut input = new_series();
 
@@ -64,14 +70,166 @@ pub fn series_to_input(events: &VecDeque<QuoteEvent>) -> anyhow::Result<InputRaw
     }
     assert!(zero_count < 2*events.len());
 
-    Ok((make_chrono_features(base_time), input))
+    Ok((make_chrono_features_on(exchange, base_time), input))
+}
+
+/// How SeriesBuilder computes the time embedding, trading exactness for trig per push.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum EmbeddingMode {
+    /// Same f32 sin/cos as series_to_input, so the output is bit identical. The embedding is of base - event,
+    /// so once full each push recomputes it for the whole window, SERIES1_SIZE * TIME_EMBEDDING_SIZE sin/cos.
+    /// For checking parity with series_to_input rather than live use.
+    Exact,
+    /// sin/cos of each event's phase are kept and rotated to the base event with the angle addition identities,
+    /// so a push does TIME_EMBEDDING_SIZE sin/cos and a build none, apart from reanchoring once an hour.
+    /// Each embedding value is within angle * f32::EPSILON + 1e-6 of series_to_input,
+    /// where angle is the f32 argument series_to_input passes to sin or cos.
+    #[default]
+    AngleAddition,
+}
+
+const EMBEDDING_PAIRS: usize = TIME_EMBEDDING_SIZE / 2;
+// Phases are relative to an anchor time, moved forward when the base gets this far from it to keep precision.
+const MAX_ANCHOR_AGE: Timestamp = 60 * 60 * 1000;
+
+struct CachedEvent {
+    timestamp: Timestamp,
+    bid: SeriesFloat,
+    ask: SeriesFloat,
+    // sin and cos of div_term * (timestamp - anchor) for each pair, only for EmbeddingMode::AngleAddition.
+    phases: [(f64, f64); EMBEDDING_PAIRS],
+    // Embedding relative to the newest event, only for EmbeddingMode::Exact.
+    embedding: [ModelFloat; TIME_EMBEDDING_SIZE],
+}
+
+/// Incremental series_to_input for live events: keeps the last SERIES1_SIZE events in a ring buffer
+/// with what can be computed per event, so a build only does the base relative work.
+pub struct SeriesBuilder {
+    mode: EmbeddingMode,
+    embedder: TimeEmbedder<TIME_EMBEDDING_SIZE>,
+    events: VecDeque<CachedEvent>,
+    anchor: Timestamp,
+    exchange: Exchange,
+}
+
+impl Default for SeriesBuilder {
+    fn default() -> Self {
+        Self::new(EmbeddingMode::default())
+    }
+}
+
+impl SeriesBuilder {
+    pub fn new(mode: EmbeddingMode) -> Self {
+        Self { mode, embedder: TimeEmbedder::new(), events: VecDeque::with_capacity(SERIES1_SIZE), anchor: 0, exchange: Exchange::default() }
+    }
+
+    /// Chrono features on the stream's exchange instead of the default one.
+    pub fn with_exchange(mut self, exchange: Exchange) -> Self {
+        self.exchange = exchange;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.events.len() == SERIES1_SIZE
+    }
+
+    pub fn reset(&mut self) {
+        self.events.clear();
+    }
+
+    /// Adds the event as the newest, dropping the oldest when full.
+    pub fn push(&mut self, event: &QuoteEvent) {
+        if self.is_full() {
+            self.events.pop_front();
+        }
+        let timestamp = event.timestamp();
+        if self.mode == EmbeddingMode::AngleAddition && (self.events.is_empty() || timestamp - self.anchor > MAX_ANCHOR_AGE) {
+            self.reanchor(timestamp);
+        }
+        let phases = self.phases(timestamp);
+        self.events.push_back(CachedEvent { timestamp, bid: event.bid, ask: event.ask, phases, embedding: [0.0; TIME_EMBEDDING_SIZE] });
+        // Nothing can be built before it's full, so the embeddings are only needed from then.
+        if self.mode == EmbeddingMode::Exact && self.is_full() {
+            let embedder = &self.embedder;
+            for cached in self.events.iter_mut() {
+                cached.embedding = embedder.embed(timestamp - cached.timestamp);
+            }
+        }
+    }
+
+    /// Same as series_to_input over the pushed events up to the EmbeddingMode's rounding, None until SERIES1_SIZE events have been pushed.
+    pub fn build(&self) -> Option<InputRaw> {
+        let mut input = new_series();
+        self.build_into(&mut input).map(|chrono| (chrono, input))
+    }
+
+    /// Fills the series and returns the chrono features, or None if not full.
+    pub fn build_into(&self, series: &mut Series) -> Option<ChronoFeatures> {
+        if !self.is_full() {
+            return None;
+        }
+        // unwrap ok because full
+        let base = self.events.back().unwrap();
+        let base_phases = self.phases(base.timestamp);
+        for (event, item) in zip(&self.events, series.iter_mut()) {
+            item[0] = adjust(base.bid / event.bid);
+            item[1] = adjust(base.ask / event.ask);
+            let embedding = &mut item[2..(2 + TIME_EMBEDDING_SIZE)];
+            match self.mode {
+                EmbeddingMode::Exact => embedding.copy_from_slice(&event.embedding),
+                EmbeddingMode::AngleAddition => {
+                    for (pair, ((sin_b, cos_b), (sin_e, cos_e))) in zip(base_phases, event.phases).enumerate() {
+                        // sin(b - e) and cos(b - e)
+                        embedding[2 * pair] = (sin_b * cos_e - cos_b * sin_e) as ModelFloat;
+                        embedding[2 * pair + 1] = (cos_b * cos_e + sin_b * sin_e) as ModelFloat;
+                    }
+                },
+            }
+        }
+        Some(make_chrono_features_on(&self.exchange, base.timestamp))
+    }
+
+    fn phases(&self, timestamp: Timestamp) -> [(f64, f64); EMBEDDING_PAIRS] {
+        let mut result = [(0.0, 1.0); EMBEDDING_PAIRS];
+        if self.mode == EmbeddingMode::AngleAddition {
+            for (pair, x) in result.iter_mut().enumerate() {
+                let phase = self.embedder.div_term(2 * pair) as f64 * (timestamp - self.anchor) as f64;
+                *x = phase.sin_cos();
+            }
+        }
+        result
+    }
+
+    fn reanchor(&mut self, anchor: Timestamp) {
+        self.anchor = anchor;
+        for i in 0..self.events.len() {
+            self.events[i].phases = self.phases(self.events[i].timestamp);
+        }
+    }
 }
 
 const MAX_TIME_SCALE: ModelFloat = 60f32 * 60f32 * 1000f32; // 1 hour in milliseconds
 
 pub struct TimeEmbedder<const D: usize> {
     // width: usize,
-    log_timescale_increment: ModelFloat,
+    // exp(k * log_timescale_increment) for even k, so embed doesn't need exp.
+    div_terms: [ModelFloat; D],
+}
+
+impl<const D: usize> Default for TimeEmbedder<D> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 // const LOG_TIMESCALE_INCREMENT: ModelFloat = -(MAX_TIME_SCALE).ln() / TIME_ENCODING_SIZE as ModelFloat;
@@ -80,7 +238,16 @@ pub struct TimeEmbedder<const D: usize> {
 impl<const D: usize> TimeEmbedder<D> {
     pub fn new() -> Self {
         let log_timescale_increment = -(MAX_TIME_SCALE).ln() / D as ModelFloat;
-        Self { log_timescale_increment }
+        let mut div_terms = [0.0; D];
+        for k in (0..D).step_by(2) {
+            div_terms[k] = (k as ModelFloat * log_timescale_increment).exp();
+        }
+        Self { div_terms }
+    }
+
+    /// Frequency of the sin/cos pair at k, for even k.
+    pub fn div_term(&self, k: usize) -> ModelFloat {
+        self.div_terms[k]
     }
 
     // Adapted from burn::nn::pos_encodings::generate_sinusoids
@@ -91,7 +258,7 @@ impl<const D: usize> TimeEmbedder<D> {
         let time_model = time as ModelFloat;
         // let mut row = Vec::with_capacity(self.width);
         for k in (0..D).step_by(2) {
-            let div_term = (k as ModelFloat * self.log_timescale_increment).exp();
+            let div_term = self.div_terms[k];
             result[k] = (div_term * time_model).sin();
             result[k+1] = (div_term * time_model).cos();
         }
@@ -159,6 +326,107 @@ impl<const D: usize> TimeEmbedder<D> {
 // //     Tensor::<B, 2>::from_data(data.convert(), device)
 // // }
 
-fn adjust(x: f32) -> f32 {
+pub fn adjust(x: f32) -> f32 {
     (x - 0.5).clamp(0.0, 1.0)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use synthetic::{SyntheticConfig, SyntheticMarket};
+
+    // Quotes a second apart with a two hour gap after the first 1100, so AngleAddition reanchors.
+    fn events_with_gap() -> Vec<QuoteEvent> {
+        (0..SERIES1_SIZE as i64 + 200).map(|i| {
+            let ts = 1_704_205_800_000 + i * 1_000 + if i >= 1_100 { 2 * 60 * 60 * 1_000 } else { 0 };
+            let mid = 100.0 + (i as f32 / 10.0).sin();
+            QuoteEvent::at(ts, mid - 0.01, mid + 0.01)
+        }).collect()
+    }
+
+    // Pushes the events and compares each build with series_to_input over the same window.
+    fn compare(mode: EmbeddingMode, events: &[QuoteEvent], check: impl Fn(&InputRaw, &InputRaw, &VecDeque<QuoteEvent>)) -> usize {
+        let mut builder = SeriesBuilder::new(mode);
+        let mut window = VecDeque::with_capacity(SERIES1_SIZE);
+        let mut builds = 0;
+        for event in events {
+            builder.push(event);
+            if window.len() == SERIES1_SIZE {
+                window.pop_front();
+            }
+            window.push_back(event.clone());
+            assert_eq!(builder.is_full(), window.len() == SERIES1_SIZE);
+            match builder.build() {
+                Some(built) => {
+                    check(&built, &series_to_input(&window).unwrap(), &window);
+                    builds += 1;
+                },
+                None => assert!(!builder.is_full()),
+            }
+        }
+        builds
+    }
+
+    #[test]
+    fn exact_build_is_identical_to_series_to_input() {
+        let synthetic: Vec<QuoteEvent> = SyntheticMarket::new(SyntheticConfig::default()).take(SERIES1_SIZE + 100).collect();
+        for events in [synthetic, events_with_gap()] {
+            let builds = compare(EmbeddingMode::Exact, &events, |built, expected, _| {
+                assert_eq!(built.0, expected.0);
+                // Bit identical, not just equal within rounding
+                assert!(built.1.as_flattened().iter().zip(expected.1.as_flattened()).all(|(a, b)| a.to_bits() == b.to_bits()));
+            });
+            assert_eq!(builds, events.len() - SERIES1_SIZE + 1);
+        }
+    }
+
+    #[test]
+    fn default_angle_addition_is_within_rounding() {
+        assert_eq!(EmbeddingMode::default(), EmbeddingMode::AngleAddition);
+        let embedder = TimeEmbedder::<TIME_EMBEDDING_SIZE>::new();
+        let synthetic: Vec<QuoteEvent> = SyntheticMarket::new(SyntheticConfig::default()).take(SERIES1_SIZE + 100).collect();
+        for events in [synthetic, events_with_gap()] {
+            compare(EmbeddingMode::default(), &events, |built, expected, window| {
+                assert_eq!(built.0, expected.0);
+                let base = window[SERIES1_SIZE - 1].timestamp();
+                for ((a, b), event) in built.1.iter().zip(&expected.1).zip(window) {
+                    assert_eq!(a[..FEATURES1_SIZE], b[..FEATURES1_SIZE]);
+                    for k in 0..TIME_EMBEDDING_SIZE {
+                        // series_to_input rounds the f32 angle, an ulp of it plus the rounding of the result
+                        let angle = embedder.div_term(k - k % 2) * (base - event.timestamp()) as ModelFloat;
+                        let bound = angle * f32::EPSILON + 1e-6;
+                        let (x, y) = (a[FEATURES1_SIZE + k], b[FEATURES1_SIZE + k]);
+                        assert!((x - y).abs() <= bound, "{} {} {}", x, y, bound);
+                    }
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn reset_empties_the_window() {
+        let events = events_with_gap();
+        let mut builder = SeriesBuilder::new(EmbeddingMode::Exact);
+        events[..SERIES1_SIZE].iter().for_each(|e| builder.push(e));
+        assert!(builder.build().is_some());
+        builder.reset();
+        assert!(builder.is_empty() && builder.build().is_none());
+        events[1..=SERIES1_SIZE].iter().for_each(|e| builder.push(e));
+        let mut series = new_series();
+        let chrono = builder.build_into(&mut series).unwrap();
+        assert_eq!((chrono, series), series_to_input(&events[1..=SERIES1_SIZE].iter().cloned().collect()).unwrap());
+    }
+
+    #[test]
+    fn chrono_features_on_builder_exchange() {
+        let events = events_with_gap();
+        let window: VecDeque<QuoteEvent> = events[..SERIES1_SIZE].iter().cloned().collect();
+        let mut builder = SeriesBuilder::new(EmbeddingMode::Exact).with_exchange(Exchange::xetra());
+        window.iter().for_each(|e| builder.push(e));
+        let built = builder.build().unwrap();
+        assert_eq!(built, series_to_input_on(&Exchange::xetra(), &window).unwrap());
+        // The base event at 9:47:03 New York is 15:47:03 in Frankfurt, late in the Xetra session.
+        assert_ne!(built.0, series_to_input(&window).unwrap().0);
+        assert_eq!(built.0[7], 24_423.0 / 60.0 / 510.0);
+    }
+}
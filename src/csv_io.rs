@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use anyhow::{bail, Context};
+use chrono::SecondsFormat;
+
+use crate::*;
+use chrono_util::{to_market_datetime, CHRONO_FEATURES_SIZE};
+use data_info::*;
+use label::LabelEvent;
+use quote::QuoteEvent;
+use stored::TrainStoredWithLabel;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CsvOptions {
+    /// Write timestamps as ISO 8601 in market time instead of epoch millis. Both are accepted when reading.
+    pub iso_time: bool,
+}
+
+/// A type that can be written to and read from a csv file with a stable header.
+/// Fixed size arrays are flattened into numbered columns, for example label_0..label_7.
+pub trait CsvRecord: Sized {
+    fn header() -> Vec<String>;
+    fn to_row(&self, opts: &CsvOptions) -> Vec<String>;
+    fn from_row(row: &CsvRow) -> anyhow::Result<Self>;
+}
+
+pub fn write_csv<'a, T: CsvRecord + 'a, W: Write>(writer: W, items: impl IntoIterator<Item = &'a T>, opts: &CsvOptions) -> anyhow::Result<()> {
+    let mut w = csv::Writer::from_writer(writer);
+    w.write_record(T::header())?;
+    for item in items {
+        w.write_record(item.to_row(opts))?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// Reads by header name, so column order doesn't matter and extra columns are ignored.
+pub fn read_csv<T: CsvRecord, R: Read>(reader: R) -> anyhow::Result<Vec<T>> {
+    let mut r = csv::Reader::from_reader(reader);
+    let index: HashMap<String, usize> = r.headers()?.iter().enumerate().map(|(i, h)| (h.to_string(), i)).collect();
+    let mut result = Vec::new();
+    for (line, record) in r.records().enumerate() {
+        let record = record?;
+        let row = CsvRow { record: &record, index: &index };
+        result.push(T::from_row(&row).with_context(|| format!("Invalid csv record {}", line + 1))?);
+    }
+    Ok(result)
+}
+
+pub struct CsvRow<'a> {
+    record: &'a csv::StringRecord,
+    index: &'a HashMap<String, usize>,
+}
+
+impl CsvRow<'_> {
+    pub fn str(&self, name: &str) -> anyhow::Result<&str> {
+        let i = self.index.get(name).with_context(|| format!("Missing column {}", name))?;
+        self.record.get(*i).with_context(|| format!("Missing value for column {}", name))
+    }
+
+    pub fn get<T: FromStr>(&self, name: &str) -> anyhow::Result<T> where T::Err: std::error::Error + Send + Sync + 'static {
+        let s = self.str(name)?;
+        s.parse().with_context(|| format!("Invalid value {} for column {}", s, name))
+    }
+
+    /// Optional column, default when missing or empty.
+    pub fn get_or_default<T: FromStr + Default>(&self, name: &str) -> anyhow::Result<T> where T::Err: std::error::Error + Send + Sync + 'static {
+        match self.str(name) {
+            Ok(s) if !s.is_empty() => self.get(name),
+            _ => Ok(T::default()),
+        }
+    }
+
+    /// Accepts epoch millis or an ISO 8601 datetime with offset.
+    pub fn timestamp(&self, name: &str) -> anyhow::Result<Timestamp> {
+        let s = self.str(name)?;
+        if let Ok(ts) = s.parse::<Timestamp>() {
+            return Ok(ts);
+        }
+        match DateTime::parse_from_rfc3339(s) {
+            Ok(dt) => Ok(dt.timestamp_millis()),
+            Err(e) => bail!("Invalid timestamp {} for column {}: {}", s, name, e),
+        }
+    }
+
+    pub fn array<const N: usize>(&self, prefix: &str) -> anyhow::Result<[ModelFloat; N]> {
+        let mut result = [0.0; N];
+        for (i, x) in result.iter_mut().enumerate() {
+            *x = self.get(&format!("{}_{}", prefix, i))?;
+        }
+        Ok(result)
+    }
+}
+
+pub fn format_timestamp(ts: Timestamp, opts: &CsvOptions) -> String {
+    if opts.iso_time {
+        to_market_datetime(ts).to_rfc3339_opts(SecondsFormat::Millis, false)
+    } else {
+        ts.to_string()
+    }
+}
+
+fn numbered(prefix: &str, n: usize) -> Vec<String> {
+    (0..n).map(|i| format!("{}_{}", prefix, i)).collect()
+}
+
+fn values(xs: &[ModelFloat]) -> impl Iterator<Item = String> + '_ {
+    xs.iter().map(|x| x.to_string())
+}
+
+fn header_of(names: &[&str]) -> Vec<String> {
+    names.iter().map(|s| s.to_string()).collect()
+}
+
+// ---- Quotes ---- //
+
+impl CsvRecord for QuoteEvent {
+    fn header() -> Vec<String> {
+        header_of(&["event_id", "offset", "bid", "biddate", "ask", "askdate", "bidsz", "asksz"])
+    }
+
+    fn to_row(&self, opts: &CsvOptions) -> Vec<String> {
+        vec![
+            self.event_id.to_string(), self.offset.to_string(),
+            self.bid.to_string(), format_timestamp(self.biddate, opts),
+            self.ask.to_string(), format_timestamp(self.askdate, opts),
+            self.bidsz.to_string(), self.asksz.to_string(),
+        ]
+    }
+
+    fn from_row(row: &CsvRow) -> anyhow::Result<Self> {
+        Ok(Self {
+            event_id: row.get_or_default("event_id")?,
+            offset: row.get_or_default("offset")?,
+            bid: row.get("bid")?,
+            biddate: row.timestamp("biddate")?,
+            ask: row.get("ask")?,
+            askdate: row.timestamp("askdate")?,
+            bidsz: row.get_or_default("bidsz")?,
+            asksz: row.get_or_default("asksz")?,
+            gap_before: 0,
+        })
+    }
+}
+
+// ---- Labels ---- //
+
+/// The horizons column is the json array of OffsetRange, empty for single range labels.
+impl CsvRecord for LabelEvent {
+    fn header() -> Vec<String> {
+        let mut h = header_of(&["event_id", "offset_from", "offset_to", "timestamp"]);
+        h.extend(numbered("label", MODEL_OUTPUT_WIDTH));
+        h.push("horizons".to_string());
+        h
+    }
+
+    fn to_row(&self, opts: &CsvOptions) -> Vec<String> {
+        let mut row = vec![
+            self.event_id.to_string(), self.offset_from.to_string(), self.offset_to.to_string(),
+            format_timestamp(self.timestamp, opts),
+        ];
+        row.extend(values(&self.label));
+        // unwrap ok because OffsetRange always serializes
+        row.push(if self.horizons.is_empty() { String::new() } else { serde_json::to_string(&self.horizons).unwrap() });
+        row
+    }
+
+    fn from_row(row: &CsvRow) -> anyhow::Result<Self> {
+        let mut label = Self::new(
+            row.get("event_id")?, row.timestamp("timestamp")?,
+            row.get("offset_from")?, row.get("offset_to")?,
+            row.array("label")?,
+        );
+        match row.str("horizons") {
+            Ok(s) if !s.is_empty() => label.horizons = serde_json::from_str(s).with_context(|| format!("Invalid horizons {}", s))?,
+            _ => (),
+        }
+        Ok(label)
+    }
+}
+
+// ---- Training records ---- //
+
+/// Input series columns are named series_{row}_{column}.
+impl CsvRecord for TrainStoredWithLabel {
+    fn header() -> Vec<String> {
+        let mut h = header_of(&["event_id", "timestamp", "offset", "loss"]);
+        h.extend(numbered("chrono", CHRONO_FEATURES_SIZE));
+        for row in 0..SERIES1_SIZE {
+            h.extend(numbered(&format!("series_{}", row), SERIES1_ITEM_SIZE));
+        }
+        h.extend(numbered("output", MODEL_OUTPUT_WIDTH));
+        h.extend(numbered("label", MODEL_OUTPUT_WIDTH));
+        h
+    }
+
+    fn to_row(&self, opts: &CsvOptions) -> Vec<String> {
+        let mut row = vec![
+            self.event_id.to_string(), format_timestamp(self.timestamp, opts),
+            self.offset.to_string(), self.loss.to_string(),
+        ];
+        let (chrono, series) = &self.input;
+        row.extend(values(chrono));
+        for item in series {
+            row.extend(values(item));
+        }
+        row.extend(values(&self.output));
+        row.extend(values(&self.label));
+        row
+    }
+
+    fn from_row(row: &CsvRow) -> anyhow::Result<Self> {
+        let mut series = convert::new_series();
+        for (i, item) in series.iter_mut().enumerate() {
+            *item = row.array(&format!("series_{}", i))?;
+        }
+        Ok(Self {
+            event_id: row.get("event_id")?,
+            timestamp: row.timestamp("timestamp")?,
+            offset: row.get("offset")?,
+            loss: row.get("loss")?,
+            input: (row.array("chrono")?, series),
+            output: row.array("output")?,
+            label: row.array("label")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use label::OffsetRange;
+
+    fn quotes() -> Vec<QuoteEvent> {
+        let mut a = QuoteEvent::at(1_704_205_800_123, 470.25, 470.27);
+        a.event_id = 7;
+        a.offset = 3;
+        a.bidsz = 200.0;
+        a.asksz = 100.0;
+        let mut b = QuoteEvent::at(1_704_205_801_000, 470.26, 470.28);
+        b.askdate += 5;
+        vec![a, b]
+    }
+
+    fn round_trip<T: CsvRecord>(items: &[T], opts: &CsvOptions) -> (String, Vec<T>) {
+        let mut buf = Vec::new();
+        write_csv(&mut buf, items, opts).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        let read = read_csv(text.as_bytes()).unwrap();
+        (text, read)
+    }
+
+    fn assert_quotes_eq(a: &[QuoteEvent], b: &[QuoteEvent]) {
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b) {
+            assert_eq!((x.event_id, x.offset, x.bid, x.biddate, x.ask, x.askdate, x.bidsz, x.asksz),
+                (y.event_id, y.offset, y.bid, y.biddate, y.ask, y.askdate, y.bidsz, y.asksz));
+        }
+    }
+
+    #[test]
+    fn quotes_round_trip_millis_and_iso() {
+        let (text, read) = round_trip(&quotes(), &CsvOptions::default());
+        assert!(text.starts_with("event_id,offset,bid,biddate,ask,askdate,bidsz,asksz\n"));
+        assert!(text.contains(",1704205800123,"));
+        assert_quotes_eq(&read, &quotes());
+
+        let (text, read) = round_trip(&quotes(), &CsvOptions { iso_time: true });
+        assert!(text.contains("2024-01-02T09:30:00.123-05:00"));
+        assert_quotes_eq(&read, &quotes());
+    }
+
+    #[test]
+    fn reads_by_header_name_with_optional_columns() {
+        let text = "askdate,ask,extra,biddate,bid\n1000,10.5,x,999,10.4\n";
+        let read: Vec<QuoteEvent> = read_csv(text.as_bytes()).unwrap();
+        assert_eq!((read[0].bid, read[0].biddate, read[0].ask, read[0].askdate), (10.4, 999, 10.5, 1000));
+        assert_eq!((read[0].event_id, read[0].bidsz, read[0].asksz), (0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn reports_bad_record_and_missing_column() {
+        let err = read_csv::<QuoteEvent, _>("bid,biddate,ask,askdate\n1,2,3,4\nx,2,3,4\n".as_bytes()).unwrap_err();
+        assert!(format!("{:#}", err).contains("Invalid csv record 2"));
+        let err = read_csv::<QuoteEvent, _>("bid,biddate,ask\n1,2,3\n".as_bytes()).unwrap_err();
+        assert!(format!("{:#}", err).contains("Missing column askdate"));
+    }
+
+    #[test]
+    fn labels_round_trip() {
+        let label = LabelEvent::new(42, 1_704_205_800_000, 43, 60, [0.5, -0.25, 0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+        let (text, read) = round_trip(&[label], &CsvOptions::default());
+        assert!(text.starts_with("event_id,offset_from,offset_to,timestamp,label_0,"));
+        let read = &read[0];
+        assert_eq!((read.event_id, read.offset_from, read.offset_to, read.timestamp), (42, 43, 60, 1_704_205_800_000));
+        assert_eq!(read.label, [0.5, -0.25, 0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert!(read.horizons.is_empty());
+    }
+
+    #[test]
+    fn multi_horizon_labels_round_trip() {
+        let horizons = vec![OffsetRange { from: 43, to: 52 }, OffsetRange { from: 43, to: 142 }];
+        let labels = [
+            LabelEvent::new_multi(42, 1_704_205_800_000, horizons.clone(), [0.5; MODEL_OUTPUT_WIDTH]),
+            LabelEvent::new(50, 1_704_205_801_000, 51, 60, [0.25; MODEL_OUTPUT_WIDTH]),
+        ];
+        let (text, read) = round_trip(&labels, &CsvOptions::default());
+        assert!(text.lines().next().unwrap().ends_with(",label_7,horizons"));
+        assert_eq!(read[0].horizons, horizons);
+        assert_eq!((read[0].offset_from, read[0].offset_to), (43, 142));
+        assert!(read[1].horizons.is_empty());
+        // Files written before the column existed still read.
+        let old = "event_id,offset_from,offset_to,timestamp,label_0,label_1,label_2,label_3,label_4,label_5,label_6,label_7\n1,2,3,4,0,0,0,0,0,0,0,0\n";
+        assert!(read_csv::<LabelEvent, _>(old.as_bytes()).unwrap()[0].horizons.is_empty());
+        assert!(read_csv::<LabelEvent, _>(text.replace("142}", "x}").as_bytes()).is_err());
+    }
+
+    #[test]
+    fn training_records_round_trip() {
+        let mut series = convert::new_series();
+        for (i, item) in series.iter_mut().enumerate() {
+            item[0] = i as ModelFloat / 7.0;
+            item[SERIES1_ITEM_SIZE - 1] = -(i as ModelFloat);
+        }
+        let record = TrainStoredWithLabel {
+            event_id: 1, timestamp: 2, offset: 3, loss: 0.125,
+            input: ([0.1; CHRONO_FEATURES_SIZE], series),
+            output: [0.3; MODEL_OUTPUT_WIDTH],
+            label: [0.7; MODEL_OUTPUT_WIDTH],
+        };
+        assert_eq!(TrainStoredWithLabel::header().len(), 4 + CHRONO_FEATURES_SIZE + SERIES1_SIZE * SERIES1_ITEM_SIZE + 2 * MODEL_OUTPUT_WIDTH);
+        let (_, read) = round_trip(&[record], &CsvOptions::default());
+        let read = &read[0];
+        assert_eq!((read.event_id, read.timestamp, read.offset, read.loss), (1, 2, 3, 0.125));
+        assert_eq!(read.input.0, [0.1; CHRONO_FEATURES_SIZE]);
+        assert_eq!(read.input.1, series);
+        assert_eq!(read.output, [0.3; MODEL_OUTPUT_WIDTH]);
+        assert_eq!(read.label, [0.7; MODEL_OUTPUT_WIDTH]);
+    }
+}
@@ -1,4 +1,9 @@
 use chrono_util::{ChronoFeatures, CHRONO_BYTE_SIZE};
+use exchange::Exchange;
+use features::{FeatureSet, QuoteFeature};
+use label::LabelSpec;
+use session::SessionConfig;
+use anyhow::bail;
 use serde_json::json;
 
 use crate::*;
@@ -9,10 +14,11 @@ pub type BatchOf<T, const N: usize> = [T; N];
 pub const MODEL_OUTPUT_WIDTH: usize = 8;
 
 /// CURRENT_VERSION should only be used in main.rs files so that all other objects receive it.
-pub const CURRENT_VERSION: VersionType = 1;
+pub const CURRENT_VERSION: VersionType = 2;
 /// Model static params: end ///
 
 
+#[allow(clippy::empty_line_after_doc_comments)]
 pub const SERIES1_FEATURES_SIZE: usize = 2;
 pub const SERIES1_SIZE: usize = 1024;
 pub const SERIES1_LENGTH: OffsetId = SERIES1_SIZE as OffsetId;
@@ -48,7 +54,7 @@ pub fn data_config() -> DataConfig{
         "quote_streams": [
             {
                 "topic_name": "raw-SPY-quote",
-                "feature_size": 4,
+                "feature_size": FEATURES1_SIZE,
                 "time_embedding_size": TIME_EMBEDDING_SIZE
             }
         ],
@@ -66,19 +72,47 @@ pub fn data_config() -> DataConfig{
 // ---- Types ---- //
 
 #[derive(serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct DataConfig {
     pub quote_streams: Vec<QuoteStreamSpec>,
     pub trade_streams: Vec<TradeStreamSpec>,
+    /// Layout of LabelType, see label::LabelSpec.
+    #[serde(default)]
+    pub label: LabelSpec,
+}
+
+impl DataConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for spec in &self.quote_streams {
+            let features = FeatureSet::from_spec(spec)?;
+            if features.item_size() != SERIES1_ITEM_SIZE {
+                bail!("Stream {} items are {} wide but the model input items are {}", spec.topic_name, features.item_size(), SERIES1_ITEM_SIZE);
+            }
+        }
+        self.label.validate()
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct QuoteStreamSpec {
     pub topic_name: String,
     pub feature_size: usize,
     pub time_embedding_size: usize,
+    /// Named features, see features::FeatureSet. If empty, the first feature_size of the default order are used.
+    #[serde(default)]
+    pub features: Vec<QuoteFeature>,
+    #[serde(default)]
+    pub volatility_window: Option<usize>,
+    #[serde(default)]
+    pub sessions: SessionConfig,
+    /// Timezone, sessions and holidays of the stream's market, US equities if missing.
+    #[serde(default)]
+    pub exchange: Exchange,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct TradeStreamSpec {
     pub topic_name: String,
     pub feature_size: usize,
@@ -86,10 +120,26 @@ pub struct TradeStreamSpec {
 }
 
 pub trait StreamSpec {
+    fn topic_name(&self) -> &str;
     fn item_size(&self) -> usize;
-    //  {
-    //     self.feature_size + self.time_embedding_size
-    // }
 }
-// impl StreamSpec {
-// }
\ No newline at end of file
+
+impl StreamSpec for QuoteStreamSpec {
+    fn topic_name(&self) -> &str {
+        &self.topic_name
+    }
+
+    fn item_size(&self) -> usize {
+        self.feature_size + self.time_embedding_size
+    }
+}
+
+impl StreamSpec for TradeStreamSpec {
+    fn topic_name(&self) -> &str {
+        &self.topic_name
+    }
+
+    fn item_size(&self) -> usize {
+        self.feature_size + self.time_embedding_size
+    }
+}
@@ -0,0 +1,417 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use memmap2::Mmap;
+
+use crate::*;
+use chrono_util::{ChronoFeatures, CHRONO_FEATURES_SIZE};
+use convert::new_series;
+use data_info::*;
+use util::convert_slice;
+
+// File layout, all little endian:
+//   header: HEADER_SIZE bytes, see DatasetHeader
+//   records: fixed stride of event_id, timestamp, offset (i64 each), chrono features, series, label (f32 each)
+// Records are appended in timestamp order so time ranges can be found by binary search.
+
+const MAGIC: [u8; 8] = *b"OMLDSET1";
+const HEADER_SIZE: usize = 64;
+const META_SIZE: usize = 3 * std::mem::size_of::<i64>();
+
+/// Shapes the file was written with, checked against the current ones when opening.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DatasetHeader {
+    pub version: VersionType,
+    pub chrono_size: u32,
+    pub series_size: u32,
+    pub series_item_size: u32,
+    pub label_width: u32,
+}
+
+impl DatasetHeader {
+    pub fn current(version: VersionType) -> Self {
+        Self {
+            version,
+            chrono_size: CHRONO_FEATURES_SIZE as u32,
+            series_size: SERIES1_SIZE as u32,
+            series_item_size: SERIES1_ITEM_SIZE as u32,
+            label_width: MODEL_OUTPUT_WIDTH as u32,
+        }
+    }
+
+    /// Bytes per record.
+    pub fn record_size(&self) -> usize {
+        let floats = self.chrono_size + self.series_size * self.series_item_size + self.label_width;
+        META_SIZE + floats as usize * std::mem::size_of::<ModelFloat>()
+    }
+
+    fn to_bytes(self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[..8].copy_from_slice(&MAGIC);
+        let fields = [self.version, self.chrono_size, self.series_size, self.series_item_size, self.label_width];
+        for (i, x) in fields.iter().enumerate() {
+            bytes[8 + 4 * i..12 + 4 * i].copy_from_slice(&x.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        if bytes.len() < HEADER_SIZE || bytes[..8] != MAGIC {
+            bail!("Not a dataset file");
+        }
+        // unwrap ok because slice is 4 bytes
+        let field = |i: usize| u32::from_le_bytes(bytes[8 + 4 * i..12 + 4 * i].try_into().unwrap());
+        Ok(Self { version: field(0), chrono_size: field(1), series_size: field(2), series_item_size: field(3), label_width: field(4) })
+    }
+
+    /// Errors if the shapes don't match the current types, the version is not checked.
+    pub fn check_shapes(&self) -> anyhow::Result<()> {
+        let current = Self::current(self.version);
+        if *self != current {
+            bail!("Dataset shapes {:?} do not match current {:?}", self, current);
+        }
+        Ok(())
+    }
+}
+
+pub struct DatasetRecord {
+    pub event_id: EventId,
+    pub timestamp: Timestamp,
+    pub offset: OffsetId,
+    pub input: InputRaw,
+    pub label: LabelType,
+}
+
+// ---- Writer ---- //
+
+/// Appends records to a dataset file, creating it if it doesn't exist.
+pub struct DatasetWriter {
+    path: PathBuf,
+    file: BufWriter<File>,
+    header: DatasetHeader,
+    len: usize,
+    last_timestamp: Timestamp,
+}
+
+impl DatasetWriter {
+    /// Opens for appending. An existing file must have the same version and shapes.
+    /// A partial record at the end, from a crash while writing, is truncated.
+    pub fn open(path: &Path, version: VersionType) -> anyhow::Result<Self> {
+        let header = DatasetHeader::current(version);
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)
+            .with_context(|| format!("Could not open dataset {:?}", path))?;
+        let file_len = file.metadata()?.len() as usize;
+        let mut last_timestamp = Timestamp::MIN;
+        let len = if file_len == 0 {
+            file.write_all(&header.to_bytes())?;
+            0
+        } else {
+            let mut bytes = [0; HEADER_SIZE];
+            file.read_exact(&mut bytes)?;
+            let existing = DatasetHeader::from_bytes(&bytes).with_context(|| format!("Invalid dataset {:?}", path))?;
+            if existing != header {
+                bail!("Dataset {:?} has {:?} but writing {:?}", path, existing, header);
+            }
+            let len = (file_len - HEADER_SIZE) / header.record_size();
+            file.set_len((HEADER_SIZE + len * header.record_size()) as u64)?;
+            if len > 0 {
+                last_timestamp = Dataset::open(path)?.timestamp(len - 1);
+            }
+            len
+        };
+        let file = OpenOptions::new().append(true).open(path)?;
+        Ok(Self { path: path.to_path_buf(), file: BufWriter::new(file), header, len, last_timestamp })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the index of the record. Timestamps must not decrease.
+    pub fn append(&mut self, record: &DatasetRecord) -> anyhow::Result<usize> {
+        if record.timestamp < self.last_timestamp {
+            bail!("Dataset record {} at {} is before the previous at {}", record.event_id, record.timestamp, self.last_timestamp);
+        }
+        let mut bytes = Vec::with_capacity(self.header.record_size());
+        bytes.extend(record.event_id.to_le_bytes());
+        bytes.extend(record.timestamp.to_le_bytes());
+        bytes.extend(record.offset.to_le_bytes());
+        bytes.extend_from_slice(convert_slice::<ModelFloat, u8>(&record.input.0));
+        bytes.extend_from_slice(convert_slice::<SeriesItem, u8>(&record.input.1));
+        bytes.extend_from_slice(convert_slice::<ModelFloat, u8>(&record.label));
+        self.file.write_all(&bytes)?;
+        self.last_timestamp = record.timestamp;
+        self.len += 1;
+        Ok(self.len - 1)
+    }
+
+    /// Readers only see records after this.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for DatasetWriter {
+    fn drop(&mut self) {
+        let _ = self.file.flush();
+    }
+}
+
+// ---- Reader ---- //
+
+/// Memory mapped dataset with random access by index and event_id.
+pub struct Dataset {
+    path: PathBuf,
+    mmap: Mmap,
+    header: DatasetHeader,
+    len: usize,
+    by_event_id: HashMap<EventId, usize>,
+}
+
+impl Dataset {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path).with_context(|| format!("Could not open dataset {:?}", path))?;
+        // The file is only ever appended to, so existing records don't change under the map.
+        let mmap = unsafe { Mmap::map(&file)? };
+        let header = DatasetHeader::from_bytes(&mmap).with_context(|| format!("Invalid dataset {:?}", path))?;
+        header.check_shapes()?;
+        let len = (mmap.len() - HEADER_SIZE) / header.record_size();
+        let mut dataset = Self { path: path.to_path_buf(), mmap, header, len, by_event_id: HashMap::with_capacity(len) };
+        for i in 0..len {
+            dataset.by_event_id.insert(dataset.event_id(i), i);
+        }
+        Ok(dataset)
+    }
+
+    /// Maps records appended since opening.
+    pub fn refresh(&mut self) -> anyhow::Result<()> {
+        *self = Self::open(&self.path)?;
+        Ok(())
+    }
+
+    pub fn header(&self) -> &DatasetHeader {
+        &self.header
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn record_bytes(&self, index: usize) -> &[u8] {
+        let size = self.header.record_size();
+        let start = HEADER_SIZE + index * size;
+        &self.mmap[start..start + size]
+    }
+
+    fn meta(&self, index: usize, field: usize) -> i64 {
+        let bytes = &self.record_bytes(index)[8 * field..8 * field + 8];
+        // unwrap ok because slice is 8 bytes
+        i64::from_le_bytes(bytes.try_into().unwrap())
+    }
+
+    /// The floats of the record without copying: chrono features, series, label.
+    /// Aligned because the map is page aligned and the header and record sizes are multiples of 4.
+    pub fn floats(&self, index: usize) -> &[ModelFloat] {
+        convert_slice(&self.record_bytes(index)[META_SIZE..])
+    }
+
+    pub fn event_id(&self, index: usize) -> EventId {
+        self.meta(index, 0)
+    }
+
+    pub fn timestamp(&self, index: usize) -> Timestamp {
+        self.meta(index, 1)
+    }
+
+    pub fn offset(&self, index: usize) -> OffsetId {
+        self.meta(index, 2)
+    }
+
+    pub fn index_of(&self, event_id: EventId) -> Option<usize> {
+        self.by_event_id.get(&event_id).copied()
+    }
+
+    pub fn input(&self, index: usize) -> InputRaw {
+        let floats = self.floats(index);
+        let (chrono, rest) = floats.split_at(CHRONO_FEATURES_SIZE);
+        let mut chrono_features: ChronoFeatures = [0.0; CHRONO_FEATURES_SIZE];
+        chrono_features.copy_from_slice(chrono);
+        let mut series = new_series();
+        series.as_flattened_mut().copy_from_slice(&rest[..SERIES1_SIZE * SERIES1_ITEM_SIZE]);
+        (chrono_features, series)
+    }
+
+    pub fn label(&self, index: usize) -> LabelType {
+        let floats = self.floats(index);
+        let mut label = [0.0; MODEL_OUTPUT_WIDTH];
+        label.copy_from_slice(&floats[floats.len() - MODEL_OUTPUT_WIDTH..]);
+        label
+    }
+
+    pub fn record(&self, index: usize) -> DatasetRecord {
+        DatasetRecord {
+            event_id: self.event_id(index),
+            timestamp: self.timestamp(index),
+            offset: self.offset(index),
+            input: self.input(index),
+            label: self.label(index),
+        }
+    }
+
+    pub fn get_by_event_id(&self, event_id: EventId) -> Option<DatasetRecord> {
+        self.index_of(event_id).map(|i| self.record(i))
+    }
+
+    /// Indices of records with from <= timestamp < to.
+    pub fn time_range(&self, from: Timestamp, to: Timestamp) -> Range<usize> {
+        let start = self.partition_point(|ts| ts < from);
+        let end = self.partition_point(|ts| ts < to).max(start);
+        start..end
+    }
+
+    fn partition_point(&self, pred: impl Fn(Timestamp) -> bool) -> usize {
+        let (mut lo, mut hi) = (0, self.len);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if pred(self.timestamp(mid)) { lo = mid + 1 } else { hi = mid }
+        }
+        lo
+    }
+}
+
+/// Dataset file for the name in the datasets directory.
+pub fn dataset_path(name: &str) -> anyhow::Result<PathBuf> {
+    Ok(paths::dataset_dir()?.join(format!("{}.dataset", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.dataset", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn record(i: i64) -> DatasetRecord {
+        let mut chrono = [0.0; CHRONO_FEATURES_SIZE];
+        chrono.iter_mut().enumerate().for_each(|(k, x)| *x = i as f32 + k as f32 / 100.0);
+        let mut series = new_series();
+        series.as_flattened_mut().iter_mut().enumerate().for_each(|(k, x)| *x = (i as f32).mul_add(0.5, k as f32));
+        let mut label = [0.0; MODEL_OUTPUT_WIDTH];
+        label[i as usize % MODEL_OUTPUT_WIDTH] = 1.0;
+        DatasetRecord { event_id: 100 + i, timestamp: 1_000 * i, offset: 10 * i, input: (chrono, series), label }
+    }
+
+    fn assert_record_eq(a: &DatasetRecord, b: &DatasetRecord) {
+        assert_eq!((a.event_id, a.timestamp, a.offset), (b.event_id, b.timestamp, b.offset));
+        assert_eq!(a.input, b.input);
+        assert_eq!(a.label, b.label);
+    }
+
+    fn write(path: &Path, range: Range<i64>) {
+        let mut writer = DatasetWriter::open(path, CURRENT_VERSION).unwrap();
+        for i in range {
+            writer.append(&record(i)).unwrap();
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let path = temp_path("round-trip");
+        write(&path, 0..5);
+        let dataset = Dataset::open(&path).unwrap();
+        assert_eq!(dataset.len(), 5);
+        assert_eq!(*dataset.header(), DatasetHeader::current(CURRENT_VERSION));
+        assert_eq!(std::fs::metadata(&path).unwrap().len() as usize, HEADER_SIZE + 5 * dataset.header().record_size());
+        for i in 0..5 {
+            assert_record_eq(&dataset.record(i as usize), &record(i));
+        }
+        assert_eq!(dataset.index_of(103), Some(3));
+        assert_record_eq(&dataset.get_by_event_id(104).unwrap(), &record(4));
+        assert!(dataset.get_by_event_id(99).is_none());
+        assert_eq!(dataset.floats(2).len(), CHRONO_FEATURES_SIZE + SERIES1_SIZE * SERIES1_ITEM_SIZE + MODEL_OUTPUT_WIDTH);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reopening_appends_in_time_order() {
+        let path = temp_path("append");
+        write(&path, 0..3);
+        let mut dataset = Dataset::open(&path).unwrap();
+        let mut writer = DatasetWriter::open(&path, CURRENT_VERSION).unwrap();
+        assert_eq!(writer.len(), 3);
+        // Continues after the last timestamp in the file.
+        assert!(writer.append(&record(1)).is_err());
+        assert_eq!(writer.append(&record(3)).unwrap(), 3);
+        writer.flush().unwrap();
+        assert_eq!(dataset.len(), 3);
+        dataset.refresh().unwrap();
+        assert_eq!(dataset.len(), 4);
+        assert_record_eq(&dataset.record(3), &record(3));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn partial_record_is_truncated() {
+        let path = temp_path("partial");
+        write(&path, 0..2);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[1; 100]).unwrap();
+        assert_eq!(Dataset::open(&path).unwrap().len(), 2);
+        let mut writer = DatasetWriter::open(&path, CURRENT_VERSION).unwrap();
+        writer.append(&record(2)).unwrap();
+        drop(writer);
+        let dataset = Dataset::open(&path).unwrap();
+        assert_eq!(dataset.len(), 3);
+        assert_record_eq(&dataset.record(2), &record(2));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_other_versions_and_shapes() {
+        let path = temp_path("shapes");
+        write(&path, 0..1);
+        assert!(DatasetWriter::open(&path, CURRENT_VERSION + 1).is_err());
+        // A file written with another series size can't be read as the current types.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[16..20].copy_from_slice(&(SERIES1_SIZE as u32 / 2).to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(Dataset::open(&path).is_err());
+        assert!(DatasetWriter::open(&path, CURRENT_VERSION).is_err());
+        std::fs::write(&path, b"not a dataset").unwrap();
+        assert!(Dataset::open(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn time_range_is_half_open() {
+        let path = temp_path("time-range");
+        write(&path, 0..10);
+        let dataset = Dataset::open(&path).unwrap();
+        assert_eq!(dataset.time_range(2_000, 5_000), 2..5);
+        assert_eq!(dataset.time_range(1_500, 5_001), 2..6);
+        assert_eq!(dataset.time_range(-1, 100_000), 0..10);
+        assert_eq!(dataset.time_range(5_000, 2_000), 5..5);
+        assert_eq!(dataset.time_range(20_000, 30_000), 10..10);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::VecDeque;
+
+use anyhow::{bail, Context};
+use half::{bf16, f16};
+
+use crate::*;
+use chrono_util::{ChronoFeatures, CHRONO_FEATURES_SIZE};
+use convert::{new_series, series_to_input, TimeEmbedder};
+use data_info::*;
+use layout::FeatureLayout;
+use quote::QuoteEvent;
+use series::SeriesEvent;
+
+// Encoded layout, little endian: encoding tag byte, chrono features as f32, then the series as below.
+// The chrono features are few so are always stored as is.
+
+/// How the series of an InputRaw is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum SeriesEncoding {
+    /// Every value as is.
+    F32,
+    F16,
+    Bf16,
+    /// Per column of each series: min and max as f32, then values quantized to 8 bits between them.
+    Affine8,
+    Affine16,
+    /// Bid and ask columns as f32 and the time delta to the base event as u32 millis.
+    /// The time embedding is recomputed on decode, so this requires the events.
+    RawDeltas,
+}
+
+impl SeriesEncoding {
+    fn tag(&self) -> u8 {
+        match self {
+            SeriesEncoding::F32 => 0,
+            SeriesEncoding::F16 => 1,
+            SeriesEncoding::Bf16 => 2,
+            SeriesEncoding::Affine8 => 3,
+            SeriesEncoding::Affine16 => 4,
+            SeriesEncoding::RawDeltas => 5,
+        }
+    }
+
+    fn from_tag(tag: u8) -> anyhow::Result<Self> {
+        Ok(match tag {
+            0 => SeriesEncoding::F32,
+            1 => SeriesEncoding::F16,
+            2 => SeriesEncoding::Bf16,
+            3 => SeriesEncoding::Affine8,
+            4 => SeriesEncoding::Affine16,
+            5 => SeriesEncoding::RawDeltas,
+            _ => bail!("Unknown series encoding tag {}", tag),
+        })
+    }
+
+    /// Encodes an input that was already converted. Fails for RawDeltas which needs the events, see encode_events.
+    pub fn encode(&self, input: &InputRaw) -> anyhow::Result<Vec<u8>> {
+        let (chrono, series) = input;
+        let mut bytes = self.start(chrono);
+        let values = series.iter().flatten();
+        match self {
+            SeriesEncoding::F32 => values.for_each(|x| bytes.extend(x.to_le_bytes())),
+            SeriesEncoding::F16 => values.for_each(|x| bytes.extend(f16::from_f32(*x).to_le_bytes())),
+            SeriesEncoding::Bf16 => values.for_each(|x| bytes.extend(bf16::from_f32(*x).to_le_bytes())),
+            SeriesEncoding::Affine8 => encode_affine(series, 8, &mut bytes),
+            SeriesEncoding::Affine16 => encode_affine(series, 16, &mut bytes),
+            SeriesEncoding::RawDeltas => bail!("RawDeltas encoding needs the events, use encode_events"),
+        }
+        Ok(bytes)
+    }
+
+    /// Converts the events as series_to_input does and encodes the result.
+    pub fn encode_events(&self, events: &VecDeque<QuoteEvent>) -> anyhow::Result<Vec<u8>> {
+        if *self != SeriesEncoding::RawDeltas {
+            return self.encode(&series_to_input(events)?);
+        }
+        let (chrono, series) = series_to_input(events)?;
+        let mut bytes = self.start(&chrono);
+        let base_time = events[SERIES1_SIZE - 1].timestamp();
+        for (event, item) in events.iter().zip(&series) {
+            let delta = u32::try_from(base_time - event.timestamp()).with_context(|| format!("Time delta for event {} does not fit", event.event_id))?;
+            bytes.extend(item[0].to_le_bytes());
+            bytes.extend(item[1].to_le_bytes());
+            bytes.extend(delta.to_le_bytes());
+        }
+        Ok(bytes)
+    }
+
+    fn start(&self, chrono: &ChronoFeatures) -> Vec<u8> {
+        let mut bytes = vec![self.tag()];
+        chrono.iter().for_each(|x| bytes.extend(x.to_le_bytes()));
+        bytes
+    }
+
+    /// Encoded size of one InputRaw.
+    pub fn byte_size(&self) -> usize {
+        let values = SERIES1_SIZE * SERIES1_ITEM_SIZE;
+        let series = match self {
+            SeriesEncoding::F32 => 4 * values,
+            SeriesEncoding::F16 | SeriesEncoding::Bf16 => 2 * values,
+            SeriesEncoding::Affine8 => 8 * SERIES1_ITEM_SIZE + values,
+            SeriesEncoding::Affine16 => 8 * SERIES1_ITEM_SIZE + 2 * values,
+            SeriesEncoding::RawDeltas => 12 * SERIES1_SIZE,
+        };
+        1 + 4 * CHRONO_FEATURES_SIZE + series
+    }
+}
+
+fn encode_affine(series: &Series, bits: u32, bytes: &mut Vec<u8>) {
+    let levels = ((1u32 << bits) - 1) as f32;
+    for col in 0..SERIES1_ITEM_SIZE {
+        let (min, max) = series.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), item| (lo.min(item[col]), hi.max(item[col])));
+        bytes.extend(min.to_le_bytes());
+        bytes.extend(max.to_le_bytes());
+        let scale = if max > min { levels / (max - min) } else { 0.0 };
+        for item in series {
+            let q = ((item[col] - min) * scale).round() as u32;
+            if bits == 8 { bytes.push(q as u8) } else { bytes.extend((q as u16).to_le_bytes()) }
+        }
+    }
+}
+
+// ---- Decoding ---- //
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        let Some(slice) = self.bytes.get(self.pos..self.pos + N) else {
+            bail!("Encoded input is truncated at {}", self.pos);
+        };
+        self.pos += N;
+        // unwrap ok because slice has length N
+        Ok(slice.try_into().unwrap())
+    }
+
+    fn f32(&mut self) -> anyhow::Result<f32> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+}
+
+/// Decodes anything produced by SeriesEncoding::encode or encode_events back into an ordinary InputRaw.
+pub fn decode(bytes: &[u8]) -> anyhow::Result<InputRaw> {
+    let Some(&tag) = bytes.first() else {
+        bail!("Encoded input is empty");
+    };
+    let encoding = SeriesEncoding::from_tag(tag)?;
+    if bytes.len() != encoding.byte_size() {
+        bail!("Encoded input has {} bytes but {:?} needs {}", bytes.len(), encoding, encoding.byte_size());
+    }
+    let mut r = Reader { bytes, pos: 1 };
+    let mut chrono = [0.0; CHRONO_FEATURES_SIZE];
+    for x in chrono.iter_mut() {
+        *x = r.f32()?;
+    }
+    let mut series = new_series();
+    match encoding {
+        SeriesEncoding::F32 => for x in series.as_flattened_mut() { *x = r.f32()? },
+        SeriesEncoding::F16 => for x in series.as_flattened_mut() { *x = f16::from_le_bytes(r.take()?).to_f32() },
+        SeriesEncoding::Bf16 => for x in series.as_flattened_mut() { *x = bf16::from_le_bytes(r.take()?).to_f32() },
+        SeriesEncoding::Affine8 | SeriesEncoding::Affine16 => {
+            let bits = if encoding == SeriesEncoding::Affine8 { 8 } else { 16 };
+            let levels = ((1u32 << bits) - 1) as f32;
+            for col in 0..SERIES1_ITEM_SIZE {
+                let min = r.f32()?;
+                let max = r.f32()?;
+                for item in series.iter_mut() {
+                    let q = if bits == 8 { r.take::<1>()?[0] as f32 } else { u16::from_le_bytes(r.take()?) as f32 };
+                    item[col] = min + q * (max - min) / levels;
+                }
+            }
+        },
+        SeriesEncoding::RawDeltas => {
+            let embedder = TimeEmbedder::<TIME_EMBEDDING_SIZE>::new();
+            for item in series.iter_mut() {
+                item[0] = r.f32()?;
+                item[1] = r.f32()?;
+                let delta = u32::from_le_bytes(r.take()?);
+                item[FEATURES1_SIZE..].copy_from_slice(&embedder.embed(delta as Timestamp));
+            }
+        },
+    }
+    Ok((chrono, series))
+}
+
+// ---- Accuracy ---- //
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ColumnError {
+    pub name: String,
+    pub max_abs_error: f32,
+}
+
+/// Maximum absolute error per column of decode(encode(input)) over a set of inputs.
+#[derive(Debug, Clone, serde::Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AccuracyReport {
+    pub encoding: SeriesEncoding,
+    pub byte_size: usize,
+    /// byte_size relative to F32.
+    pub ratio: f32,
+    pub count: usize,
+    pub series: Vec<ColumnError>,
+    pub chrono: Vec<ColumnError>,
+}
+
+impl AccuracyReport {
+    fn new(encoding: SeriesEncoding) -> Self {
+        let layout = FeatureLayout::default();
+        let columns = |cols: &[layout::ColumnInfo]| cols.iter().map(|c| ColumnError { name: c.name.clone(), max_abs_error: 0.0 }).collect();
+        Self {
+            encoding,
+            byte_size: encoding.byte_size(),
+            ratio: encoding.byte_size() as f32 / SeriesEncoding::F32.byte_size() as f32,
+            count: 0,
+            series: columns(&layout.series),
+            chrono: columns(&layout.chrono),
+        }
+    }
+
+    /// Fails for RawDeltas, use for_events.
+    pub fn for_inputs<'a>(encoding: SeriesEncoding, inputs: impl IntoIterator<Item = &'a InputRaw>) -> anyhow::Result<Self> {
+        let mut report = Self::new(encoding);
+        for input in inputs {
+            report.add(input, &decode(&encoding.encode(input)?)?);
+        }
+        Ok(report)
+    }
+
+    /// Each window is SERIES1_SIZE events as for series_to_input.
+    pub fn for_events<'a>(encoding: SeriesEncoding, windows: impl IntoIterator<Item = &'a VecDeque<QuoteEvent>>) -> anyhow::Result<Self> {
+        let mut report = Self::new(encoding);
+        for events in windows {
+            report.add(&series_to_input(events)?, &decode(&encoding.encode_events(events)?)?);
+        }
+        Ok(report)
+    }
+
+    fn add(&mut self, original: &InputRaw, decoded: &InputRaw) {
+        self.count += 1;
+        for (col, error) in self.series.iter_mut().enumerate() {
+            for (a, b) in original.1.iter().zip(&decoded.1) {
+                error.max_abs_error = error.max_abs_error.max((a[col] - b[col]).abs());
+            }
+        }
+        for (error, (a, b)) in self.chrono.iter_mut().zip(original.0.iter().zip(&decoded.0)) {
+            error.max_abs_error = error.max_abs_error.max((a - b).abs());
+        }
+    }
+
+    pub fn max_abs_error(&self) -> f32 {
+        self.series.iter().chain(&self.chrono).map(|c| c.max_abs_error).fold(0.0, f32::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use synthetic::{SyntheticConfig, SyntheticMarket};
+
+    const ALL: [SeriesEncoding; 6] = [
+        SeriesEncoding::F32, SeriesEncoding::F16, SeriesEncoding::Bf16,
+        SeriesEncoding::Affine8, SeriesEncoding::Affine16, SeriesEncoding::RawDeltas,
+    ];
+
+    fn window(seed: u64) -> VecDeque<QuoteEvent> {
+        SyntheticMarket::new(SyntheticConfig { seed, ..SyntheticConfig::default() }).take(SERIES1_SIZE).collect()
+    }
+
+    fn column_range(series: &Series, col: usize) -> f32 {
+        let (lo, hi) = series.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), item| (lo.min(item[col]), hi.max(item[col])));
+        hi - lo
+    }
+
+    /// Largest error per column allowed for the encoding.
+    fn bound(encoding: SeriesEncoding, series: &Series, col: usize) -> f32 {
+        match encoding {
+            SeriesEncoding::F32 | SeriesEncoding::RawDeltas => 0.0,
+            // Half an ulp for values up to 1 in magnitude
+            SeriesEncoding::F16 => 2f32.powi(-12),
+            SeriesEncoding::Bf16 => 2f32.powi(-9),
+            // Half a quantization step, plus rounding of the reconstruction
+            SeriesEncoding::Affine8 => column_range(series, col) / 255.0 / 2.0 + 1e-6,
+            SeriesEncoding::Affine16 => column_range(series, col) / 65_535.0 / 2.0 + 1e-6,
+        }
+    }
+
+    #[test]
+    fn decode_errors_are_within_bounds() {
+        for seed in [0, 1] {
+            let events = window(seed);
+            let input = series_to_input(&events).unwrap();
+            assert!(input.1.as_flattened().iter().all(|x| x.abs() <= 1.0));
+            for encoding in ALL {
+                let bytes = encoding.encode_events(&events).unwrap();
+                assert_eq!(bytes.len(), encoding.byte_size(), "{:?}", encoding);
+                let (chrono, series) = decode(&bytes).unwrap();
+                assert_eq!(chrono, input.0, "{:?}", encoding);
+                for col in 0..SERIES1_ITEM_SIZE {
+                    let bound = bound(encoding, &input.1, col);
+                    let error = input.1.iter().zip(&series).map(|(a, b)| (a[col] - b[col]).abs()).fold(0.0, f32::max);
+                    assert!(error <= bound, "{:?} column {} error {} > {}", encoding, col, error, bound);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn constant_columns_decode_exactly_with_affine() {
+        let mut input = series_to_input(&window(0)).unwrap();
+        input.1.iter_mut().for_each(|item| item[0] = 0.25);
+        for encoding in [SeriesEncoding::Affine8, SeriesEncoding::Affine16] {
+            let (_, series) = decode(&encoding.encode(&input).unwrap()).unwrap();
+            assert!(series.iter().all(|item| item[0] == 0.25));
+        }
+    }
+
+    #[test]
+    fn accuracy_report_matches_bounds() {
+        let windows = [window(0), window(1)];
+        let inputs: Vec<InputRaw> = windows.iter().map(|w| series_to_input(w).unwrap()).collect();
+        let exact = AccuracyReport::for_inputs(SeriesEncoding::F32, &inputs).unwrap();
+        assert_eq!((exact.count, exact.max_abs_error(), exact.ratio), (2, 0.0, 1.0));
+        let half = AccuracyReport::for_inputs(SeriesEncoding::F16, &inputs).unwrap();
+        assert!(half.max_abs_error() > 0.0 && half.max_abs_error() <= 2f32.powi(-12));
+        assert!(half.ratio < 0.51);
+        assert_eq!(half.series.len(), SERIES1_ITEM_SIZE);
+        assert_eq!(half.chrono.len(), CHRONO_FEATURES_SIZE);
+        assert!(AccuracyReport::for_inputs(SeriesEncoding::RawDeltas, &inputs).is_err());
+        assert_eq!(AccuracyReport::for_events(SeriesEncoding::RawDeltas, &windows).unwrap().max_abs_error(), 0.0);
+    }
+
+    #[test]
+    fn decode_rejects_bad_input() {
+        let input = series_to_input(&window(0)).unwrap();
+        assert!(SeriesEncoding::RawDeltas.encode(&input).is_err());
+        assert!(decode(&[]).is_err());
+        assert!(decode(&[9]).is_err());
+        let bytes = SeriesEncoding::F16.encode(&input).unwrap();
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert!(decode(&longer).is_err());
+        // A valid length for the wrong tag
+        let mut retagged = bytes;
+        retagged[0] = SeriesEncoding::Bf16.tag();
+        assert!(decode(&retagged).is_ok());
+        retagged[0] = SeriesEncoding::F32.tag();
+        assert!(decode(&retagged).is_err());
+    }
+
+    #[test]
+    fn tags_and_names_round_trip() {
+        for encoding in ALL {
+            assert_eq!(SeriesEncoding::from_tag(encoding.tag()).unwrap(), encoding);
+            let name = serde_json::to_value(encoding).unwrap();
+            assert_eq!(serde_json::from_value::<SeriesEncoding>(name).unwrap(), encoding);
+        }
+        assert_eq!(serde_json::to_value(SeriesEncoding::Affine8).unwrap(), "affine8");
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+
+use chrono::Timelike;
+
+use crate::*;
+use data_info::*;
+use exchange::Exchange;
+use gap::SESSION_BUCKET_MINUTES;
+use stored::TrainStoredWithLabel;
+
+/// How to interpret each output slot when evaluating.
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct EvalConfig {
+    /// Quantile level of each output slot, if the model outputs quantiles. Enables coverage, pinball and calibration.
+    pub quantiles: Option<[f32; MODEL_OUTPUT_WIDTH]>,
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DimMetrics {
+    pub mae: f64,
+    pub rmse: f64,
+    /// Mean of output - label.
+    pub bias: f64,
+    /// Fraction of records where output and label have the same sign, ignoring zero labels.
+    /// A zero output predicts no move, so it's a miss.
+    pub directional_accuracy: f64,
+    /// Fraction of records where label <= output, only with quantile outputs.
+    pub coverage: Option<f64>,
+    pub pinball: Option<f64>,
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct EvalReport {
+    pub count: usize,
+    pub mean_loss: f64,
+    pub dims: Vec<DimMetrics>,
+    /// (nominal quantile, observed coverage) sorted by nominal, only with quantile outputs.
+    pub calibration: Vec<(f64, f64)>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GroupReport {
+    pub key: String,
+    pub report: EvalReport,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WindowReport {
+    pub from: Timestamp,
+    pub to: Timestamp,
+    pub report: EvalReport,
+}
+
+/// Running sums that an EvalReport is computed from.
+#[derive(Debug, Clone)]
+pub struct EvalAccumulator {
+    quantiles: Option<[f32; MODEL_OUTPUT_WIDTH]>,
+    count: usize,
+    loss: f64,
+    abs_err: [f64; MODEL_OUTPUT_WIDTH],
+    sq_err: [f64; MODEL_OUTPUT_WIDTH],
+    err: [f64; MODEL_OUTPUT_WIDTH],
+    direction_hits: [usize; MODEL_OUTPUT_WIDTH],
+    direction_count: [usize; MODEL_OUTPUT_WIDTH],
+    covered: [usize; MODEL_OUTPUT_WIDTH],
+    pinball: [f64; MODEL_OUTPUT_WIDTH],
+}
+
+impl EvalAccumulator {
+    pub fn new(config: &EvalConfig) -> Self {
+        Self {
+            quantiles: config.quantiles, count: 0, loss: 0.0,
+            abs_err: [0.0; MODEL_OUTPUT_WIDTH], sq_err: [0.0; MODEL_OUTPUT_WIDTH], err: [0.0; MODEL_OUTPUT_WIDTH],
+            direction_hits: [0; MODEL_OUTPUT_WIDTH], direction_count: [0; MODEL_OUTPUT_WIDTH],
+            covered: [0; MODEL_OUTPUT_WIDTH], pinball: [0.0; MODEL_OUTPUT_WIDTH],
+        }
+    }
+
+    pub fn add(&mut self, output: &LabelType, label: &LabelType, loss: LossType) {
+        self.count += 1;
+        self.loss += loss as f64;
+        for i in 0..MODEL_OUTPUT_WIDTH {
+            let (out, lab) = (output[i] as f64, label[i] as f64);
+            let e = out - lab;
+            self.err[i] += e;
+            self.abs_err[i] += e.abs();
+            self.sq_err[i] += e * e;
+            if lab != 0.0 {
+                self.direction_count[i] += 1;
+                if out != 0.0 && out.signum() == lab.signum() {
+                    self.direction_hits[i] += 1;
+                }
+            }
+            if let Some(qs) = &self.quantiles {
+                let q = qs[i] as f64;
+                if lab <= out {
+                    self.covered[i] += 1;
+                }
+                self.pinball[i] += pinball_loss(q, out, lab);
+            }
+        }
+    }
+
+    pub fn add_record(&mut self, record: &TrainStoredWithLabel) {
+        self.add(&record.output, &record.label, record.loss);
+    }
+
+    pub fn report(&self) -> EvalReport {
+        let n = self.count.max(1) as f64;
+        let dims = (0..MODEL_OUTPUT_WIDTH).map(|i| {
+            let quantile = self.quantiles.is_some();
+            DimMetrics {
+                mae: self.abs_err[i] / n,
+                rmse: (self.sq_err[i] / n).sqrt(),
+                bias: self.err[i] / n,
+                directional_accuracy: self.direction_hits[i] as f64 / self.direction_count[i].max(1) as f64,
+                coverage: quantile.then(|| self.covered[i] as f64 / n),
+                pinball: quantile.then(|| self.pinball[i] / n),
+            }
+        }).collect::<Vec<_>>();
+        let mut calibration: Vec<(f64, f64)> = match &self.quantiles {
+            Some(qs) => qs.iter().zip(&dims).map(|(q, d)| (*q as f64, d.coverage.unwrap_or_default())).collect(),
+            None => Vec::new(),
+        };
+        calibration.sort_by(|a, b| a.0.total_cmp(&b.0));
+        EvalReport { count: self.count, mean_loss: self.loss / n, dims, calibration }
+    }
+}
+
+pub fn pinball_loss(q: f64, predicted: f64, actual: f64) -> f64 {
+    let diff = actual - predicted;
+    if diff >= 0.0 { q * diff } else { (q - 1.0) * diff }
+}
+
+pub fn evaluate<'a>(config: &EvalConfig, records: impl IntoIterator<Item = &'a TrainStoredWithLabel>) -> EvalReport {
+    let mut acc = EvalAccumulator::new(config);
+    records.into_iter().for_each(|r| acc.add_record(r));
+    acc.report()
+}
+
+/// Reports over windows of `window` consecutive records, advancing by `step`. Records should be in time order.
+pub fn evaluate_rolling(config: &EvalConfig, records: &[TrainStoredWithLabel], window: usize, step: usize) -> Vec<WindowReport> {
+    if window == 0 || records.len() < window {
+        return Vec::new();
+    }
+    (0..=records.len() - window).step_by(step.max(1)).map(|start| {
+        let slice = &records[start..start + window];
+        WindowReport { from: slice[0].timestamp, to: slice[window - 1].timestamp, report: evaluate(config, slice) }
+    }).collect()
+}
+
+/// Reports grouped by the key function, sorted by key.
+pub fn evaluate_grouped<'a>(config: &EvalConfig, records: impl IntoIterator<Item = &'a TrainStoredWithLabel>, key: impl Fn(&TrainStoredWithLabel) -> String) -> Vec<GroupReport> {
+    let mut groups: BTreeMap<String, EvalAccumulator> = BTreeMap::new();
+    for record in records {
+        groups.entry(key(record)).or_insert_with(|| EvalAccumulator::new(config)).add_record(record);
+    }
+    groups.into_iter().map(|(key, acc)| GroupReport { key, report: acc.report() }).collect()
+}
+
+/// Group key of the exchange's session date, like 2024-05-01.
+pub fn by_market_date(exchange: &Exchange, record: &TrainStoredWithLabel) -> String {
+    exchange.session_date(record.timestamp).to_string()
+}
+
+/// Group key of the start of the 30 minute bucket of the exchange's local time, like 09:30.
+/// Unlike the gap buckets this isn't clamped to the regular session, so pre and post market get their own keys.
+pub fn by_time_of_day(exchange: &Exchange, record: &TrainStoredWithLabel) -> String {
+    let local = exchange.to_local(record.timestamp);
+    let minutes = (local.hour() * 60 + local.minute()) / SESSION_BUCKET_MINUTES * SESSION_BUCKET_MINUTES;
+    format!("{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(timestamp: Timestamp, output: LabelType, label: LabelType) -> TrainStoredWithLabel {
+        TrainStoredWithLabel {
+            event_id: 0, timestamp, offset: 0, loss: 1.0,
+            input: ([0.0; chrono_util::CHRONO_FEATURES_SIZE], convert::new_series()),
+            output, label,
+        }
+    }
+
+    fn dim0(values: &[(ModelFloat, ModelFloat)]) -> DimMetrics {
+        let mut acc = EvalAccumulator::new(&EvalConfig::default());
+        for &(out, lab) in values {
+            let mut output = [0.0; MODEL_OUTPUT_WIDTH];
+            let mut label = [0.0; MODEL_OUTPUT_WIDTH];
+            output[0] = out;
+            label[0] = lab;
+            acc.add(&output, &label, 0.0);
+        }
+        acc.report().dims[0].clone()
+    }
+
+    #[test]
+    fn error_metrics() {
+        let d = dim0(&[(1.0, 0.0), (-1.0, 1.0)]);
+        assert_eq!(d.mae, 1.5);
+        assert_eq!(d.rmse, 2.5f64.sqrt());
+        assert_eq!(d.bias, -0.5);
+        assert_eq!(d.coverage, None);
+    }
+
+    #[test]
+    fn zero_output_is_not_a_predicted_move() {
+        // Zero labels are skipped, zero outputs are misses for either direction.
+        let d = dim0(&[(1.0, 2.0), (-1.0, -2.0), (0.0, 1.0), (0.0, -1.0), (1.0, 0.0)]);
+        assert_eq!(d.directional_accuracy, 0.5);
+        assert_eq!(dim0(&[(0.0, 1.0)]).directional_accuracy, 0.0);
+    }
+
+    #[test]
+    fn quantile_coverage_and_pinball() {
+        let mut quantiles = [0.5; MODEL_OUTPUT_WIDTH];
+        quantiles[0] = 0.9;
+        let config = EvalConfig { quantiles: Some(quantiles) };
+        let mut output = [0.0; MODEL_OUTPUT_WIDTH];
+        output[0] = 1.0;
+        let mut high = [0.0; MODEL_OUTPUT_WIDTH];
+        high[0] = 3.0;
+        let records = [record(0, output, [0.0; MODEL_OUTPUT_WIDTH]), record(1, output, high)];
+        let report = evaluate(&config, &records);
+        assert_eq!(report.dims[0].coverage, Some(0.5));
+        // 0.9 quantile: (1 - 0.9) * 1 below and 0.9 * 2 above
+        assert!((report.dims[0].pinball.unwrap() - (0.1 + 1.8) / 2.0).abs() < 1e-6);
+        assert_eq!(report.calibration.len(), MODEL_OUTPUT_WIDTH);
+        assert_eq!(report.calibration.last().unwrap().0 as f32, 0.9);
+        assert_eq!(pinball_loss(0.5, 1.0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn rolling_windows() {
+        let records: Vec<_> = (0..5).map(|i| record(i * 10, [0.0; MODEL_OUTPUT_WIDTH], [0.0; MODEL_OUTPUT_WIDTH])).collect();
+        let windows = evaluate_rolling(&EvalConfig::default(), &records, 3, 2);
+        assert_eq!(windows.iter().map(|w| (w.from, w.to, w.report.count)).collect::<Vec<_>>(), vec![(0, 20, 3), (20, 40, 3)]);
+        assert!(evaluate_rolling(&EvalConfig::default(), &records, 6, 1).is_empty());
+    }
+
+    fn at(exchange: &Exchange, date: (i32, u32, u32), hour: u32, minute: u32) -> Timestamp {
+        let local = NaiveDate::from_ymd_opt(date.0, date.1, date.2).unwrap().and_hms_opt(hour, minute, 0).unwrap();
+        local.and_local_timezone(exchange.timezone).unwrap().timestamp_millis()
+    }
+
+    #[test]
+    fn time_of_day_uses_exchange_open() {
+        let us = Exchange::us_equities();
+        let xetra = Exchange::xetra();
+        let cme = Exchange::cme_equity_futures();
+        let key = |exchange: &Exchange, ts| by_time_of_day(exchange, &record(ts, [0.0; MODEL_OUTPUT_WIDTH], [0.0; MODEL_OUTPUT_WIDTH]));
+        assert_eq!(key(&us, at(&us, (2024, 1, 2), 9, 45)), "09:30");
+        assert_eq!(key(&us, at(&us, (2024, 1, 2), 10, 1)), "10:00");
+        assert_eq!(key(&xetra, at(&xetra, (2024, 1, 2), 9, 15)), "09:00");
+        assert_eq!(key(&xetra, at(&xetra, (2024, 1, 2), 11, 40)), "11:30");
+        // CME opens at 17:00 the evening before the session date.
+        assert_eq!(key(&cme, at(&cme, (2024, 1, 1), 17, 10)), "17:00");
+        // Past the first 6 hours of the session the keys keep following the clock.
+        assert_eq!(key(&us, at(&us, (2024, 1, 2), 15, 59)), "15:30");
+        assert_eq!(key(&xetra, at(&xetra, (2024, 1, 2), 17, 20)), "17:00");
+        assert_eq!(key(&cme, at(&cme, (2024, 1, 2), 8, 45)), "08:30");
+
+        let records = [
+            record(at(&us, (2024, 1, 2), 9, 45), [0.0; MODEL_OUTPUT_WIDTH], [0.0; MODEL_OUTPUT_WIDTH]),
+            record(at(&us, (2024, 1, 2), 20, 30), [0.0; MODEL_OUTPUT_WIDTH], [0.0; MODEL_OUTPUT_WIDTH]),
+        ];
+        let groups = evaluate_grouped(&EvalConfig::default(), &records, |r| by_market_date(&us, r));
+        // After 20:00 New York time belongs to the next session date.
+        assert_eq!(groups.iter().map(|g| g.key.as_str()).collect::<Vec<_>>(), vec!["2024-01-02", "2024-01-03"]);
+    }
+
+    #[test]
+    fn time_of_day_outside_regular_session() {
+        let us = Exchange::us_equities();
+        let key = |ts| by_time_of_day(&us, &record(ts, [0.0; MODEL_OUTPUT_WIDTH], [0.0; MODEL_OUTPUT_WIDTH]));
+        assert_eq!(key(at(&us, (2024, 1, 2), 4, 10)), "04:00");
+        assert_eq!(key(at(&us, (2024, 1, 2), 8, 59)), "08:30");
+        assert_eq!(key(at(&us, (2024, 1, 2), 16, 5)), "16:00");
+        assert_eq!(key(at(&us, (2024, 1, 2), 19, 45)), "19:30");
+    }
+}
@@ -0,0 +1,272 @@
+use std::sync::LazyLock;
+
+use chrono::{Datelike, Duration, NaiveTime, TimeZone, Timelike};
+
+use crate::*;
+use session::Session;
+
+/// Holidays and early closes of an exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum HolidayCalendar {
+    /// NYSE rules from calendar.rs, also used for CME as an approximation.
+    Nyse,
+    /// Only weekends are closed.
+    // TODO: european exchange holidays
+    WeekendsOnly,
+}
+
+impl HolidayCalendar {
+    pub fn is_trading_day(&self, date: NaiveDate) -> bool {
+        match self {
+            HolidayCalendar::Nyse => calendar::is_trading_day(date),
+            HolidayCalendar::WeekendsOnly => !calendar::is_weekend(date),
+        }
+    }
+
+    pub fn is_early_close(&self, date: NaiveDate) -> bool {
+        match self {
+            HolidayCalendar::Nyse => calendar::is_early_close(date),
+            HolidayCalendar::WeekendsOnly => false,
+        }
+    }
+
+    /// Trading days after `from` up to and including `to`.
+    pub fn trading_days_between(&self, from: NaiveDate, to: NaiveDate) -> i64 {
+        from.iter_days().skip(1).take_while(|d| *d <= to).filter(|d| self.is_trading_day(*d)).count() as i64
+    }
+}
+
+/// Session boundaries in minutes from midnight of the session date in the exchange timezone.
+/// They can be negative for sessions that open the evening before, like CME futures.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SessionSchedule {
+    /// Local time from which events belong to the next session date, if sessions span midnight.
+    pub rollover: Option<NaiveTime>,
+    pub pre_open: i32,
+    pub open: i32,
+    pub close: i32,
+    /// Close on early close days.
+    pub early_close: Option<i32>,
+    pub post_close: i32,
+    /// Whether the time between post_close and the next pre_open is an overnight session or closed.
+    pub overnight: bool,
+}
+
+/// Where a stream trades: timezone, session schedule and holiday calendar.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Exchange {
+    pub name: String,
+    /// IANA name like America/New_York.
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
+    pub timezone: chrono_tz::Tz,
+    pub schedule: SessionSchedule,
+    pub calendar: HolidayCalendar,
+}
+
+static US_EQUITIES: LazyLock<Exchange> = LazyLock::new(Exchange::us_equities);
+
+/// The exchange used when a stream doesn't configure one.
+pub fn default_exchange() -> &'static Exchange {
+    &US_EQUITIES
+}
+
+impl Default for Exchange {
+    fn default() -> Self {
+        default_exchange().clone()
+    }
+}
+
+const fn hm(hour: i32, minute: i32) -> i32 {
+    hour * 60 + minute
+}
+
+impl Exchange {
+    /// NYSE/Nasdaq: pre-market from 4:00, regular 9:30 to 16:00, post-market to 20:00, overnight until 4:00.
+    pub fn us_equities() -> Self {
+        Self {
+            name: "US".to_string(),
+            timezone: chrono_tz::US::Eastern,
+            schedule: SessionSchedule {
+                rollover: NaiveTime::from_hms_opt(20, 0, 0),
+                pre_open: hm(4, 0), open: hm(9, 30), close: hm(16, 0), early_close: Some(hm(13, 0)), post_close: hm(20, 0),
+                overnight: true,
+            },
+            calendar: HolidayCalendar::Nyse,
+        }
+    }
+
+    /// CME Globex equity futures: 17:00 the evening before to 16:00 Chicago time, with a daily break.
+    pub fn cme_equity_futures() -> Self {
+        Self {
+            name: "CME".to_string(),
+            timezone: chrono_tz::America::Chicago,
+            schedule: SessionSchedule {
+                rollover: NaiveTime::from_hms_opt(17, 0, 0),
+                pre_open: hm(-7, 0), open: hm(-7, 0), close: hm(16, 0), early_close: Some(hm(12, 0)), post_close: hm(16, 0),
+                overnight: false,
+            },
+            calendar: HolidayCalendar::Nyse,
+        }
+    }
+
+    /// Xetra: 9:00 to 17:30 Frankfurt time.
+    pub fn xetra() -> Self {
+        Self {
+            name: "XETRA".to_string(),
+            timezone: chrono_tz::Europe::Berlin,
+            schedule: SessionSchedule {
+                rollover: None,
+                pre_open: hm(9, 0), open: hm(9, 0), close: hm(17, 30), early_close: None, post_close: hm(17, 30),
+                overnight: false,
+            },
+            calendar: HolidayCalendar::WeekendsOnly,
+        }
+    }
+
+    /// One of the presets by its function name, e.g. "xetra".
+    pub fn preset(name: &str) -> Option<Self> {
+        match name {
+            "us_equities" => Some(Self::us_equities()),
+            "cme_equity_futures" => Some(Self::cme_equity_futures()),
+            "xetra" => Some(Self::xetra()),
+            _ => None,
+        }
+    }
+
+    pub fn to_local(&self, ts: Timestamp) -> DateTime<chrono_tz::Tz> {
+        DateTime::from_timestamp_millis(ts).unwrap().with_timezone(&self.timezone)
+    }
+
+    /// The trading date the timestamp belongs to, which is the next day after the rollover time.
+    pub fn session_date(&self, ts: Timestamp) -> NaiveDate {
+        self.session_date_and_minutes(ts).0
+    }
+
+    // Session date and minutes from its midnight, negative before midnight.
+    fn session_date_and_minutes(&self, ts: Timestamp) -> (NaiveDate, i32) {
+        let local = self.to_local(ts);
+        let minutes = (local.hour() * 60 + local.minute()) as i32;
+        match self.schedule.rollover {
+            Some(rollover) if local.time() >= rollover => (local.date_naive() + Duration::days(1), minutes - hm(24, 0)),
+            _ => (local.date_naive(), minutes),
+        }
+    }
+
+    pub fn is_trading_day(&self, date: NaiveDate) -> bool {
+        self.calendar.is_trading_day(date)
+    }
+
+    /// The date if it's a trading day, otherwise the next one.
+    pub fn next_trading_day(&self, date: NaiveDate) -> NaiveDate {
+        let mut d = date;
+        while !self.is_trading_day(d) {
+            d += Duration::days(1);
+        }
+        d
+    }
+
+    /// Timestamp of the minutes from midnight of the session date, like the schedule times.
+    pub fn timestamp_at(&self, date: NaiveDate, minutes: i32) -> Timestamp {
+        // unwrap ok because midnight is valid
+        let local = date.and_hms_opt(0, 0, 0).unwrap() + Duration::minutes(minutes as i64);
+        // A time skipped by DST moves past the change, an ambiguous one takes the earlier.
+        let dt = self.timezone.from_local_datetime(&local).earliest()
+            .or_else(|| self.timezone.from_local_datetime(&(local + Duration::hours(1))).earliest());
+        // unwrap ok because DST changes are at most an hour
+        dt.unwrap().timestamp_millis()
+    }
+
+    pub fn open_timestamp(&self, date: NaiveDate) -> Timestamp {
+        self.timestamp_at(date, self.schedule.open)
+    }
+
+    /// Close of the regular session, early close aware.
+    pub fn close_timestamp(&self, date: NaiveDate) -> Timestamp {
+        self.timestamp_at(date, self.close_minutes(date))
+    }
+
+    /// Close of the regular session in minutes from midnight, early close aware.
+    pub fn close_minutes(&self, date: NaiveDate) -> i32 {
+        match self.schedule.early_close {
+            Some(early) if self.calendar.is_early_close(date) => early,
+            _ => self.schedule.close,
+        }
+    }
+
+    pub fn session_of(&self, ts: Timestamp) -> Session {
+        let (date, minutes) = self.session_date_and_minutes(ts);
+        if !self.is_trading_day(date) {
+            return Session::Closed;
+        }
+        let s = &self.schedule;
+        let close = self.close_minutes(date);
+        // Without a post-market session it ends with the regular one, also on early close days.
+        let post_close = if s.post_close == s.close { close } else { s.post_close };
+        if minutes < s.pre_open || minutes >= post_close {
+            if s.overnight { Session::Overnight } else { Session::Closed }
+        } else if minutes < s.open {
+            Session::PreMarket
+        } else if minutes < close {
+            Session::Regular
+        } else {
+            Session::PostMarket
+        }
+    }
+
+    pub fn in_regular_session(&self, ts: Timestamp) -> bool {
+        self.session_of(ts) == Session::Regular
+    }
+
+    /// Minutes since the regular open, negative before it.
+    pub fn minutes_since_open(&self, ts: Timestamp) -> i32 {
+        let (_, minutes) = self.session_date_and_minutes(ts);
+        minutes - self.schedule.open
+    }
+
+    pub fn is_weekday(&self, ts: Timestamp) -> bool {
+        self.to_local(ts).weekday().num_days_from_monday() < 5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn d(y: i32, m: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, day).unwrap()
+    }
+
+    #[test]
+    fn open_and_close_timestamps() {
+        let us = Exchange::us_equities();
+        // 9:30 New York is 14:30 UTC in winter and 13:30 in summer
+        assert_eq!(us.open_timestamp(d(2024, 1, 2)), 1_704_205_800_000);
+        assert_eq!(us.open_timestamp(d(2024, 7, 1)) % 86_400_000, (13 * 60 + 30) * 60_000);
+        assert_eq!(us.close_timestamp(d(2024, 7, 3)) - us.open_timestamp(d(2024, 7, 3)), 210 * 60_000);
+        assert_eq!(us.session_of(us.open_timestamp(d(2024, 1, 2))), Session::Regular);
+        assert_eq!(us.session_of(us.close_timestamp(d(2024, 1, 2))), Session::PostMarket);
+        // CME opens at 17:00 Chicago the evening before the session date.
+        let cme = Exchange::cme_equity_futures();
+        let open = cme.open_timestamp(d(2024, 1, 3));
+        assert_eq!(cme.to_local(open).format("%Y-%m-%d %H:%M").to_string(), "2024-01-02 17:00");
+        assert_eq!(cme.session_date(open), d(2024, 1, 3));
+    }
+
+    #[test]
+    fn next_trading_day_uses_calendar() {
+        assert_eq!(Exchange::us_equities().next_trading_day(d(2024, 3, 29)), d(2024, 4, 1));
+        assert_eq!(Exchange::xetra().next_trading_day(d(2024, 3, 29)), d(2024, 3, 29));
+        assert_eq!(Exchange::xetra().next_trading_day(d(2024, 3, 30)), d(2024, 4, 1));
+    }
+
+    #[test]
+    fn presets_by_name() {
+        assert_eq!(Exchange::preset("xetra"), Some(Exchange::xetra()));
+        assert_eq!(Exchange::preset("us_equities"), Some(Exchange::default()));
+        assert_eq!(Exchange::preset("nope"), None);
+    }
+}
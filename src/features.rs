@@ -0,0 +1,282 @@
+use std::collections::VecDeque;
+
+use anyhow::bail;
+
+use crate::*;
+use chrono_util::make_chrono_features_on;
+use convert::{adjust, new_series, TimeEmbedder};
+use data_info::*;
+use exchange::Exchange;
+use quote::QuoteEvent;
+use series::SeriesEvent;
+
+/// Derived per-event features that can be selected by name in QuoteStreamSpec.features.
+/// The order here is the default order when only feature_size is given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum QuoteFeature {
+    /// adjust(base_bid / bid), the original feature 0.
+    BidRatio,
+    /// adjust(base_ask / ask), the original feature 1.
+    AskRatio,
+    /// ln(mid / base_mid) in bps.
+    MidLogReturn,
+    /// (ask - bid) / mid in bps.
+    SpreadBps,
+    /// Size weighted microprice relative to mid in bps, 0 when sizes are not available.
+    Microprice,
+    /// ln(1 + millis since the previous event).
+    InterArrival,
+    /// Std dev of mid log returns in bps over the trailing volatility_window events.
+    RollingVolatility,
+    /// 1 if a gap was detected before the event, otherwise 0.
+    Gap,
+    /// Session::index / 4 of the event.
+    Session,
+}
+
+impl QuoteFeature {
+    pub fn name(&self) -> &'static str {
+        match self {
+            QuoteFeature::BidRatio => "bid_ratio",
+            QuoteFeature::AskRatio => "ask_ratio",
+            QuoteFeature::MidLogReturn => "mid_log_return",
+            QuoteFeature::SpreadBps => "spread_bps",
+            QuoteFeature::Microprice => "microprice",
+            QuoteFeature::InterArrival => "inter_arrival",
+            QuoteFeature::RollingVolatility => "rolling_volatility",
+            QuoteFeature::Gap => "gap",
+            QuoteFeature::Session => "session",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            QuoteFeature::BidRatio => "adjust(base_bid / bid)",
+            QuoteFeature::AskRatio => "adjust(base_ask / ask)",
+            QuoteFeature::MidLogReturn => "ln(mid / base_mid) in bps",
+            QuoteFeature::SpreadBps => "(ask - bid) / mid in bps",
+            QuoteFeature::Microprice => "size weighted microprice relative to mid in bps",
+            QuoteFeature::InterArrival => "ln(1 + millis since previous event)",
+            QuoteFeature::RollingVolatility => "std dev of mid log returns in bps over the volatility window",
+            QuoteFeature::Gap => "1 if a gap was detected before the event",
+            QuoteFeature::Session => "session index / 4: pre-market, regular, post-market, overnight, closed",
+        }
+    }
+
+    /// Typical (min, max) of the values, not enforced.
+    pub fn range(&self) -> (ModelFloat, ModelFloat) {
+        match self {
+            QuoteFeature::BidRatio | QuoteFeature::AskRatio | QuoteFeature::Gap | QuoteFeature::Session => (0.0, 1.0),
+            QuoteFeature::MidLogReturn => (-500.0, 500.0),
+            QuoteFeature::SpreadBps | QuoteFeature::RollingVolatility => (0.0, 100.0),
+            QuoteFeature::Microprice => (-50.0, 50.0),
+            QuoteFeature::InterArrival => (0.0, 16.0),
+        }
+    }
+}
+
+pub const ALL_QUOTE_FEATURES: [QuoteFeature; 9] = [
+    QuoteFeature::BidRatio, QuoteFeature::AskRatio, QuoteFeature::MidLogReturn, QuoteFeature::SpreadBps,
+    QuoteFeature::Microprice, QuoteFeature::InterArrival, QuoteFeature::RollingVolatility, QuoteFeature::Gap,
+    QuoteFeature::Session,
+];
+
+pub const DEFAULT_VOLATILITY_WINDOW: usize = 32;
+
+const BPS: f32 = 10_000.0;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FeatureSet {
+    pub features: Vec<QuoteFeature>,
+    pub time_embedding_size: usize,
+    pub volatility_window: usize,
+    /// Exchange of the stream, for QuoteFeature::Session.
+    #[serde(default)]
+    pub exchange: Exchange,
+}
+
+impl FeatureSet {
+    pub fn from_spec(spec: &QuoteStreamSpec) -> anyhow::Result<Self> {
+        let features = if spec.features.is_empty() {
+            if spec.feature_size > ALL_QUOTE_FEATURES.len() {
+                bail!("feature_size {} is more than the {} available features", spec.feature_size, ALL_QUOTE_FEATURES.len());
+            }
+            ALL_QUOTE_FEATURES[..spec.feature_size].to_vec()
+        } else if spec.features.len() != spec.feature_size {
+            bail!("feature_size {} does not match the {} named features", spec.feature_size, spec.features.len());
+        } else {
+            spec.features.clone()
+        };
+        if spec.time_embedding_size != TIME_EMBEDDING_SIZE {
+            bail!("time_embedding_size {} is not supported, only {}", spec.time_embedding_size, TIME_EMBEDDING_SIZE);
+        }
+        let volatility_window = spec.volatility_window.unwrap_or(DEFAULT_VOLATILITY_WINDOW);
+        Ok(Self { features, time_embedding_size: spec.time_embedding_size, volatility_window, exchange: spec.exchange.clone() })
+    }
+
+    pub fn item_size(&self) -> usize {
+        self.features.len() + self.time_embedding_size
+    }
+
+    /// Like series_to_input, but with the configured features followed by the time embedding in each item.
+    /// Returns the items flattened, oldest event first, each item_size() wide.
+    pub fn series_to_features(&self, events: &VecDeque<QuoteEvent>) -> anyhow::Result<Vec<ModelFloat>> {
+        let Some(base) = events.back() else {
+            bail!("No events to convert");
+        };
+        let base_time = base.timestamp();
+        let base_mid = mid(base);
+        let embedder = TimeEmbedder::<TIME_EMBEDDING_SIZE>::new();
+
+        let mut returns = Vec::with_capacity(events.len());
+        let mut prev_mid = mid(&events[0]);
+        for event in events {
+            let m = mid(event);
+            returns.push((m / prev_mid).ln() * BPS);
+            prev_mid = m;
+        }
+
+        let width = self.item_size();
+        let mut result = Vec::with_capacity(events.len() * width);
+        for (i, event) in events.iter().enumerate() {
+            for feature in &self.features {
+                let value = match feature {
+                    QuoteFeature::BidRatio => adjust(base.bid / event.bid),
+                    QuoteFeature::AskRatio => adjust(base.ask / event.ask),
+                    QuoteFeature::MidLogReturn => (mid(event) / base_mid).ln() * BPS,
+                    QuoteFeature::SpreadBps => (event.ask - event.bid) / mid(event) * BPS,
+                    QuoteFeature::Microprice => microprice_offset(event),
+                    QuoteFeature::InterArrival => {
+                        let delta = if i == 0 { 0 } else { event.timestamp() - events[i - 1].timestamp() };
+                        (delta.max(0) as ModelFloat).ln_1p()
+                    },
+                    QuoteFeature::RollingVolatility => {
+                        let from = (i + 1).saturating_sub(self.volatility_window);
+                        std_dev(&returns[from..=i])
+                    },
+                    QuoteFeature::Gap => if event.gap_before > 0 { 1.0 } else { 0.0 },
+                    QuoteFeature::Session => self.exchange.session_of(event.timestamp()).index() as ModelFloat / 4.0,
+                };
+                result.push(value);
+            }
+            result.extend_from_slice(&embedder.embed(base_time - event.timestamp()));
+        }
+        Ok(result)
+    }
+
+    /// Same as series_to_input for a window of SERIES1_SIZE events, only when the features fit the model's SeriesItem.
+    pub fn series_to_input(&self, events: &VecDeque<QuoteEvent>) -> anyhow::Result<InputRaw> {
+        if self.item_size() != SERIES1_ITEM_SIZE {
+            bail!("Feature set items are {} wide but SeriesItem is {}", self.item_size(), SERIES1_ITEM_SIZE);
+        }
+        if events.len() != SERIES1_SIZE {
+            bail!("Need {} events for a series, got {}", SERIES1_SIZE, events.len());
+        }
+        let values = self.series_to_features(events)?;
+        let mut input = new_series();
+        for (item, chunk) in input.iter_mut().zip(values.chunks_exact(SERIES1_ITEM_SIZE)) {
+            item.copy_from_slice(chunk);
+        }
+        // unwrap ok because checked len above
+        Ok((make_chrono_features_on(&self.exchange, events.back().unwrap().timestamp()), input))
+    }
+}
+
+pub fn mid(event: &QuoteEvent) -> SeriesFloat {
+    (event.bid + event.ask) / 2.0
+}
+
+fn microprice_offset(event: &QuoteEvent) -> ModelFloat {
+    let total = event.bidsz + event.asksz;
+    if total <= 0.0 {
+        return 0.0;
+    }
+    let micro = (event.bid * event.asksz + event.ask * event.bidsz) / total;
+    (micro / mid(event) - 1.0) * BPS
+}
+
+pub fn std_dev(xs: &[ModelFloat]) -> ModelFloat {
+    if xs.len() < 2 {
+        return 0.0;
+    }
+    let n = xs.len() as ModelFloat;
+    let mean = xs.iter().sum::<ModelFloat>() / n;
+    (xs.iter().map(|x| (x - mean).powi(2)).sum::<ModelFloat>() / (n - 1.0)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use synthetic::{SyntheticConfig, SyntheticMarket};
+
+    fn window() -> VecDeque<QuoteEvent> {
+        SyntheticMarket::new(SyntheticConfig::default()).take(SERIES1_SIZE).collect()
+    }
+
+    fn spec(feature_size: usize, features: Vec<QuoteFeature>) -> QuoteStreamSpec {
+        let mut spec = data_config().quote_streams.remove(0);
+        spec.feature_size = feature_size;
+        spec.features = features;
+        spec
+    }
+
+    #[test]
+    fn default_config_fits_model_input() {
+        let config = data_config();
+        config.validate().unwrap();
+        let features = FeatureSet::from_spec(&config.quote_streams[0]).unwrap();
+        assert_eq!(features.item_size(), SERIES1_ITEM_SIZE);
+        assert_eq!(features.features, vec![QuoteFeature::BidRatio, QuoteFeature::AskRatio]);
+    }
+
+    #[test]
+    fn default_features_match_series_to_input() {
+        let events = window();
+        let features = FeatureSet::from_spec(&spec(FEATURES1_SIZE, Vec::new())).unwrap();
+        let (chrono, series) = features.series_to_input(&events).unwrap();
+        let (expected_chrono, expected_series) = convert::series_to_input(&events).unwrap();
+        assert_eq!(chrono, expected_chrono);
+        assert_eq!(series, expected_series);
+    }
+
+    #[test]
+    fn wider_feature_sets_are_not_model_input() {
+        let events = window();
+        let features = FeatureSet::from_spec(&spec(4, Vec::new())).unwrap();
+        assert_eq!(features.series_to_features(&events).unwrap().len(), SERIES1_SIZE * features.item_size());
+        assert!(features.series_to_input(&events).is_err());
+
+        let mut config = data_config();
+        config.quote_streams[0].feature_size = 4;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn from_spec_checks_sizes() {
+        assert!(FeatureSet::from_spec(&spec(ALL_QUOTE_FEATURES.len() + 1, Vec::new())).is_err());
+        assert!(FeatureSet::from_spec(&spec(2, vec![QuoteFeature::SpreadBps])).is_err());
+        let named = FeatureSet::from_spec(&spec(2, vec![QuoteFeature::SpreadBps, QuoteFeature::Gap])).unwrap();
+        assert_eq!(named.features, vec![QuoteFeature::SpreadBps, QuoteFeature::Gap]);
+    }
+
+    #[test]
+    fn session_uses_stream_exchange() {
+        // 2024-01-02 14:00 UTC is 9:00 pre-market in New York and 15:00 regular in Frankfurt.
+        let events: VecDeque<QuoteEvent> = [QuoteEvent::at(1_704_204_000_000, 100.0, 100.1)].into();
+        let mut spec = spec(1, vec![QuoteFeature::Session]);
+        let us = FeatureSet::from_spec(&spec).unwrap().series_to_features(&events).unwrap();
+        assert_eq!(us[0], 0.0);
+        spec.exchange = Exchange::xetra();
+        let xetra = FeatureSet::from_spec(&spec).unwrap().series_to_features(&events).unwrap();
+        assert_eq!(xetra[0], 0.25);
+    }
+
+    #[test]
+    fn std_dev_is_sample() {
+        assert_eq!(std_dev(&[1.0]), 0.0);
+        assert_eq!(std_dev(&[1.0, 3.0]), 2f32.sqrt());
+    }
+}
@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+
+use crate::*;
+use exchange::{default_exchange, Exchange};
+
+/// What the handler does when a gap is detected between two consecutive valid events.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum GapPolicy {
+    /// Only record the gap in the stats.
+    #[default]
+    Ignore,
+    /// Drop the current window and start over from the event after the gap.
+    Reset,
+    /// Keep the window but mark the event after the gap (see SeriesEvent::mark_gap).
+    Marker,
+}
+
+/// Regular session is split into 30 minute buckets, each with its own expected inter-arrival time.
+pub const SESSION_BUCKET_MINUTES: u32 = 30;
+pub const SESSION_BUCKETS: usize = 13;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GapConfig {
+    pub policy: GapPolicy,
+    /// Deltas at or below this are never a gap, regardless of the expected rate.
+    pub min_gap: Timestamp,
+    /// A delta is a gap when it exceeds this multiple of the expected inter-arrival time.
+    pub rate_multiple: f32,
+    /// Expected inter-arrival time in millis per session bucket.
+    pub expected: [f32; SESSION_BUCKETS],
+    /// EWMA weight to learn the expected inter-arrival times online. 0 disables learning.
+    pub learn_alpha: f32,
+}
+
+impl Default for GapConfig {
+    fn default() -> Self {
+        // Quotes arrive fastest near the open and close, slowest midday.
+        let expected = [50.0, 100.0, 150.0, 200.0, 250.0, 300.0, 300.0, 300.0, 250.0, 200.0, 150.0, 100.0, 50.0];
+        Self { policy: GapPolicy::Ignore, min_gap: 10_000, rate_multiple: 200.0, expected, learn_alpha: 0.0 }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GapStats {
+    pub count: u32,
+    pub total: Timestamp,
+    pub largest: Timestamp,
+    /// Timestamp of the event that ended the largest gap.
+    pub largest_at: Timestamp,
+}
+
+impl GapStats {
+    fn record(&mut self, delta: Timestamp, ts: Timestamp) {
+        self.count += 1;
+        self.total += delta;
+        if delta > self.largest {
+            self.largest = delta;
+            self.largest_at = ts;
+        }
+    }
+}
+
+/// What GapDetector learned while running, saved in handler checkpoints.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GapState {
+    pub expected: [f32; SESSION_BUCKETS],
+    pub by_day: BTreeMap<NaiveDate, GapStats>,
+}
+
+#[derive(Debug, Default)]
+pub struct GapDetector {
+    pub config: GapConfig,
+    by_day: BTreeMap<NaiveDate, GapStats>,
+}
+
+impl GapDetector {
+    pub fn new(config: GapConfig) -> Self {
+        Self { config, by_day: BTreeMap::new() }
+    }
+
+    pub fn policy(&self) -> GapPolicy {
+        self.config.policy
+    }
+
+    /// Returns the size of the gap in millis if the delta from prev to ts is one, and records it in the stats.
+    pub fn check(&mut self, exchange: &Exchange, prev: Timestamp, ts: Timestamp) -> Option<Timestamp> {
+        let delta = ts - prev;
+        let bucket = exchange_session_bucket(exchange, ts);
+        let threshold = (self.config.rate_multiple * self.config.expected[bucket]) as Timestamp;
+        if delta > self.config.min_gap.max(threshold) {
+            self.by_day.entry(exchange.session_date(ts)).or_default().record(delta, ts);
+            Some(delta)
+        } else {
+            let alpha = self.config.learn_alpha;
+            if alpha > 0.0 {
+                let expected = &mut self.config.expected[bucket];
+                *expected = (1.0 - alpha) * *expected + alpha * delta as f32;
+            }
+            None
+        }
+    }
+
+    pub fn stats_for(&self, date: NaiveDate) -> Option<&GapStats> {
+        self.by_day.get(&date)
+    }
+
+    pub fn daily_stats(&self) -> &BTreeMap<NaiveDate, GapStats> {
+        &self.by_day
+    }
+
+    pub fn clear_stats(&mut self) {
+        self.by_day.clear();
+    }
+
+    /// The learned expected rates and stats. The previous timestamp isn't kept here, check is given the handler's last event.
+    pub fn state(&self) -> GapState {
+        GapState { expected: self.config.expected, by_day: self.by_day.clone() }
+    }
+
+    pub fn restore(&mut self, state: GapState) {
+        self.config.expected = state.expected;
+        self.by_day = state.by_day;
+    }
+}
+
+/// Index of the 30 minute bucket since the 9:30 open, clamped to the regular session.
+#[deprecated(note = "only the default exchange, use exchange_session_bucket")]
+pub fn session_bucket(dt: MarketTimestamp) -> usize {
+    exchange_session_bucket(default_exchange(), dt.timestamp_millis())
+}
+
+/// Same as session_bucket from the exchange's open.
+pub fn exchange_session_bucket(exchange: &Exchange, ts: Timestamp) -> usize {
+    let minutes = exchange.minutes_since_open(ts);
+    (minutes.max(0) as usize / SESSION_BUCKET_MINUTES as usize).min(SESSION_BUCKETS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use quote::{QuoteEvent, QuoteValues};
+    use series_proc::{BaseHandler, HandleOutcome, Processor};
+
+    struct Keep;
+
+    impl Processor<VecDeque<QuoteEvent>, QuoteValues> for Keep {
+        fn process(&mut self, _start_values: &QuoteValues, _events: &mut VecDeque<QuoteEvent>) -> bool {
+            true
+        }
+    }
+
+    // 2024-01-02 at hh:mm New York time, a regular NYSE trading day.
+    fn ts(hour: u32, minute: u32) -> Timestamp {
+        NaiveDate::from_ymd_opt(2024, 1, 2).unwrap().and_hms_opt(hour + 5, minute, 0).unwrap().and_utc().timestamp_millis()
+    }
+
+    #[test]
+    fn threshold_is_min_gap_or_rate_multiple_of_bucket() {
+        let exchange = default_exchange();
+        let mut gaps = GapDetector::default();
+        let open = ts(9, 30);
+        // First bucket expects 50ms, so the threshold is max(10_000, 200 * 50)
+        assert_eq!(gaps.check(exchange, open, open + 10_000), None);
+        assert_eq!(gaps.check(exchange, open, open + 10_001), Some(10_001));
+        // Midday bucket expects 300ms, so the threshold is 60_000
+        let midday = ts(12, 30);
+        assert_eq!(exchange_session_bucket(exchange, midday), 6);
+        assert_eq!(gaps.check(exchange, midday, midday + 60_000), None);
+        assert_eq!(gaps.check(exchange, midday, midday + 60_001), Some(60_001));
+
+        let stats = gaps.stats_for(exchange.session_date(open)).unwrap();
+        assert_eq!(stats.count, 2);
+        assert_eq!(stats.total, 70_002);
+        assert_eq!(stats.largest, 60_001);
+        assert_eq!(stats.largest_at, midday + 60_001);
+    }
+
+    #[test]
+    fn learns_expected_rate_from_non_gaps() {
+        let exchange = default_exchange();
+        let mut gaps = GapDetector::new(GapConfig { learn_alpha: 0.5, ..GapConfig::default() });
+        let open = ts(9, 30);
+        assert_eq!(gaps.check(exchange, open, open + 1_000), None);
+        assert_eq!(gaps.config.expected[0], 525.0);
+        assert!(gaps.daily_stats().is_empty());
+    }
+
+    #[test]
+    fn bucket_clamps_to_regular_session() {
+        let exchange = default_exchange();
+        assert_eq!(exchange_session_bucket(exchange, ts(8, 0)), 0);
+        assert_eq!(exchange_session_bucket(exchange, ts(10, 0)), 1);
+        assert_eq!(exchange_session_bucket(exchange, ts(15, 59)), SESSION_BUCKETS - 1);
+        assert_eq!(exchange_session_bucket(exchange, ts(18, 0)), SESSION_BUCKETS - 1);
+    }
+
+    fn run(policy: GapPolicy) -> (BaseHandler<QuoteValues, QuoteEvent, Keep>, Vec<HandleOutcome>) {
+        let config = GapConfig { policy, ..GapConfig::default() };
+        let mut handler = BaseHandler::new_with_gaps(Keep, GapDetector::new(config));
+        let open = ts(9, 30);
+        let outcomes = [open, open + 1_000, open + 31_000]
+            .into_iter()
+            .map(|t| handler.handle_with_outcome(QuoteEvent::at(t, 100.0, 100.1)).1)
+            .collect();
+        (handler, outcomes)
+    }
+
+    #[test]
+    fn ignore_policy_keeps_window() {
+        let (handler, outcomes) = run(GapPolicy::Ignore);
+        assert_eq!(outcomes[2], HandleOutcome::AcceptedAfterGap(30_000));
+        assert_eq!(handler.events.len(), 3);
+        assert_eq!(handler.events[2].gap_before, 0);
+        assert_eq!(handler.gaps.daily_stats().values().next().unwrap().count, 1);
+    }
+
+    #[test]
+    fn reset_policy_starts_over_from_event_after_gap() {
+        let (handler, outcomes) = run(GapPolicy::Reset);
+        assert_eq!(outcomes[1], HandleOutcome::Accepted);
+        assert_eq!(outcomes[2], HandleOutcome::AcceptedAfterGap(30_000));
+        assert_eq!(handler.events.len(), 1);
+        assert_eq!(handler.events[0].biddate, ts(9, 30) + 31_000);
+    }
+
+    #[test]
+    fn marker_policy_marks_event_after_gap() {
+        let (handler, _) = run(GapPolicy::Marker);
+        assert_eq!(handler.events.len(), 3);
+        assert_eq!(handler.events[1].gap_before, 0);
+        assert_eq!(handler.events[2].gap_before, 30_000);
+    }
+}
@@ -1,5 +1,8 @@
+use anyhow::bail;
+
 use crate::*;
 use data_info::*;
+use features::std_dev;
 
 // impl From<Vec<u8>> for Label {
 //     fn from(bytes: Vec<u8>) -> Self {
@@ -9,15 +12,212 @@ use data_info::*;
 
 /// Published to series by label and read by train.
 #[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct LabelEvent {
     pub event_id: EventId,
+    /// Covers all horizons when there are several.
     pub offset_from: OffsetId,
     pub offset_to: OffsetId,
     pub timestamp: Timestamp,
-    pub label: LabelType
+    pub label: LabelType,
+    /// Offsets used for each horizon of the LabelSpec, in spec order. Empty for single range labels.
+    #[serde(default)]
+    pub horizons: Vec<OffsetRange>,
 }
 impl LabelEvent {
     pub fn new(event_id: EventId, timestamp: Timestamp, offset_from: OffsetId, offset_to: OffsetId, label: LabelType) -> Self {
-        Self { event_id, offset_from, offset_to, timestamp, label }
+        Self { event_id, offset_from, offset_to, timestamp, label, horizons: Vec::new() }
+    }
+
+    /// offset_from and offset_to are set to cover all the horizon ranges.
+    pub fn new_multi(event_id: EventId, timestamp: Timestamp, horizons: Vec<OffsetRange>, label: LabelType) -> Self {
+        let offset_from = horizons.iter().map(|r| r.from).min().unwrap_or_default();
+        let offset_to = horizons.iter().map(|r| r.to).max().unwrap_or_default();
+        Self { event_id, offset_from, offset_to, timestamp, label, horizons }
+    }
+
+    /// The range for the horizon index, falling back to the whole range for single range labels.
+    pub fn horizon_range(&self, horizon: usize) -> OffsetRange {
+        self.horizons.get(horizon).copied().unwrap_or(OffsetRange { from: self.offset_from, to: self.offset_to })
+    }
+}
+
+/// Inclusive range of offsets of the events used for a label.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct OffsetRange {
+    pub from: OffsetId,
+    pub to: OffsetId,
+}
+
+/// How far after the labeled event a label looks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Horizon {
+    /// Number of events after the labeled one.
+    Events(OffsetId),
+    /// Millis after the labeled event's timestamp.
+    Millis(Timestamp),
+}
+
+impl Horizon {
+    /// True if an event at offset/ts after the base event is within the horizon.
+    pub fn contains(&self, base_offset: OffsetId, base_ts: Timestamp, offset: OffsetId, ts: Timestamp) -> bool {
+        match *self {
+            Horizon::Events(n) => offset > base_offset && offset - base_offset <= n,
+            Horizon::Millis(millis) => offset > base_offset && ts - base_ts <= millis,
+        }
+    }
+
+    fn len(&self) -> i64 {
+        match *self {
+            Horizon::Events(n) => n,
+            Horizon::Millis(millis) => millis,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match *self {
+            Horizon::Events(n) => format!("{}ev", n),
+            Horizon::Millis(millis) => format!("{}ms", millis),
+        }
+    }
+}
+
+/// Statistic of the mid price change from the labeled event over a horizon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum LabelStat {
+    Min,
+    Max,
+    Last,
+    Mean,
+    /// Sample std dev (n - 1), the same as features::std_dev. 0 for a single change.
+    Std,
+}
+
+impl LabelStat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LabelStat::Min => "min",
+            LabelStat::Max => "max",
+            LabelStat::Last => "last",
+            LabelStat::Mean => "mean",
+            LabelStat::Std => "std",
+        }
+    }
+
+    /// The statistic of the changes in the horizon, 0 if there are none.
+    pub fn compute(&self, changes: &[SeriesFloat]) -> ModelFloat {
+        if changes.is_empty() {
+            return 0.0;
+        }
+        let n = changes.len() as SeriesFloat;
+        let mean = changes.iter().sum::<SeriesFloat>() / n;
+        let value = match self {
+            LabelStat::Min => changes.iter().copied().fold(SeriesFloat::INFINITY, SeriesFloat::min),
+            LabelStat::Max => changes.iter().copied().fold(SeriesFloat::NEG_INFINITY, SeriesFloat::max),
+            // unwrap ok because checked not empty
+            LabelStat::Last => *changes.last().unwrap(),
+            LabelStat::Mean => mean,
+            LabelStat::Std => std_dev(changes),
+        };
+        value as ModelFloat
+    }
+}
+
+/// Which horizon and statistic fills a slot of LabelType.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct LabelSlot {
+    /// Index into LabelSpec::horizons.
+    pub horizon: usize,
+    pub stat: LabelStat,
+}
+
+/// Layout of LabelType: a set of horizons and the statistic in each slot. Unused trailing slots are 0.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct LabelSpec {
+    pub horizons: Vec<Horizon>,
+    pub slots: Vec<LabelSlot>,
+}
+
+impl Default for LabelSpec {
+    /// Min, max, last and mean over 1 and 5 minutes.
+    fn default() -> Self {
+        Self::grid(vec![Horizon::Millis(60_000), Horizon::Millis(300_000)], &[LabelStat::Min, LabelStat::Max, LabelStat::Last, LabelStat::Mean])
+    }
+}
+
+impl LabelSpec {
+    /// Every stat for every horizon, ordered by horizon then stat.
+    pub fn grid(horizons: Vec<Horizon>, stats: &[LabelStat]) -> Self {
+        let slots = (0..horizons.len()).flat_map(|horizon| stats.iter().map(move |&stat| LabelSlot { horizon, stat })).collect();
+        Self { horizons, slots }
+    }
+
+    pub fn width(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.slots.is_empty() {
+            bail!("Label spec has no slots");
+        }
+        if self.slots.len() > MODEL_OUTPUT_WIDTH {
+            bail!("Label spec has {} slots but MODEL_OUTPUT_WIDTH is {}", self.slots.len(), MODEL_OUTPUT_WIDTH);
+        }
+        if let Some(h) = self.horizons.iter().find(|h| h.len() <= 0) {
+            bail!("Label horizon {:?} is not positive", h);
+        }
+        if let Some(slot) = self.slots.iter().find(|s| s.horizon >= self.horizons.len()) {
+            bail!("Label slot {:?} refers to missing horizon, there are {}", slot, self.horizons.len());
+        }
+        if let Some(unused) = (0..self.horizons.len()).find(|i| !self.slots.iter().any(|s| s.horizon == *i)) {
+            bail!("Label horizon {:?} is not used by any slot", self.horizons[unused]);
+        }
+        Ok(())
+    }
+
+    /// Column names like "60000ms_max", for layouts and csv.
+    pub fn slot_names(&self) -> Vec<String> {
+        self.slots.iter().map(|s| format!("{}_{}", self.horizons[s.horizon].name(), s.stat.name())).collect()
+    }
+
+    /// Fills the label from the mid changes within each horizon, in horizon order.
+    pub fn make_label(&self, changes_by_horizon: &[Vec<SeriesFloat>]) -> LabelType {
+        let mut label = [0.0; MODEL_OUTPUT_WIDTH];
+        for (x, slot) in label.iter_mut().zip(&self.slots) {
+            *x = changes_by_horizon.get(slot.horizon).map_or(0.0, |changes| slot.stat.compute(changes));
+        }
+        label
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_of_changes() {
+        let changes = [1.0, -2.0, 4.0, 3.0];
+        assert_eq!(LabelStat::Min.compute(&changes), -2.0);
+        assert_eq!(LabelStat::Max.compute(&changes), 4.0);
+        assert_eq!(LabelStat::Last.compute(&changes), 3.0);
+        assert_eq!(LabelStat::Mean.compute(&changes), 1.5);
+        let all = [LabelStat::Min, LabelStat::Max, LabelStat::Last, LabelStat::Mean, LabelStat::Std];
+        assert!(all.iter().all(|stat| stat.compute(&[]) == 0.0));
+    }
+
+    #[test]
+    fn std_is_sample_like_features() {
+        let changes = [1.0, -2.0, 4.0, 3.0];
+        // Squared deviations from 1.5 sum to 21, over n - 1 = 3
+        assert!((LabelStat::Std.compute(&changes) - 7f32.sqrt()).abs() < 1e-6);
+        assert_eq!(LabelStat::Std.compute(&changes), std_dev(&changes));
+        assert_eq!(LabelStat::Std.compute(&[5.0]), 0.0);
     }
 }
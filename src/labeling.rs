@@ -0,0 +1,361 @@
+use anyhow::bail;
+
+use crate::*;
+use data_info::*;
+use features::{mid, std_dev};
+use label::{Horizon, LabelEvent};
+use quote::QuoteEvent;
+use series::SeriesEvent;
+use util::norm_cdf;
+
+/// How class outcomes are written into LabelType, starting at slot 0.
+#[derive(Debug, Default, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ClassEncoding {
+    /// 1 for the outcome class, 0 for the others.
+    #[default]
+    OneHot,
+    /// Probabilities that reflect how decisive the outcome was, see each labeler. The scale is in bps.
+    Probability { scale_bps: f32 },
+}
+
+impl ClassEncoding {
+    /// A scale of 0 would divide by 0 and make NaN labels.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match *self {
+            ClassEncoding::Probability { scale_bps } if !(scale_bps > 0.0 && scale_bps.is_finite()) => {
+                bail!("Probability scale_bps must be positive, got {}", scale_bps)
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Result of labeling one event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassLabel {
+    /// Index of the outcome class.
+    pub class: usize,
+    pub label: LabelType,
+    /// Offset of the event that decided the outcome: the barrier hit or the last event in the horizon.
+    pub hit_offset: OffsetId,
+    pub hit_timestamp: Timestamp,
+}
+
+/// Labels events in a window of quotes from the events after them.
+pub trait Labeler {
+    fn num_classes(&self) -> usize;
+    fn class_names(&self) -> &'static [&'static str];
+
+    /// Labels events[base] using the events before it for volatility and after it for the outcome.
+    /// None if the window doesn't yet extend past the horizon, or if no event after base is within it.
+    fn label(&self, events: &[QuoteEvent], base: usize) -> Option<ClassLabel>;
+
+    fn label_event(&self, events: &[QuoteEvent], base: usize) -> Option<LabelEvent> {
+        let result = self.label(events, base)?;
+        let event = &events[base];
+        Some(LabelEvent::new(event.event_id, event.timestamp(), event.offset + 1, result.hit_offset, result.label))
+    }
+}
+
+const BPS: f32 = 10_000.0;
+
+fn log_return_bps(from: &QuoteEvent, to: &QuoteEvent) -> f32 {
+    (mid(to) / mid(from)).ln() * BPS
+}
+
+/// Indices of the events after base within the horizon, None if no event after the horizon has arrived yet
+/// or none is within it, e.g. after a gap longer than the horizon.
+fn horizon_range(events: &[QuoteEvent], base: usize, horizon: &Horizon) -> Option<std::ops::Range<usize>> {
+    let base_ts = events[base].timestamp();
+    let within = |i: usize| horizon.contains(base as OffsetId, base_ts, i as OffsetId, events[i].timestamp());
+    let end = (base + 1..events.len()).find(|&i| !within(i))?;
+    (end > base + 1).then_some(base + 1..end)
+}
+
+/// Std dev of the mid log returns in bps over the window events before and including base.
+fn trailing_volatility_bps(events: &[QuoteEvent], base: usize, window: usize) -> f32 {
+    let from = (base + 1).saturating_sub(window + 1);
+    let returns: Vec<f32> = events[from..=base].windows(2).map(|w| log_return_bps(&w[0], &w[1])).collect();
+    std_dev(&returns)
+}
+
+fn encode(class: usize, probs: Option<&[f32]>) -> LabelType {
+    let mut label = [0.0; MODEL_OUTPUT_WIDTH];
+    match probs {
+        Some(probs) => label[..probs.len()].copy_from_slice(probs),
+        None => label[class] = 1.0,
+    }
+    label
+}
+
+// ---- Triple barrier ---- //
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarrierOutcome {
+    TakeProfit,
+    StopLoss,
+    Timeout,
+}
+
+impl BarrierOutcome {
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+/// Barriers are multiples of the trailing volatility of mid log returns, with a floor in bps.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TripleBarrierConfig {
+    pub take_profit: f32,
+    pub stop_loss: f32,
+    /// The vertical barrier.
+    pub horizon: Horizon,
+    pub volatility_window: usize,
+    pub min_barrier_bps: f32,
+    #[serde(default)]
+    pub encoding: ClassEncoding,
+}
+
+impl Default for TripleBarrierConfig {
+    fn default() -> Self {
+        Self {
+            take_profit: 2.0, stop_loss: 2.0, horizon: Horizon::Millis(5 * 60_000),
+            volatility_window: 100, min_barrier_bps: 1.0, encoding: ClassEncoding::OneHot,
+        }
+    }
+}
+
+/// Classes are take profit, stop loss and timeout, in that order.
+pub struct TripleBarrierLabeler {
+    pub config: TripleBarrierConfig,
+}
+
+impl TripleBarrierConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.encoding.validate()
+    }
+}
+
+impl TripleBarrierLabeler {
+    pub fn new(config: TripleBarrierConfig) -> anyhow::Result<Self> {
+        config.validate()?;
+        Ok(Self { config })
+    }
+
+    /// Upper and lower barriers in bps from the base mid.
+    pub fn barriers(&self, events: &[QuoteEvent], base: usize) -> (f32, f32) {
+        let vol = trailing_volatility_bps(events, base, self.config.volatility_window);
+        let min = self.config.min_barrier_bps;
+        ((self.config.take_profit * vol).max(min), -(self.config.stop_loss * vol).max(min))
+    }
+}
+
+impl Labeler for TripleBarrierLabeler {
+    fn num_classes(&self) -> usize {
+        3
+    }
+
+    fn class_names(&self) -> &'static [&'static str] {
+        &["take_profit", "stop_loss", "timeout"]
+    }
+
+    /// With probability encoding, barrier hits are one hot and timeouts are split between
+    /// the barriers by how close the final return got to each.
+    fn label(&self, events: &[QuoteEvent], base: usize) -> Option<ClassLabel> {
+        let range = horizon_range(events, base, &self.config.horizon)?;
+        let (upper, lower) = self.barriers(events, base);
+        let start = &events[base];
+        // The range isn't empty so last is always an event after start.
+        let mut last = start;
+        let mut outcome = BarrierOutcome::Timeout;
+        for event in &events[range] {
+            last = event;
+            let ret = log_return_bps(start, event);
+            if ret >= upper {
+                outcome = BarrierOutcome::TakeProfit;
+                break;
+            } else if ret <= lower {
+                outcome = BarrierOutcome::StopLoss;
+                break;
+            }
+        }
+        let probs = match (self.config.encoding, outcome) {
+            (ClassEncoding::Probability { scale_bps }, BarrierOutcome::Timeout) => {
+                let ret = log_return_bps(start, last);
+                let up = norm_cdf(((ret - (upper + lower) / 2.0) / scale_bps) as f64) as f32;
+                let decided = ((ret - lower) / (upper - lower)).clamp(0.0, 1.0);
+                // The further from the middle, the less of a timeout it was.
+                let timeout = 1.0 - (2.0 * decided - 1.0).abs();
+                Some(vec![(1.0 - timeout) * up, (1.0 - timeout) * (1.0 - up), timeout])
+            },
+            _ => None,
+        };
+        let class = outcome.index();
+        Some(ClassLabel { class, label: encode(class, probs.as_deref()), hit_offset: last.offset, hit_timestamp: last.timestamp() })
+    }
+}
+
+// ---- Up, flat, down ---- //
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Flat,
+    Down,
+}
+
+impl Direction {
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DirectionConfig {
+    pub horizon: Horizon,
+    /// Returns within plus or minus this are flat.
+    pub dead_zone_bps: f32,
+    #[serde(default)]
+    pub encoding: ClassEncoding,
+}
+
+impl Default for DirectionConfig {
+    fn default() -> Self {
+        Self { horizon: Horizon::Millis(60_000), dead_zone_bps: 1.0, encoding: ClassEncoding::OneHot }
+    }
+}
+
+/// Classes are up, flat and down by the mid return at the end of the horizon.
+pub struct DirectionLabeler {
+    pub config: DirectionConfig,
+}
+
+impl DirectionConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.encoding.validate()
+    }
+}
+
+impl DirectionLabeler {
+    pub fn new(config: DirectionConfig) -> anyhow::Result<Self> {
+        config.validate()?;
+        Ok(Self { config })
+    }
+}
+
+impl Labeler for DirectionLabeler {
+    fn num_classes(&self) -> usize {
+        3
+    }
+
+    fn class_names(&self) -> &'static [&'static str] {
+        &["up", "flat", "down"]
+    }
+
+    /// With probability encoding, the return is treated as normal with std dev scale_bps
+    /// and each class gets the probability of its side of the dead zone.
+    fn label(&self, events: &[QuoteEvent], base: usize) -> Option<ClassLabel> {
+        let range = horizon_range(events, base, &self.config.horizon)?;
+        let start = &events[base];
+        // unwrap ok because horizon_range is never empty
+        let last = events[range].last().unwrap();
+        let ret = log_return_bps(start, last);
+        let dead_zone = self.config.dead_zone_bps;
+        let direction = if ret > dead_zone {
+            Direction::Up
+        } else if ret < -dead_zone {
+            Direction::Down
+        } else {
+            Direction::Flat
+        };
+        let probs = match self.config.encoding {
+            ClassEncoding::Probability { scale_bps } => {
+                let up = 1.0 - norm_cdf(((dead_zone - ret) / scale_bps) as f64) as f32;
+                let down = norm_cdf(((-dead_zone - ret) / scale_bps) as f64) as f32;
+                Some(vec![up, (1.0 - up - down).max(0.0), down])
+            },
+            ClassEncoding::OneHot => None,
+        };
+        let class = direction.index();
+        Some(ClassLabel { class, label: encode(class, probs.as_deref()), hit_offset: last.offset, hit_timestamp: last.timestamp() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Quotes with the mids at the timestamps, offsets from 0.
+    fn events(quotes: &[(Timestamp, f32)]) -> Vec<QuoteEvent> {
+        quotes.iter().enumerate().map(|(i, &(t, m))| {
+            let mut event = QuoteEvent::at(t, m - 0.01, m + 0.01);
+            event.set_ids(i as EventId, i as OffsetId);
+            event
+        }).collect()
+    }
+
+    fn direction(horizon: Horizon) -> DirectionLabeler {
+        DirectionLabeler::new(DirectionConfig { horizon, ..DirectionConfig::default() }).unwrap()
+    }
+
+    #[test]
+    fn direction_classes() {
+        let labeler = direction(Horizon::Events(2));
+        let events = events(&[(0, 100.0), (1, 100.0), (2, 101.0), (3, 100.0), (4, 99.0), (5, 99.0)]);
+        assert_eq!(labeler.label(&events, 0).unwrap().class, Direction::Up.index());
+        assert_eq!(labeler.label(&events, 1).unwrap().class, Direction::Flat.index());
+        assert_eq!(labeler.label(&events, 2).unwrap().class, Direction::Down.index());
+        // The event after the horizon hasn't arrived yet.
+        assert!(labeler.label(&events, 3).is_none());
+
+        let label = labeler.label_event(&events, 0).unwrap();
+        assert_eq!((label.offset_from, label.offset_to), (1, 2));
+        assert_eq!(label.label[Direction::Up.index()], 1.0);
+    }
+
+    #[test]
+    fn empty_horizon_is_not_labeled() {
+        // The next event is after a gap longer than the horizon.
+        let events = events(&[(0, 100.0), (120_000, 101.0), (120_001, 101.0), (200_000, 101.0)]);
+        let labeler = direction(Horizon::Millis(60_000));
+        assert!(labeler.label(&events, 0).is_none());
+        assert!(labeler.label_event(&events, 0).is_none());
+        let barrier = TripleBarrierLabeler::new(TripleBarrierConfig { horizon: Horizon::Millis(60_000), ..TripleBarrierConfig::default() }).unwrap();
+        assert!(barrier.label_event(&events, 0).is_none());
+        let label = labeler.label_event(&events, 1).unwrap();
+        assert_eq!((label.offset_from, label.offset_to), (2, 2));
+    }
+
+    #[test]
+    fn triple_barrier_hits() {
+        let config = TripleBarrierConfig { horizon: Horizon::Events(3), volatility_window: 2, min_barrier_bps: 10.0, ..TripleBarrierConfig::default() };
+        let labeler = TripleBarrierLabeler::new(config).unwrap();
+        let events = events(&[(0, 100.0), (1, 100.0), (2, 100.0), (3, 100.2), (4, 100.0), (5, 99.0), (6, 99.0), (7, 99.0), (8, 99.0), (9, 99.0)]);
+        let hit = labeler.label(&events, 2).unwrap();
+        assert_eq!(hit.class, BarrierOutcome::TakeProfit.index());
+        assert_eq!(hit.hit_offset, 3);
+        assert_eq!(labeler.label(&events, 4).unwrap().class, BarrierOutcome::StopLoss.index());
+        let timeout = labeler.label(&events, 5).unwrap();
+        assert_eq!(timeout.class, BarrierOutcome::Timeout.index());
+        assert_eq!(timeout.hit_offset, 8);
+    }
+
+    #[test]
+    fn probability_scale_must_be_positive() {
+        for scale_bps in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            let encoding = ClassEncoding::Probability { scale_bps };
+            assert!(encoding.validate().is_err());
+            assert!(DirectionLabeler::new(DirectionConfig { encoding, ..DirectionConfig::default() }).is_err());
+            assert!(TripleBarrierLabeler::new(TripleBarrierConfig { encoding, ..TripleBarrierConfig::default() }).is_err());
+        }
+        let encoding = ClassEncoding::Probability { scale_bps: 2.0 };
+        let labeler = DirectionLabeler::new(DirectionConfig { horizon: Horizon::Events(1), encoding, ..DirectionConfig::default() }).unwrap();
+        let label = labeler.label(&events(&[(0, 100.0), (1, 100.05), (2, 100.0)]), 0).unwrap().label;
+        assert!(label.iter().all(|p| p.is_finite()));
+        assert!((label[..3].iter().sum::<f32>() - 1.0).abs() < 1e-5);
+    }
+}
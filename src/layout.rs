@@ -0,0 +1,209 @@
+use std::fmt::Write;
+use std::ops::Range;
+
+use anyhow::{bail, Context};
+
+use crate::*;
+use chrono_util::{ChronoFeatures, CHRONO_FEATURE_SET};
+use data_info::*;
+use features::FeatureSet;
+
+/// Describes a single column of a series item or chrono features.
+#[derive(Debug, Clone, serde::Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ColumnInfo {
+    pub name: String,
+    pub index: usize,
+    /// Expected (min, max) of the values.
+    pub range: (ModelFloat, ModelFloat),
+    pub description: String,
+}
+
+impl ColumnInfo {
+    fn new(name: impl Into<String>, index: usize, range: (ModelFloat, ModelFloat), description: impl Into<String>) -> Self {
+        Self { name: name.into(), index, range, description: description.into() }
+    }
+}
+
+/// Names the columns of SeriesItem and the entries of ChronoFeatures so inputs can be inspected by name.
+#[derive(Debug, Clone, serde::Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FeatureLayout {
+    pub series: Vec<ColumnInfo>,
+    pub chrono: Vec<ColumnInfo>,
+}
+
+impl Default for FeatureLayout {
+    /// Layout of InputRaw as produced by convert::series_to_input.
+    fn default() -> Self {
+        let mut series = vec![
+            ColumnInfo::new("bid_ratio", 0, (0.0, 1.0), "adjust(base_bid / bid)"),
+            ColumnInfo::new("ask_ratio", 1, (0.0, 1.0), "adjust(base_ask / ask)"),
+        ];
+        push_time_embedding(&mut series, FEATURES1_SIZE, TIME_EMBEDDING_SIZE);
+        Self { series, chrono: chrono_columns() }
+    }
+}
+
+impl FeatureLayout {
+    /// Layout of the items produced by FeatureSet::series_to_features.
+    pub fn for_features(set: &FeatureSet) -> Self {
+        let mut series: Vec<ColumnInfo> = set.features.iter().enumerate()
+            .map(|(i, f)| ColumnInfo::new(f.name(), i, f.range(), f.description()))
+            .collect();
+        push_time_embedding(&mut series, set.features.len(), set.time_embedding_size);
+        Self { series, chrono: chrono_columns() }
+    }
+
+    pub fn item_size(&self) -> usize {
+        self.series.len()
+    }
+
+    pub fn series_column(&self, name: &str) -> Option<&ColumnInfo> {
+        self.series.iter().find(|c| c.name == name)
+    }
+
+    pub fn chrono_column(&self, name: &str) -> Option<&ColumnInfo> {
+        self.chrono.iter().find(|c| c.name == name)
+    }
+
+    /// Indices of the named series columns, in the order given.
+    pub fn indices(&self, names: &[&str]) -> anyhow::Result<Vec<usize>> {
+        names.iter()
+            .map(|name| self.series_column(name).map(|c| c.index).with_context(|| format!("Unknown series column {}", name)))
+            .collect()
+    }
+
+    /// Values of the named columns for each row, in the order given.
+    pub fn select<'a>(&self, rows: impl IntoIterator<Item = &'a [ModelFloat]>, names: &[&str]) -> anyhow::Result<Vec<Vec<ModelFloat>>> {
+        let indices = self.indices(names)?;
+        rows.into_iter().map(|row| {
+            if row.len() != self.item_size() {
+                bail!("Row width {} does not match layout width {}", row.len(), self.item_size());
+            }
+            Ok(indices.iter().map(|&i| row[i]).collect())
+        }).collect()
+    }
+
+    pub fn select_series(&self, series: &Series, names: &[&str]) -> anyhow::Result<Vec<Vec<ModelFloat>>> {
+        self.select(series.iter().map(|item| item.as_slice()), names)
+    }
+
+    pub fn chrono_value(&self, chrono: &ChronoFeatures, name: &str) -> Option<ModelFloat> {
+        self.chrono_column(name).and_then(|c| chrono.get(c.index).copied())
+    }
+
+    /// Labeled table of the chrono features followed by the given rows of the series.
+    pub fn format_input(&self, input: &InputRaw, rows: Range<usize>) -> String {
+        let (chrono, series) = input;
+        let mut out = String::new();
+        for c in &self.chrono {
+            let _ = writeln!(out, "{:>16}: {:>10.6}", c.name, chrono[c.index]);
+        }
+        let rows = rows.start.min(SERIES1_SIZE)..rows.end.min(SERIES1_SIZE);
+        out.push_str(&self.format_rows(series[rows.clone()].iter().map(|item| item.as_slice()), rows.start));
+        out
+    }
+
+    /// Labeled table of the rows with a header of column names, numbering rows from first_row.
+    pub fn format_rows<'a>(&self, rows: impl IntoIterator<Item = &'a [ModelFloat]>, first_row: usize) -> String {
+        let width = self.series.iter().map(|c| c.name.len()).max().unwrap_or(0).max(10);
+        let mut out = format!("{:>6}", "row");
+        for c in &self.series {
+            let _ = write!(out, " {:>width$}", c.name);
+        }
+        out.push('\n');
+        for (i, row) in rows.into_iter().enumerate() {
+            let _ = write!(out, "{:>6}", first_row + i);
+            for value in row {
+                let _ = write!(out, " {:>width$.6}", value);
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn push_time_embedding(series: &mut Vec<ColumnInfo>, start: usize, size: usize) {
+    for k in 0..size {
+        let func = if k % 2 == 0 { "sin" } else { "cos" };
+        series.push(ColumnInfo::new(
+            format!("time_{}_{}", func, k / 2), start + k, (-1.0, 1.0),
+            format!("{}(base_time - time) at timescale {} of the time embedding", func, k / 2),
+        ));
+    }
+}
+
+fn chrono_columns() -> Vec<ColumnInfo> {
+    CHRONO_FEATURE_SET.iter().enumerate().map(|(i, f)| ColumnInfo::new(f.name(), i, (0.0, 1.0), f.description())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use chrono_util::CHRONO_FEATURES_SIZE;
+    use features::QuoteFeature;
+    use quote::QuoteEvent;
+    use synthetic::{SyntheticConfig, SyntheticMarket};
+
+    fn window() -> VecDeque<QuoteEvent> {
+        SyntheticMarket::new(SyntheticConfig::default()).take(SERIES1_SIZE).collect()
+    }
+
+    #[test]
+    fn default_layout_matches_input_raw() {
+        let layout = FeatureLayout::default();
+        assert_eq!(layout.item_size(), SERIES1_ITEM_SIZE);
+        assert_eq!(layout.chrono.len(), CHRONO_FEATURES_SIZE);
+        assert!(layout.series.iter().enumerate().all(|(i, c)| c.index == i));
+        assert!(layout.chrono.iter().enumerate().all(|(i, c)| c.index == i));
+        assert_eq!(layout.indices(&["ask_ratio", "time_sin_0", "time_cos_1"]).unwrap(), vec![1, 2, 5]);
+    }
+
+    #[test]
+    fn select_series_round_trips_columns() {
+        let layout = FeatureLayout::default();
+        let (chrono, series) = convert::series_to_input(&window()).unwrap();
+        let selected = layout.select_series(&series, &["bid_ratio", "time_cos_0"]).unwrap();
+        assert_eq!(selected.len(), SERIES1_SIZE);
+        for (row, item) in selected.iter().zip(series.iter()) {
+            assert_eq!(row, &vec![item[0], item[3]]);
+        }
+        for (i, c) in layout.chrono.iter().enumerate() {
+            assert_eq!(layout.chrono_value(&chrono, &c.name), Some(chrono[i]));
+        }
+        assert!(layout.select_series(&series, &["nope"]).is_err());
+    }
+
+    #[test]
+    fn for_features_names_feature_set_items() {
+        let set = FeatureSet { features: vec![QuoteFeature::SpreadBps, QuoteFeature::Gap, QuoteFeature::BidRatio], time_embedding_size: TIME_EMBEDDING_SIZE, volatility_window: 8, exchange: Default::default() };
+        let layout = FeatureLayout::for_features(&set);
+        assert_eq!(layout.item_size(), set.item_size());
+        assert_eq!(layout.series_column("time_sin_0").unwrap().index, 3);
+
+        let events = window();
+        let values = set.series_to_features(&events).unwrap();
+        let rows: Vec<&[ModelFloat]> = values.chunks_exact(set.item_size()).collect();
+        let selected = layout.select(rows.iter().copied(), &["bid_ratio", "spread_bps"]).unwrap();
+        for (row, item) in selected.iter().zip(&rows) {
+            assert_eq!(row, &vec![item[2], item[0]]);
+        }
+        // Rows of the wrong width are rejected.
+        assert!(layout.select([&values[..SERIES1_ITEM_SIZE]], &["gap"]).is_err());
+    }
+
+    #[test]
+    fn format_rows_numbers_from_first_row() {
+        let layout = FeatureLayout::default();
+        let row = [0.5; SERIES1_ITEM_SIZE];
+        let text = layout.format_rows([row.as_slice()], 7);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("bid_ratio") && lines[0].contains("time_cos_1"));
+        assert!(lines[1].trim_start().starts_with("7 "));
+        assert_eq!(lines[1].matches("0.500000").count(), SERIES1_ITEM_SIZE);
+    }
+}
@@ -1,5 +1,6 @@
 #![feature(trait_alias)]
 #![feature(iter_array_chunks)]
+#![allow(unused_features)]
 
 pub mod util;
 pub mod series;
@@ -7,10 +8,34 @@ pub mod convert;
 pub mod series_proc;
 pub mod paths;
 pub mod chrono_util;
+pub mod calendar;
+pub mod exchange;
+pub mod session;
 pub mod stored;
+pub mod dataset;
+pub mod encoding;
 pub mod quote;
 pub mod label;
+pub mod labeling;
 pub mod data_info;
+pub mod gap;
+pub mod quality;
+pub mod resample;
+pub mod features;
+pub mod layout;
+pub mod csv_io;
+pub mod eval;
+pub mod prediction;
+pub mod backtest;
+pub mod options;
+pub mod synthetic;
+pub mod macro_events;
+pub mod checkpoint;
+pub mod bus;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "schema")]
+pub mod schema;
 
 use chrono::{DateTime, NaiveDate, NaiveDateTime};
 use chrono_tz::Tz;
@@ -0,0 +1,237 @@
+use std::path::Path;
+
+use anyhow::{bail, Context};
+use chrono::{LocalResult, TimeZone};
+
+use crate::*;
+use chrono_util::*;
+use exchange::Exchange;
+
+/// Timezone of naive times in the calendar file, the US releases are scheduled in New York time.
+pub const MACRO_TIMEZONE: chrono_tz::Tz = chrono_tz::America::New_York;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum MacroKind {
+    Fomc,
+    Cpi,
+    Nfp,
+    Other(String),
+}
+
+/// As stored in the calendar file.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MacroEventSpec {
+    pub kind: MacroKind,
+    /// ISO 8601 with offset, or "YYYY-MM-DD HH:MM" in the timezone.
+    pub time: String,
+    /// Timezone the release is scheduled in, MACRO_TIMEZONE if missing. Not the timezone of the traded exchange.
+    #[serde(default)]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<String>"))]
+    pub timezone: Option<chrono_tz::Tz>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroEvent {
+    pub kind: MacroKind,
+    pub timestamp: Timestamp,
+}
+
+/// Scheduled macro releases sorted by time.
+#[derive(Debug, Default, Clone)]
+pub struct MacroCalendar {
+    events: Vec<MacroEvent>,
+}
+
+impl MacroCalendar {
+    pub fn new(mut events: Vec<MacroEvent>) -> Self {
+        events.sort_by_key(|e| e.timestamp);
+        Self { events }
+    }
+
+    /// Loads the json array of MacroEventSpec from the data directory.
+    pub fn load_default() -> anyhow::Result<Self> {
+        Self::load(&paths::macro_calendar_path()?)
+    }
+
+    /// Local times are in each spec's timezone, so the same file works for streams on any exchange.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Could not read macro calendar {:?}", path))?;
+        let specs: Vec<MacroEventSpec> = serde_json::from_str(&text).with_context(|| format!("Invalid macro calendar {:?}", path))?;
+        Self::from_specs(specs)
+    }
+
+    pub fn from_specs(specs: Vec<MacroEventSpec>) -> anyhow::Result<Self> {
+        let events = specs.into_iter()
+            .map(|spec| Ok(MacroEvent { timestamp: parse_event_time(&spec.time, spec.timezone.unwrap_or(MACRO_TIMEZONE))?, kind: spec.kind }))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self::new(events))
+    }
+
+    pub fn events(&self) -> &[MacroEvent] {
+        &self.events
+    }
+
+    /// First event of the kind (any kind if None) strictly after the timestamp.
+    pub fn next_event(&self, kind: Option<&MacroKind>, ts: Timestamp) -> Option<&MacroEvent> {
+        let from = self.events.partition_point(|e| e.timestamp <= ts);
+        self.events[from..].iter().find(|e| kind.is_none_or(|k| *k == e.kind))
+    }
+
+    /// Last event of the kind (any kind if None) at or before the timestamp.
+    pub fn prev_event(&self, kind: Option<&MacroKind>, ts: Timestamp) -> Option<&MacroEvent> {
+        let to = self.events.partition_point(|e| e.timestamp <= ts);
+        self.events[..to].iter().rev().find(|e| kind.is_none_or(|k| *k == e.kind))
+    }
+
+    /// True if an event of the kind is within the given minutes before or after the timestamp.
+    pub fn is_within(&self, kind: Option<&MacroKind>, ts: Timestamp, minutes: i64) -> bool {
+        let window = minutes * 60_000;
+        self.next_event(kind, ts).is_some_and(|e| e.timestamp - ts <= window)
+            || self.prev_event(kind, ts).is_some_and(|e| ts - e.timestamp <= window)
+    }
+}
+
+fn parse_event_time(s: &str, timezone: chrono_tz::Tz) -> anyhow::Result<Timestamp> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Ok(dt.timestamp_millis());
+    }
+    let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").with_context(|| format!("Invalid macro event time {}", s))?;
+    match timezone.from_local_datetime(&naive) {
+        LocalResult::Single(dt) => Ok(dt.timestamp_millis()),
+        LocalResult::Ambiguous(..) => bail!("Ambiguous macro event time {} in {}", s, timezone),
+        LocalResult::None => bail!("Macro event time {} does not exist in {}", s, timezone),
+    }
+}
+
+/// Time to the next and since the last event of each kind, appended to the chrono features.
+/// Each is encoded as exp(-minutes / scale_minutes), so 1 at the event and approaching 0 far away.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MacroFeatures {
+    pub kinds: Vec<MacroKind>,
+    pub scale_minutes: f32,
+}
+
+impl Default for MacroFeatures {
+    fn default() -> Self {
+        Self { kinds: vec![MacroKind::Fomc, MacroKind::Cpi, MacroKind::Nfp], scale_minutes: 24.0 * 60.0 }
+    }
+}
+
+impl MacroFeatures {
+    pub fn size(&self) -> usize {
+        2 * self.kinds.len()
+    }
+
+    /// For each kind: time to next event, then time since last event.
+    pub fn features(&self, calendar: &MacroCalendar, ts: Timestamp) -> Vec<ModelFloat> {
+        let encode = |delta: Option<Timestamp>| delta.map_or(0.0, |d| (-(d as f32 / 60_000.0) / self.scale_minutes).exp());
+        self.kinds.iter().flat_map(|kind| {
+            let to = calendar.next_event(Some(kind), ts).map(|e| e.timestamp - ts);
+            let since = calendar.prev_event(Some(kind), ts).map(|e| ts - e.timestamp);
+            [encode(to), encode(since)]
+        }).collect()
+    }
+
+    /// make_chrono_features_on the stream's exchange followed by the macro features.
+    pub fn chrono_features_with_macro(&self, calendar: &MacroCalendar, exchange: &Exchange, ts: Timestamp) -> Vec<ModelFloat> {
+        let mut result = make_chrono_features_on(exchange, ts).to_vec();
+        result.extend(self.features(calendar, ts));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-01-31 14:00 New York
+    const FOMC: Timestamp = 1_706_727_600_000;
+    const HOUR: Timestamp = 3_600_000;
+
+    fn calendar() -> MacroCalendar {
+        MacroCalendar::new(vec![
+            MacroEvent { kind: MacroKind::Nfp, timestamp: FOMC + 48 * HOUR },
+            MacroEvent { kind: MacroKind::Fomc, timestamp: FOMC },
+            MacroEvent { kind: MacroKind::Cpi, timestamp: FOMC - 24 * HOUR },
+        ])
+    }
+
+    #[test]
+    fn local_times_are_in_release_timezone() {
+        // 14:00 is 19:00 UTC in New York and 13:00 UTC in Frankfurt
+        assert_eq!(parse_event_time("2024-01-31 14:00", MACRO_TIMEZONE).unwrap(), FOMC);
+        assert_eq!(parse_event_time("2024-01-31 14:00", chrono_tz::Europe::Berlin).unwrap(), 1_706_706_000_000);
+        assert_eq!(parse_event_time("2024-01-31T19:00:00Z", chrono_tz::Europe::Berlin).unwrap(), FOMC);
+
+        let specs: Vec<MacroEventSpec> = serde_json::from_str(r#"[
+            {"kind": "fomc", "time": "2024-01-31 14:00"},
+            {"kind": {"other": "ecb"}, "time": "2024-01-25 14:15", "timezone": "Europe/Berlin"}
+        ]"#).unwrap();
+        let calendar = MacroCalendar::from_specs(specs).unwrap();
+        assert_eq!(calendar.events()[0], MacroEvent { kind: MacroKind::Other("ecb".to_string()), timestamp: 1_706_188_500_000 });
+        assert_eq!(calendar.events()[1], MacroEvent { kind: MacroKind::Fomc, timestamp: FOMC });
+    }
+
+    #[test]
+    fn skipped_and_ambiguous_times_are_distinguished() {
+        let skipped = parse_event_time("2024-03-10 02:30", MACRO_TIMEZONE).unwrap_err().to_string();
+        assert!(skipped.contains("does not exist"), "{}", skipped);
+        let ambiguous = parse_event_time("2024-11-03 01:30", MACRO_TIMEZONE).unwrap_err().to_string();
+        assert!(ambiguous.contains("Ambiguous"), "{}", ambiguous);
+    }
+
+    #[test]
+    fn next_is_strict_and_prev_inclusive() {
+        let calendar = calendar();
+        assert_eq!(calendar.prev_event(None, FOMC).unwrap().kind, MacroKind::Fomc);
+        assert_eq!(calendar.next_event(None, FOMC).unwrap().kind, MacroKind::Nfp);
+        assert_eq!(calendar.next_event(None, FOMC - 1).unwrap().kind, MacroKind::Fomc);
+        assert_eq!(calendar.prev_event(None, FOMC - 1).unwrap().kind, MacroKind::Cpi);
+        assert!(calendar.prev_event(None, FOMC - 24 * HOUR - 1).is_none());
+        assert!(calendar.next_event(None, FOMC + 48 * HOUR).is_none());
+    }
+
+    #[test]
+    fn filters_by_kind() {
+        let calendar = calendar();
+        assert_eq!(calendar.next_event(Some(&MacroKind::Nfp), FOMC - 30 * HOUR).unwrap().timestamp, FOMC + 48 * HOUR);
+        assert_eq!(calendar.prev_event(Some(&MacroKind::Cpi), FOMC + 48 * HOUR).unwrap().timestamp, FOMC - 24 * HOUR);
+        assert!(calendar.next_event(Some(&MacroKind::Cpi), FOMC).is_none());
+        assert!(calendar.prev_event(Some(&MacroKind::Other("ecb".to_string())), FOMC + 48 * HOUR).is_none());
+    }
+
+    #[test]
+    fn within_window_on_both_sides() {
+        let calendar = calendar();
+        let fomc = Some(&MacroKind::Fomc);
+        assert!(calendar.is_within(fomc, FOMC - 30 * 60_000, 30));
+        assert!(!calendar.is_within(fomc, FOMC - 30 * 60_000 - 1, 30));
+        assert!(calendar.is_within(fomc, FOMC, 0));
+        assert!(calendar.is_within(fomc, FOMC + 30 * 60_000, 30));
+        assert!(!calendar.is_within(fomc, FOMC + 30 * 60_000 + 1, 30));
+        // Another kind's event close by doesn't count.
+        assert!(!calendar.is_within(Some(&MacroKind::Cpi), FOMC, 60));
+        assert!(calendar.is_within(None, FOMC - 24 * HOUR + 1, 1));
+    }
+
+    #[test]
+    fn exp_encoding() {
+        let calendar = calendar();
+        let features = MacroFeatures { kinds: vec![MacroKind::Fomc, MacroKind::Other("ecb".to_string())], scale_minutes: 60.0 };
+        assert_eq!(features.size(), 4);
+        // At the event the time since is 0 and there is no next one, and there are no ecb events at all.
+        assert_eq!(features.features(&calendar, FOMC), vec![0.0, 1.0, 0.0, 0.0]);
+        let values = features.features(&calendar, FOMC - HOUR);
+        assert!((values[0] - (-1f32).exp()).abs() < 1e-6);
+        assert_eq!(values[1], 0.0);
+
+        let xetra = Exchange::xetra();
+        let with_macro = features.chrono_features_with_macro(&calendar, &xetra, FOMC);
+        assert_eq!(with_macro[..CHRONO_FEATURES_SIZE], make_chrono_features_on(&xetra, FOMC));
+        assert_eq!(with_macro[CHRONO_FEATURES_SIZE..], [0.0, 1.0, 0.0, 0.0]);
+    }
+}
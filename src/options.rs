@@ -0,0 +1,410 @@
+use std::fmt;
+
+use anyhow::{bail, Context};
+use chrono::Datelike;
+
+use crate::*;
+use chrono_util::*;
+use exchange::{default_exchange, Exchange};
+use series::*;
+use series_proc::BaseValues;
+use util::{norm_cdf, norm_pdf};
+
+pub const MILLIS_PER_YEAR: f64 = 365.0 * 24.0 * 60.0 * 60.0 * 1000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum OptionKind {
+    Call,
+    Put,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct OptionContract {
+    pub underlying: String,
+    #[serde(with = "naive_date_string")]
+    #[cfg_attr(feature = "schema", schemars(with = "NaiveDate"))]
+    pub expiry: NaiveDate,
+    pub strike: f64,
+    pub kind: OptionKind,
+}
+
+impl OptionContract {
+    /// Parses OCC symbols both with the root padded to 6 characters ("SPY   240621C00500000") and without ("SPY240621C00500000").
+    pub fn parse_occ(symbol: &str) -> anyhow::Result<Self> {
+        let s = symbol.trim();
+        if s.len() < 16 || !s.is_ascii() {
+            bail!("Invalid OCC symbol {}", symbol);
+        }
+        let (root, rest) = s.split_at(s.len() - 15);
+        let underlying = root.trim_end().to_string();
+        if underlying.is_empty() || underlying.len() > 6 {
+            bail!("Invalid underlying in OCC symbol {}", symbol);
+        }
+        let expiry = NaiveDate::parse_from_str(&rest[0..6], "%y%m%d").with_context(|| format!("Invalid expiry in OCC symbol {}", symbol))?;
+        let kind = match &rest[6..7] {
+            "C" => OptionKind::Call,
+            "P" => OptionKind::Put,
+            _ => bail!("Invalid call/put in OCC symbol {}", symbol),
+        };
+        let strike_thousandths: u32 = rest[7..15].parse().with_context(|| format!("Invalid strike in OCC symbol {}", symbol))?;
+        Ok(Self { underlying, expiry, strike: strike_thousandths as f64 / 1000.0, kind })
+    }
+
+    /// Standard 21 character OCC symbol with the root padded to 6 characters.
+    pub fn to_occ(&self) -> anyhow::Result<String> {
+        Ok(format!("{:<6}{}", self.underlying, self.occ_suffix()?))
+    }
+
+    /// OCC symbol without padding, as used by tradier.
+    pub fn to_occ_compact(&self) -> anyhow::Result<String> {
+        Ok(format!("{}{}", self.underlying, self.occ_suffix()?))
+    }
+
+    /// The strike is 8 digits of thousandths, so it has to be in 0..=99999.999.
+    fn occ_suffix(&self) -> anyhow::Result<String> {
+        let kind = match self.kind { OptionKind::Call => 'C', OptionKind::Put => 'P' };
+        let strike_thousandths = (self.strike * 1000.0).round();
+        if !(0.0..=99_999_999.0).contains(&strike_thousandths) {
+            bail!("Strike {} does not fit an OCC symbol", self.strike);
+        }
+        Ok(format!("{}{}{:08}", self.expiry.format("%y%m%d"), kind, strike_thousandths as u32))
+    }
+
+    /// Expiration at the regular close of the default exchange on the expiry date.
+    pub fn expiration(&self) -> MarketTimestamp {
+        self.expiration_on(default_exchange())
+    }
+
+    /// Expiration at the exchange's regular close on the expiry date, early close aware.
+    pub fn expiration_on(&self, exchange: &Exchange) -> DateTime<chrono_tz::Tz> {
+        exchange.to_local(exchange.close_timestamp(self.expiry))
+    }
+
+    /// Years from the timestamp to expiration on the default exchange, 0 if already expired.
+    pub fn time_to_expiry(&self, ts: Timestamp) -> f64 {
+        self.time_to_expiry_on(default_exchange(), ts)
+    }
+
+    pub fn time_to_expiry_on(&self, exchange: &Exchange, ts: Timestamp) -> f64 {
+        ((self.expiration_on(exchange).timestamp_millis() - ts).max(0) as f64) / MILLIS_PER_YEAR
+    }
+
+    pub fn is_monthly(&self) -> bool {
+        self.expiry.weekday() == chrono::Weekday::Fri && (15..=21).contains(&self.expiry.day())
+    }
+}
+
+impl fmt::Display for OptionContract {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_occ_compact().map_err(|_| fmt::Error)?)
+    }
+}
+
+mod naive_date_string {
+    use super::*;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(date: &NaiveDate, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&date.format("%Y-%m-%d").to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<NaiveDate, D::Error> {
+        let s = String::deserialize(d)?;
+        NaiveDate::parse_from_str(&s, "%Y-%m-%d").map_err(serde::de::Error::custom)
+    }
+}
+
+// ---- Black-Scholes ---- //
+
+/// Inputs to Black-Scholes. Rates are continuously compounded annual, time is in years.
+#[derive(Debug, Clone, Copy)]
+pub struct BsParams {
+    pub spot: f64,
+    pub strike: f64,
+    pub time: f64,
+    pub rate: f64,
+    pub dividend: f64,
+    pub kind: OptionKind,
+}
+
+#[derive(Debug, Default, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Greeks {
+    pub delta: f64,
+    pub gamma: f64,
+    /// Per 1.00 change in volatility.
+    pub vega: f64,
+    /// Per year.
+    pub theta: f64,
+    /// Per 1.00 change in rate.
+    pub rho: f64,
+}
+
+impl BsParams {
+    fn d1_d2(&self, vol: f64) -> (f64, f64) {
+        let sqrt_t = self.time.sqrt();
+        let d1 = ((self.spot / self.strike).ln() + (self.rate - self.dividend + 0.5 * vol * vol) * self.time) / (vol * sqrt_t);
+        (d1, d1 - vol * sqrt_t)
+    }
+
+    fn intrinsic(&self) -> f64 {
+        let df_q = (-self.dividend * self.time).exp();
+        let df_r = (-self.rate * self.time).exp();
+        match self.kind {
+            OptionKind::Call => (self.spot * df_q - self.strike * df_r).max(0.0),
+            OptionKind::Put => (self.strike * df_r - self.spot * df_q).max(0.0),
+        }
+    }
+
+    pub fn price(&self, vol: f64) -> f64 {
+        if self.time <= 0.0 || vol <= 0.0 {
+            return self.intrinsic();
+        }
+        let (d1, d2) = self.d1_d2(vol);
+        let df_q = (-self.dividend * self.time).exp();
+        let df_r = (-self.rate * self.time).exp();
+        match self.kind {
+            OptionKind::Call => self.spot * df_q * norm_cdf(d1) - self.strike * df_r * norm_cdf(d2),
+            OptionKind::Put => self.strike * df_r * norm_cdf(-d2) - self.spot * df_q * norm_cdf(-d1),
+        }
+    }
+
+    pub fn greeks(&self, vol: f64) -> Greeks {
+        if self.time <= 0.0 || vol <= 0.0 {
+            return Greeks::default();
+        }
+        let (d1, d2) = self.d1_d2(vol);
+        let sqrt_t = self.time.sqrt();
+        let df_q = (-self.dividend * self.time).exp();
+        let df_r = (-self.rate * self.time).exp();
+        let gamma = df_q * norm_pdf(d1) / (self.spot * vol * sqrt_t);
+        let vega = self.spot * df_q * norm_pdf(d1) * sqrt_t;
+        let decay = -self.spot * df_q * norm_pdf(d1) * vol / (2.0 * sqrt_t);
+        match self.kind {
+            OptionKind::Call => Greeks {
+                delta: df_q * norm_cdf(d1), gamma, vega,
+                theta: decay - self.rate * self.strike * df_r * norm_cdf(d2) + self.dividend * self.spot * df_q * norm_cdf(d1),
+                rho: self.strike * self.time * df_r * norm_cdf(d2),
+            },
+            OptionKind::Put => Greeks {
+                delta: -df_q * norm_cdf(-d1), gamma, vega,
+                theta: decay + self.rate * self.strike * df_r * norm_cdf(-d2) - self.dividend * self.spot * df_q * norm_cdf(-d1),
+                rho: -self.strike * self.time * df_r * norm_cdf(-d2),
+            },
+        }
+    }
+
+    /// Newton's method safeguarded by bisection. The bracket is widened until it contains the price,
+    /// failing for prices that need a volatility above 1024.
+    pub fn implied_vol(&self, price: f64) -> anyhow::Result<f64> {
+        const TOLERANCE: f64 = 1e-8;
+        const MAX_ITERATIONS: usize = 200;
+        const MAX_VOL: f64 = 1024.0;
+        let upper_bound = match self.kind {
+            OptionKind::Call => self.spot * (-self.dividend * self.time).exp(),
+            OptionKind::Put => self.strike * (-self.rate * self.time).exp(),
+        };
+        if self.time <= 0.0 {
+            bail!("Cannot imply volatility of an expired option");
+        }
+        if price <= self.intrinsic() || price >= upper_bound {
+            bail!("Price {} is outside the no-arbitrage bounds ({}, {})", price, self.intrinsic(), upper_bound);
+        }
+
+        // The price increases with vol towards upper_bound, so widen until it's bracketed.
+        let (mut lo, mut hi) = (1e-6, 4.0);
+        while self.price(hi) < price {
+            if hi >= MAX_VOL {
+                bail!("Price {} needs a volatility above {}", price, MAX_VOL);
+            }
+            lo = hi;
+            hi *= 2.0;
+        }
+        let mut vol = if lo < 0.2 && 0.2 < hi { 0.2 } else { (lo + hi) / 2.0 };
+        for _ in 0..MAX_ITERATIONS {
+            let diff = self.price(vol) - price;
+            if diff.abs() < TOLERANCE {
+                return Ok(vol);
+            }
+            if diff > 0.0 { hi = vol } else { lo = vol }
+            let vega = self.greeks(vol).vega;
+            let newton = vol - diff / vega;
+            vol = if vega > 1e-12 && newton > lo && newton < hi { newton } else { (lo + hi) / 2.0 };
+            if hi - lo < TOLERANCE {
+                return Ok(vol);
+            }
+        }
+        bail!("Implied volatility did not converge for price {}", price)
+    }
+}
+
+// ---- Option quotes ---- //
+
+/// Published to series by ingest for option streams.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct OptionQuoteEvent {
+    #[serde(default)]
+    pub event_id: EventId,
+    #[serde(default)]
+    pub offset: OffsetId,
+    pub symbol: String,
+    pub bid: f32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[cfg_attr(feature = "schema", schemars(with = "schema::StringOrNumber"))]
+    pub biddate: Timestamp,
+    pub ask: f32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[cfg_attr(feature = "schema", schemars(with = "schema::StringOrNumber"))]
+    pub askdate: Timestamp,
+    #[serde(default)]
+    pub bidsz: SeriesFloat,
+    #[serde(default)]
+    pub asksz: SeriesFloat,
+}
+
+impl OptionQuoteEvent {
+    pub fn contract(&self) -> anyhow::Result<OptionContract> {
+        OptionContract::parse_occ(&self.symbol)
+    }
+
+    pub fn mid(&self) -> f64 {
+        (self.bid as f64 + self.ask as f64) / 2.0
+    }
+
+    fn date(&self, exchange: &Exchange) -> NaiveDate {
+        exchange.session_date(self.timestamp())
+    }
+}
+
+impl SeriesEvent for OptionQuoteEvent {
+    type BV = OptionQuoteValues;
+
+    fn set_ids(&mut self, event_id: EventId, offset: OffsetId) {
+        self.event_id = event_id;
+        self.offset = offset;
+    }
+
+    fn timestamp(&self) -> Timestamp {
+        // Same as QuoteEvent so gaps, sessions and ordering agree across event types
+        self.biddate
+    }
+
+    fn validity(&self, base: &Self::BV, exchange: &Exchange) -> Validity {
+        base.validity(self, exchange)
+    }
+}
+
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct OptionQuoteValues {
+    pub date_or_0: NaiveDate,
+    pub bid: SeriesFloat,
+    pub ask: SeriesFloat,
+}
+
+impl BaseValues<OptionQuoteEvent> for OptionQuoteValues {
+    fn convert_from(event: &OptionQuoteEvent, exchange: &Exchange) -> Self {
+        Self { date_or_0: event.date(exchange), bid: event.bid, ask: event.ask }
+    }
+
+    /// Which sessions are accepted is checked by the handler's SessionFilter.
+    fn validity(&self, event: &OptionQuoteEvent, exchange: &Exchange) -> Validity {
+        if !same_date(event.date(exchange), self.date_or_0) {
+            Validity::CauseReset
+        } else {
+            Validity::Valid
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(kind: OptionKind, strike: f64, time: f64) -> BsParams {
+        BsParams { spot: 100.0, strike, time, rate: 0.03, dividend: 0.01, kind }
+    }
+
+    #[test]
+    fn implied_vol_recovers_vol() {
+        for kind in [OptionKind::Call, OptionKind::Put] {
+            for (strike, time) in [(100.0, 0.5), (80.0, 1.0), (130.0, 0.1)] {
+                for vol in [0.05, 0.2, 1.5, 9.0, 15.0, 40.0] {
+                    let p = params(kind, strike, time);
+                    let price = p.price(vol);
+                    let upper = match kind { OptionKind::Call => p.spot * (-p.dividend * p.time).exp(), OptionKind::Put => p.strike * (-p.rate * p.time).exp() };
+                    // Too close to the bounds to be distinguishable in f64
+                    if price <= p.intrinsic() + 1e-9 || price >= upper - 1e-9 {
+                        continue;
+                    }
+                    let implied = p.implied_vol(price).unwrap();
+                    assert!((p.price(implied) - price).abs() < 1e-6, "{:?} {} {} {}: {}", kind, strike, time, vol, implied);
+                    assert!((implied - vol).abs() / vol < 1e-3, "{:?} {} {} {}: {}", kind, strike, time, vol, implied);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn implied_vol_errors_outside_bounds() {
+        let p = params(OptionKind::Call, 300.0, 1.0 / 365.0);
+        let upper = p.spot * (-p.dividend * p.time).exp();
+        assert!(p.implied_vol(upper).is_err());
+        assert!(p.implied_vol(0.0).is_err());
+        // Within the bounds, but about 30 seconds from expiry it would need a vol above the maximum.
+        let p = params(OptionKind::Call, 300.0, 1e-6);
+        assert!(p.price(1024.0) < 50.0);
+        assert!(p.implied_vol(50.0).is_err());
+        assert!(params(OptionKind::Call, 100.0, 0.0).implied_vol(1.0).is_err());
+    }
+
+    #[test]
+    fn put_call_parity() {
+        let (call, put) = (params(OptionKind::Call, 105.0, 0.75), params(OptionKind::Put, 105.0, 0.75));
+        let forward = call.spot * (-call.dividend * call.time).exp() - call.strike * (-call.rate * call.time).exp();
+        assert!((call.price(0.3) - put.price(0.3) - forward).abs() < 1e-9);
+        assert!((call.greeks(0.3).delta - put.greeks(0.3).delta - (-call.dividend * call.time).exp()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn occ_symbols_round_trip() {
+        let contract = OptionContract::parse_occ("SPY   240621C00500000").unwrap();
+        assert_eq!(contract.underlying, "SPY");
+        assert_eq!(contract.expiry, NaiveDate::from_ymd_opt(2024, 6, 21).unwrap());
+        assert_eq!((contract.strike, contract.kind), (500.0, OptionKind::Call));
+        assert!(contract.is_monthly());
+        assert_eq!(contract.to_occ().unwrap(), "SPY   240621C00500000");
+        assert_eq!(OptionContract::parse_occ("SPY240621P00500500").unwrap().strike, 500.5);
+        assert_eq!(contract.to_occ_compact().unwrap(), "SPY240621C00500000");
+        assert!(OptionContract::parse_occ("SPY240621X00500000").is_err());
+        let max = OptionContract { strike: 99_999.999, ..contract.clone() };
+        assert_eq!(max.to_occ_compact().unwrap(), "SPY240621C99999999");
+        assert!(OptionContract { strike: 100_000.0, ..contract.clone() }.to_occ().is_err());
+        assert!(OptionContract { strike: -1.0, ..contract.clone() }.to_occ().is_err());
+        assert!(OptionContract { strike: f64::NAN, ..contract }.to_occ().is_err());
+    }
+
+    #[test]
+    fn expiration_at_exchange_close() {
+        let contract = OptionContract::parse_occ("SPY240621C00500000").unwrap();
+        // 16:00 New York is 20:00 UTC in summer
+        assert_eq!(contract.expiration().timestamp_millis(), 1_719_000_000_000);
+        let day_after_thanksgiving = OptionContract::parse_occ("SPY241129C00500000").unwrap();
+        assert_eq!(day_after_thanksgiving.expiration().format("%H:%M").to_string(), "13:00");
+        let xetra = contract.expiration_on(&Exchange::xetra());
+        assert_eq!(xetra.format("%H:%M %Z").to_string(), "17:30 CEST");
+        assert_eq!(contract.time_to_expiry(contract.expiration().timestamp_millis() + 1), 0.0);
+        let day = 24.0 * 3_600_000.0 / MILLIS_PER_YEAR;
+        assert!((contract.time_to_expiry_on(&Exchange::xetra(), xetra.timestamp_millis() - 86_400_000) - day).abs() < 1e-12);
+    }
+
+    #[test]
+    fn option_quote_timestamp_is_bid_time() {
+        let event: OptionQuoteEvent = serde_json::from_str(r#"{"symbol": "SPY240621C00500000", "bid": 1.0, "biddate": "1000", "ask": 1.1, "askdate": 2000}"#).unwrap();
+        let quote = quote::QuoteEvent { askdate: 2_000, ..quote::QuoteEvent::at(1_000, 1.0, 1.1) };
+        assert_eq!(event.timestamp(), quote.timestamp());
+    }
+}
@@ -11,3 +11,19 @@ pub fn data_dir() -> anyhow::Result<PathBuf> {
     let h = home::home_dir().with_context(|| "Could not get user home directory")?;
     Ok(h.join("data"))
 }
+
+pub fn macro_calendar_path() -> anyhow::Result<PathBuf> {
+    Ok(data_dir()?.join("calendar").join("macro_events.json"))
+}
+
+pub fn checkpoint_dir() -> anyhow::Result<PathBuf> {
+    let path = data_dir()?.join("checkpoints");
+    std::fs::create_dir_all(&path)?;
+    Ok(path)
+}
+
+pub fn dataset_dir() -> anyhow::Result<PathBuf> {
+    let path = data_dir()?.join("datasets");
+    std::fs::create_dir_all(&path)?;
+    Ok(path)
+}
@@ -0,0 +1,217 @@
+use anyhow::bail;
+
+use crate::*;
+use data_info::*;
+use util::{norm_cdf, norm_inv};
+
+/// What the MODEL_OUTPUT_WIDTH output slots mean for a model.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum OutputHead {
+    /// Each slot is the value at the given quantile level of forward return. Levels must be increasing.
+    Quantiles([f32; MODEL_OUTPUT_WIDTH]),
+    /// Slots are (mean, std) pairs of forward return, one for each horizon in millis.
+    MeanStd([Timestamp; MODEL_OUTPUT_WIDTH / 2]),
+}
+
+impl OutputHead {
+    pub fn decode(&self, output: &LabelType) -> anyhow::Result<PredictionView> {
+        Ok(match self {
+            OutputHead::Quantiles(levels) => PredictionView::Quantiles(QuantilePrediction::new(levels, output)?),
+            OutputHead::MeanStd(horizons) => {
+                let mut preds = Vec::with_capacity(horizons.len());
+                for (horizon, pair) in horizons.iter().zip(output.chunks_exact(2)) {
+                    preds.push((*horizon, GaussianPrediction::new(pair[0] as f64, pair[1] as f64)?));
+                }
+                PredictionView::MeanStd(preds)
+            },
+        })
+    }
+}
+
+/// Typed view of a model output according to its OutputHead.
+#[derive(Debug, Clone)]
+pub enum PredictionView {
+    Quantiles(QuantilePrediction),
+    /// One prediction per horizon in millis.
+    MeanStd(Vec<(Timestamp, GaussianPrediction)>),
+}
+
+/// A univariate predictive distribution.
+pub trait Prediction {
+    fn cdf(&self, x: f64) -> f64;
+    fn quantile(&self, p: f64) -> f64;
+
+    /// The median.
+    fn point(&self) -> f64 {
+        self.quantile(0.5)
+    }
+
+    /// Central interval containing the given probability mass.
+    fn interval(&self, coverage: f64) -> (f64, f64) {
+        let tail = (1.0 - coverage.clamp(0.0, 1.0)) / 2.0;
+        (self.quantile(tail), self.quantile(1.0 - tail))
+    }
+
+    fn prob_exceeds(&self, threshold: f64) -> f64 {
+        1.0 - self.cdf(threshold)
+    }
+}
+
+/// Piecewise linear distribution through the (value, level) points, extrapolated linearly past the ends.
+#[derive(Debug, Clone)]
+pub struct QuantilePrediction {
+    levels: Vec<f64>,
+    values: Vec<f64>,
+}
+
+impl QuantilePrediction {
+    /// Fails if levels are not strictly increasing within (0, 1) or values are decreasing.
+    pub fn new(levels: &[f32], values: &[f32]) -> anyhow::Result<Self> {
+        if levels.len() != values.len() || levels.len() < 2 {
+            bail!("Need at least 2 levels and one value per level, got {} levels and {} values", levels.len(), values.len());
+        }
+        if levels.iter().any(|l| *l <= 0.0 || *l >= 1.0) || levels.windows(2).any(|w| w[0] >= w[1]) {
+            bail!("Quantile levels must be strictly increasing within (0, 1): {:?}", levels);
+        }
+        if values.windows(2).any(|w| w[0] > w[1]) {
+            bail!("Quantile values are not monotonic: {:?}", values);
+        }
+        Ok(Self { levels: levels.iter().map(|&x| x as f64).collect(), values: values.iter().map(|&x| x as f64).collect() })
+    }
+
+    /// Like new, but sorts crossed quantile values instead of failing (rearrangement).
+    pub fn new_rearranged(levels: &[f32], values: &[f32]) -> anyhow::Result<Self> {
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        Self::new(levels, &sorted)
+    }
+
+    pub fn levels(&self) -> &[f64] {
+        &self.levels
+    }
+
+    pub fn values(&self) -> &[f64] {
+        &self.values
+    }
+}
+
+/// Linear interpolation of y at x over the increasing xs, extrapolating with the end segments.
+fn interpolate(xs: &[f64], ys: &[f64], x: f64) -> f64 {
+    let n = xs.len();
+    let i = xs.partition_point(|v| *v < x).clamp(1, n - 1);
+    let (x0, x1, y0, y1) = (xs[i - 1], xs[i], ys[i - 1], ys[i]);
+    if x1 == x0 {
+        return if x < x0 { y0 } else { y1 };
+    }
+    y0 + (x - x0) * (y1 - y0) / (x1 - x0)
+}
+
+impl Prediction for QuantilePrediction {
+    fn cdf(&self, x: f64) -> f64 {
+        interpolate(&self.values, &self.levels, x).clamp(0.0, 1.0)
+    }
+
+    fn quantile(&self, p: f64) -> f64 {
+        interpolate(&self.levels, &self.values, p)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GaussianPrediction {
+    pub mean: f64,
+    pub std: f64,
+}
+
+impl GaussianPrediction {
+    pub fn new(mean: f64, std: f64) -> anyhow::Result<Self> {
+        if std <= 0.0 || !mean.is_finite() || !std.is_finite() {
+            bail!("Invalid gaussian prediction mean {} std {}", mean, std);
+        }
+        Ok(Self { mean, std })
+    }
+}
+
+impl Prediction for GaussianPrediction {
+    fn cdf(&self, x: f64) -> f64 {
+        norm_cdf((x - self.mean) / self.std)
+    }
+
+    fn quantile(&self, p: f64) -> f64 {
+        self.mean + self.std * norm_inv(p)
+    }
+
+    fn point(&self) -> f64 {
+        self.mean
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEVELS: [f32; MODEL_OUTPUT_WIDTH] = [0.05, 0.1, 0.25, 0.4, 0.5, 0.6, 0.75, 0.9];
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn quantiles_interpolate_and_extrapolate() {
+        let q = QuantilePrediction::new(&[0.25, 0.5, 0.75], &[-1.0, 0.0, 2.0]).unwrap();
+        assert!(close(q.point(), 0.0));
+        assert!(close(q.quantile(0.625), 1.0));
+        assert!(close(q.quantile(0.375), -0.5));
+        // Past the ends the end segments continue.
+        assert!(close(q.quantile(0.9), 3.2));
+        assert!(close(q.quantile(0.0), -2.0));
+        assert!(close(q.cdf(1.0), 0.625));
+        assert_eq!(q.cdf(100.0), 1.0);
+        assert_eq!(q.cdf(-100.0), 0.0);
+        assert!(close(q.prob_exceeds(0.0), 0.5));
+        let (lo, hi) = q.interval(0.5);
+        assert!(close(lo, -1.0) && close(hi, 2.0));
+    }
+
+    #[test]
+    fn quantiles_are_validated() {
+        assert!(QuantilePrediction::new(&[0.5], &[0.0]).is_err());
+        assert!(QuantilePrediction::new(&[0.5, 0.25], &[0.0, 1.0]).is_err());
+        assert!(QuantilePrediction::new(&[0.0, 0.5], &[0.0, 1.0]).is_err());
+        assert!(QuantilePrediction::new(&[0.25, 0.5], &[1.0, 0.0]).is_err());
+        let q = QuantilePrediction::new_rearranged(&[0.25, 0.5], &[1.0, 0.0]).unwrap();
+        assert_eq!(q.values(), &[0.0, 1.0]);
+    }
+
+    #[test]
+    fn gaussian_matches_normal_quantiles() {
+        let g = GaussianPrediction::new(1.0, 2.0).unwrap();
+        assert_eq!(g.point(), 1.0);
+        assert!(close(g.cdf(1.0), 0.5));
+        let (lo, hi) = g.interval(0.95);
+        assert!((lo - (1.0 - 1.959964 * 2.0)).abs() < 1e-4 && (hi - (1.0 + 1.959964 * 2.0)).abs() < 1e-4);
+        assert!((g.cdf(g.quantile(0.8)) - 0.8).abs() < 1e-6);
+        assert!(GaussianPrediction::new(0.0, 0.0).is_err());
+        assert!(GaussianPrediction::new(f64::NAN, 1.0).is_err());
+    }
+
+    #[test]
+    fn decode_by_head() {
+        let output = [-3.0, -2.0, -1.0, -0.5, 0.0, 0.5, 1.0, 2.0];
+        let PredictionView::Quantiles(q) = OutputHead::Quantiles(LEVELS).decode(&output).unwrap() else {
+            panic!("expected quantiles");
+        };
+        assert!(close(q.point(), 0.0));
+        assert!(OutputHead::Quantiles(LEVELS).decode(&[0.0, -1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]).is_err());
+
+        let head = OutputHead::MeanStd([1_000, 5_000, 30_000, 60_000]);
+        let output = [0.1, 1.0, 0.2, 2.0, 0.3, 3.0, 0.4, 4.0];
+        let PredictionView::MeanStd(preds) = head.decode(&output).unwrap() else {
+            panic!("expected mean std");
+        };
+        assert_eq!(preds.len(), 4);
+        assert_eq!(preds[2].0, 30_000);
+        assert!(close(preds[2].1.mean, 0.3f32 as f64) && close(preds[2].1.std, 3.0));
+        assert!(head.decode(&[0.0; MODEL_OUTPUT_WIDTH]).is_err());
+    }
+}
@@ -0,0 +1,333 @@
+// Python bindings so notebooks use the same conversions as the services. Enabled by the python feature.
+
+use std::collections::VecDeque;
+
+use numpy::{PyArray1, PyArray2, PyArrayMethods, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::*;
+use chrono_util::{ChronoFeatures, CHRONO_FEATURES_SIZE, CHRONO_FEATURE_SET};
+use convert::{new_series, TimeEmbedder};
+use data_info::*;
+use encoding::SeriesEncoding;
+use exchange::Exchange;
+use label::LabelEvent;
+use quote::QuoteEvent;
+
+fn to_py_err(e: anyhow::Error) -> PyErr {
+    PyValueError::new_err(format!("{:#}", e))
+}
+
+type PyInput<'py> = (Bound<'py, PyArray1<ModelFloat>>, Bound<'py, PyArray2<ModelFloat>>);
+
+/// InputRaw as (chrono features of shape [CHRONO_FEATURES_SIZE], series of shape [SERIES1_SIZE, SERIES1_ITEM_SIZE]).
+fn input_to_numpy<'py>(py: Python<'py>, input: &InputRaw) -> PyResult<PyInput<'py>> {
+    let chrono = PyArray1::from_slice(py, &input.0);
+    let series = PyArray1::from_slice(py, input.1.as_flattened()).reshape([SERIES1_SIZE, SERIES1_ITEM_SIZE])?;
+    Ok((chrono, series))
+}
+
+fn input_from_numpy(chrono: PyReadonlyArray1<ModelFloat>, series: PyReadonlyArray2<ModelFloat>) -> PyResult<InputRaw> {
+    let chrono_slice = chrono.as_slice()?;
+    let series_slice = series.as_slice()?;
+    if chrono_slice.len() != CHRONO_FEATURES_SIZE || series_slice.len() != SERIES1_SIZE * SERIES1_ITEM_SIZE {
+        return Err(PyValueError::new_err(format!("Expected shapes [{}] and [{}, {}]", CHRONO_FEATURES_SIZE, SERIES1_SIZE, SERIES1_ITEM_SIZE)));
+    }
+    let mut chrono_features: ChronoFeatures = [0.0; CHRONO_FEATURES_SIZE];
+    chrono_features.copy_from_slice(chrono_slice);
+    let mut input = new_series();
+    input.as_flattened_mut().copy_from_slice(series_slice);
+    Ok((chrono_features, input))
+}
+
+// ---- Events ---- //
+
+#[pyclass(name = "QuoteEvent")]
+#[derive(Clone)]
+pub struct PyQuoteEvent {
+    pub inner: QuoteEvent,
+}
+
+#[pymethods]
+impl PyQuoteEvent {
+    #[new]
+    #[pyo3(signature = (bid, biddate, ask, askdate, bidsz=0.0, asksz=0.0, event_id=0, offset=0))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(bid: f32, biddate: Timestamp, ask: f32, askdate: Timestamp, bidsz: SeriesFloat, asksz: SeriesFloat, event_id: EventId, offset: OffsetId) -> Self {
+        Self { inner: QuoteEvent { event_id, offset, bid, biddate, ask, askdate, bidsz, asksz, gap_before: 0 } }
+    }
+
+    /// Parses the json published to the quote topics.
+    #[staticmethod]
+    fn from_json(json: &str) -> PyResult<Self> {
+        let inner = serde_json::from_str(json).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(Self { inner })
+    }
+
+    fn to_json(&self) -> PyResult<String> {
+        serde_json::to_string(&self.inner).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    #[getter] fn event_id(&self) -> EventId { self.inner.event_id }
+    #[getter] fn offset(&self) -> OffsetId { self.inner.offset }
+    #[getter] fn bid(&self) -> f32 { self.inner.bid }
+    #[getter] fn biddate(&self) -> Timestamp { self.inner.biddate }
+    #[getter] fn ask(&self) -> f32 { self.inner.ask }
+    #[getter] fn askdate(&self) -> Timestamp { self.inner.askdate }
+    #[getter] fn bidsz(&self) -> SeriesFloat { self.inner.bidsz }
+    #[getter] fn asksz(&self) -> SeriesFloat { self.inner.asksz }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.inner)
+    }
+}
+
+#[pyclass(name = "LabelEvent")]
+pub struct PyLabelEvent {
+    pub inner: LabelEvent,
+}
+
+#[pymethods]
+impl PyLabelEvent {
+    #[new]
+    fn new(event_id: EventId, timestamp: Timestamp, offset_from: OffsetId, offset_to: OffsetId, label: PyReadonlyArray1<ModelFloat>) -> PyResult<Self> {
+        let label: LabelType = label.as_slice()?.try_into()
+            .map_err(|_| PyValueError::new_err(format!("Label must have {} values", MODEL_OUTPUT_WIDTH)))?;
+        Ok(Self { inner: LabelEvent::new(event_id, timestamp, offset_from, offset_to, label) })
+    }
+
+    #[staticmethod]
+    fn from_json(json: &str) -> PyResult<Self> {
+        let inner = serde_json::from_str(json).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(Self { inner })
+    }
+
+    fn to_json(&self) -> PyResult<String> {
+        serde_json::to_string(&self.inner).map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    #[getter] fn event_id(&self) -> EventId { self.inner.event_id }
+    #[getter] fn timestamp(&self) -> Timestamp { self.inner.timestamp }
+    #[getter] fn offset_from(&self) -> OffsetId { self.inner.offset_from }
+    #[getter] fn offset_to(&self) -> OffsetId { self.inner.offset_to }
+
+    #[getter]
+    fn label<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray1<ModelFloat>> {
+        PyArray1::from_slice(py, &self.inner.label)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.inner)
+    }
+}
+
+// ---- Conversions ---- //
+
+/// Returns (chrono, series) numpy arrays for exactly SERIES1_SIZE events, oldest first.
+#[pyfunction]
+#[pyo3(signature = (events, exchange=None))]
+fn series_to_input<'py>(py: Python<'py>, events: Vec<PyQuoteEvent>, exchange: Option<&str>) -> PyResult<PyInput<'py>> {
+    if events.len() != SERIES1_SIZE {
+        return Err(PyValueError::new_err(format!("Expected {} events but got {}", SERIES1_SIZE, events.len())));
+    }
+    let events: VecDeque<QuoteEvent> = events.into_iter().map(|e| e.inner).collect();
+    input_to_numpy(py, &convert::series_to_input_on(&exchange_arg(exchange)?, &events).map_err(to_py_err)?)
+}
+
+#[pyfunction]
+#[pyo3(signature = (timestamp, exchange=None))]
+fn make_chrono_features<'py>(py: Python<'py>, timestamp: Timestamp, exchange: Option<&str>) -> PyResult<Bound<'py, PyArray1<ModelFloat>>> {
+    Ok(PyArray1::from_slice(py, &chrono_util::make_chrono_features_on(&exchange_arg(exchange)?, timestamp)))
+}
+
+/// Names of the make_chrono_features entries in order.
+#[pyfunction]
+fn chrono_feature_names() -> Vec<&'static str> {
+    CHRONO_FEATURE_SET.iter().map(|f| f.name()).collect()
+}
+
+#[pyclass(name = "TimeEmbedder")]
+pub struct PyTimeEmbedder {
+    inner: TimeEmbedder<TIME_EMBEDDING_SIZE>,
+}
+
+#[pymethods]
+impl PyTimeEmbedder {
+    #[new]
+    fn new() -> Self {
+        Self { inner: TimeEmbedder::new() }
+    }
+
+    fn embed<'py>(&self, py: Python<'py>, time: Timestamp) -> Bound<'py, PyArray1<ModelFloat>> {
+        PyArray1::from_slice(py, &self.inner.embed(time))
+    }
+
+    /// Shape [len(times), TIME_EMBEDDING_SIZE].
+    fn embed_many<'py>(&self, py: Python<'py>, times: Vec<Timestamp>) -> PyResult<Bound<'py, PyArray2<ModelFloat>>> {
+        let values: Vec<ModelFloat> = times.iter().flat_map(|t| self.inner.embed(*t)).collect();
+        PyArray1::from_vec(py, values).reshape([times.len(), TIME_EMBEDDING_SIZE])
+    }
+}
+
+// ---- Calendar ---- //
+
+// The calendar functions take the exchange as a preset name like "xetra" or Exchange json, the default exchange if None.
+fn exchange_arg(exchange: Option<&str>) -> PyResult<Exchange> {
+    let Some(name) = exchange else {
+        return Ok(Exchange::default());
+    };
+    match Exchange::preset(name) {
+        Some(exchange) => Ok(exchange),
+        None => serde_json::from_str(name)
+            .map_err(|e| PyValueError::new_err(format!("Unknown exchange {}, not a preset name or valid Exchange json: {}", name, e))),
+    }
+}
+
+#[pyfunction]
+#[pyo3(signature = (date, exchange=None))]
+fn is_trading_day(date: NaiveDate, exchange: Option<&str>) -> PyResult<bool> {
+    Ok(exchange_arg(exchange)?.is_trading_day(date))
+}
+
+#[pyfunction]
+#[pyo3(signature = (date, exchange=None))]
+fn is_early_close(date: NaiveDate, exchange: Option<&str>) -> PyResult<bool> {
+    Ok(exchange_arg(exchange)?.calendar.is_early_close(date))
+}
+
+/// Holiday and early close aware.
+#[pyfunction]
+#[pyo3(signature = (timestamp, exchange=None))]
+fn in_regular_session(timestamp: Timestamp, exchange: Option<&str>) -> PyResult<bool> {
+    Ok(exchange_arg(exchange)?.in_regular_session(timestamp))
+}
+
+#[pyfunction]
+#[pyo3(signature = (timestamp, exchange=None))]
+fn session_date(timestamp: Timestamp, exchange: Option<&str>) -> PyResult<NaiveDate> {
+    Ok(exchange_arg(exchange)?.session_date(timestamp))
+}
+
+/// Session name, e.g. "regular".
+#[pyfunction]
+#[pyo3(signature = (timestamp, exchange=None))]
+fn session_of(timestamp: Timestamp, exchange: Option<&str>) -> PyResult<String> {
+    Ok(exchange_arg(exchange)?.session_of(timestamp).name().to_string())
+}
+
+// ---- Codecs ---- //
+
+/// Encodes (chrono, series) with the encoding name, e.g. "f16" or "affine8", see encoding::SeriesEncoding.
+#[pyfunction]
+fn encode_input<'py>(py: Python<'py>, chrono: PyReadonlyArray1<ModelFloat>, series: PyReadonlyArray2<ModelFloat>, encoding: &str) -> PyResult<Bound<'py, PyBytes>> {
+    let encoding: SeriesEncoding = serde_json::from_value(serde_json::Value::String(encoding.to_string()))
+        .map_err(|_| PyValueError::new_err(format!("Unknown encoding {}", encoding)))?;
+    let bytes = encoding.encode(&input_from_numpy(chrono, series)?).map_err(to_py_err)?;
+    Ok(PyBytes::new(py, &bytes))
+}
+
+#[pyfunction]
+fn decode_input<'py>(py: Python<'py>, bytes: &[u8]) -> PyResult<PyInput<'py>> {
+    input_to_numpy(py, &encoding::decode(bytes).map_err(to_py_err)?)
+}
+
+#[pyfunction]
+fn event_id_to_bytes<'py>(py: Python<'py>, event_id: EventId) -> Bound<'py, PyBytes> {
+    PyBytes::new(py, &convert::event_id_to_bytes(event_id))
+}
+
+#[pyfunction]
+fn bytes_to_event_id(bytes: &[u8]) -> PyResult<EventId> {
+    let bytes = bytes.try_into().map_err(|_| PyValueError::new_err("Event id must be 8 bytes"))?;
+    Ok(convert::bytes_to_event_id(bytes))
+}
+
+#[pymodule]
+fn shared_types(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyQuoteEvent>()?;
+    m.add_class::<PyLabelEvent>()?;
+    m.add_class::<PyTimeEmbedder>()?;
+    m.add_function(wrap_pyfunction!(series_to_input, m)?)?;
+    m.add_function(wrap_pyfunction!(make_chrono_features, m)?)?;
+    m.add_function(wrap_pyfunction!(chrono_feature_names, m)?)?;
+    m.add_function(wrap_pyfunction!(is_trading_day, m)?)?;
+    m.add_function(wrap_pyfunction!(is_early_close, m)?)?;
+    m.add_function(wrap_pyfunction!(in_regular_session, m)?)?;
+    m.add_function(wrap_pyfunction!(session_date, m)?)?;
+    m.add_function(wrap_pyfunction!(session_of, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_input, m)?)?;
+    m.add_function(wrap_pyfunction!(decode_input, m)?)?;
+    m.add_function(wrap_pyfunction!(event_id_to_bytes, m)?)?;
+    m.add_function(wrap_pyfunction!(bytes_to_event_id, m)?)?;
+    m.add("CURRENT_VERSION", CURRENT_VERSION)?;
+    m.add("SERIES1_SIZE", SERIES1_SIZE)?;
+    m.add("SERIES1_ITEM_SIZE", SERIES1_ITEM_SIZE)?;
+    m.add("CHRONO_FEATURES_SIZE", CHRONO_FEATURES_SIZE)?;
+    m.add("MODEL_OUTPUT_WIDTH", MODEL_OUTPUT_WIDTH)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use numpy::PyUntypedArrayMethods;
+
+    use super::*;
+    use series::SeriesEvent;
+    use synthetic::{SyntheticConfig, SyntheticMarket};
+
+    fn with_python<R>(f: impl for<'py> FnOnce(Python<'py>) -> R) -> R {
+        Python::initialize();
+        Python::attach(f)
+    }
+
+    // The numpy conversions need the numpy package in the interpreter the tests link to.
+    fn has_numpy(py: Python<'_>) -> bool {
+        let found = py.import("numpy").is_ok();
+        if !found {
+            eprintln!("numpy is not installed for the linked python, skipping the numpy parity check");
+        }
+        found
+    }
+
+    fn bits(values: &[ModelFloat]) -> Vec<u32> {
+        values.iter().map(|x| x.to_bits()).collect()
+    }
+
+    #[test]
+    fn numpy_input_matches_series_to_input() {
+        with_python(|py| {
+            if !has_numpy(py) {
+                return;
+            }
+            let window: VecDeque<QuoteEvent> = SyntheticMarket::new(SyntheticConfig::default()).take(SERIES1_SIZE).collect();
+            let events = window.iter().map(|e| PyQuoteEvent { inner: e.clone() }).collect();
+            let (chrono, series) = series_to_input(py, events, None).unwrap();
+            let expected = convert::series_to_input(&window).unwrap();
+            assert_eq!(series.shape(), [SERIES1_SIZE, SERIES1_ITEM_SIZE]);
+            assert_eq!(bits(chrono.readonly().as_slice().unwrap()), bits(&expected.0));
+            assert_eq!(bits(series.readonly().as_slice().unwrap()), bits(expected.1.as_flattened()));
+
+            let ts = window[SERIES1_SIZE - 1].timestamp();
+            let xetra = make_chrono_features(py, ts, Some("xetra")).unwrap();
+            assert_eq!(bits(xetra.readonly().as_slice().unwrap()), bits(&chrono_util::make_chrono_features_on(&Exchange::xetra(), ts)));
+            assert!(series_to_input(py, Vec::new(), None).is_err());
+        });
+    }
+
+    #[test]
+    fn exchange_arg_reports_the_parse_error() {
+        assert_eq!(exchange_arg(None).unwrap(), Exchange::default());
+        assert_eq!(exchange_arg(Some("xetra")).unwrap(), Exchange::xetra());
+        let json = serde_json::to_string(&Exchange::cme_equity_futures()).unwrap();
+        assert_eq!(exchange_arg(Some(&json)).unwrap(), Exchange::cme_equity_futures());
+        with_python(|py| {
+            let err = exchange_arg(Some("nyse")).unwrap_err();
+            assert!(err.value(py).to_string().contains("Unknown exchange nyse"));
+            let err = exchange_arg(Some(r#"{"name": "X", "timezone": "Mars/Base"}"#)).unwrap_err();
+            let message = err.value(py).to_string();
+            assert!(message.contains("Mars/Base"), "{}", message);
+        });
+    }
+}
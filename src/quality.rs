@@ -0,0 +1,391 @@
+// Per trading day data quality report, to pick the healthy days before retraining.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write;
+
+use crate::*;
+use data_info::{QuoteStreamSpec, SERIES1_SIZE};
+use exchange::Exchange;
+use gap::{GapConfig, GapDetector, GapPolicy};
+use quote::{QuoteEvent, QuoteValues};
+use series::SeriesEvent;
+use series_proc::{BaseHandler, HandleOutcome, Processor};
+
+/// How many of the largest gaps are kept in the report.
+pub const LARGEST_GAPS: usize = 10;
+
+/// Keeps the last SERIES1_SIZE events and counts the full windows, like the series processor would produce.
+#[derive(Debug, Default)]
+pub struct WindowCounter {
+    pub windows: usize,
+}
+
+impl Processor<VecDeque<QuoteEvent>, QuoteValues> for WindowCounter {
+    fn process(&mut self, _start_values: &QuoteValues, events: &mut VecDeque<QuoteEvent>) -> bool {
+        while events.len() > SERIES1_SIZE {
+            events.pop_front();
+        }
+        if events.len() == SERIES1_SIZE {
+            self.windows += 1;
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GapEntry {
+    /// Timestamp of the event after the gap.
+    pub timestamp: Timestamp,
+    pub millis: Timestamp,
+}
+
+/// Spread distribution in basis points of the mid.
+#[derive(Debug, Default, Clone, serde::Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SpreadStats {
+    pub count: usize,
+    pub min: f32,
+    pub p50: f32,
+    pub p90: f32,
+    pub p99: f32,
+    pub max: f32,
+    pub mean: f32,
+    /// Locked or crossed quotes, ask <= bid.
+    pub non_positive: usize,
+}
+
+impl SpreadStats {
+    fn from_bps(mut spreads: Vec<f32>, non_positive: usize) -> Self {
+        if spreads.is_empty() {
+            return Self { non_positive, ..Self::default() };
+        }
+        spreads.sort_by(f32::total_cmp);
+        let n = spreads.len();
+        let at = |p: f64| spreads[((n - 1) as f64 * p).round() as usize];
+        Self {
+            count: n,
+            min: spreads[0],
+            p50: at(0.5),
+            p90: at(0.9),
+            p99: at(0.99),
+            max: spreads[n - 1],
+            mean: spreads.iter().sum::<f32>() / n as f32,
+            non_positive,
+        }
+    }
+}
+
+/// Summary of one trading day of quotes as seen by the handler.
+#[derive(Debug, Clone, serde::Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DayQualityReport {
+    pub date: NaiveDate,
+    pub exchange: String,
+    pub events: usize,
+    /// Events by the session they fall in, see Session::name.
+    pub sessions: BTreeMap<String, usize>,
+    pub accepted: usize,
+    /// Events dropped by the handler by OutcomeSource::name.
+    pub invalid: BTreeMap<String, usize>,
+    /// Handler resets by OutcomeSource::name, and "gap" for gaps with GapPolicy::Reset.
+    pub resets: BTreeMap<String, usize>,
+    pub gaps: usize,
+    /// Largest first.
+    pub largest_gaps: Vec<GapEntry>,
+    pub spread_bps: SpreadStats,
+    /// Events with a timestamp before the previous event's.
+    pub out_of_order: usize,
+    /// Events where the bid and ask belong to different session dates.
+    pub date_mismatches: usize,
+    /// Largest difference between askdate and biddate in millis.
+    pub max_bid_ask_skew: Timestamp,
+    /// Full SERIES1_SIZE windows the handler produced.
+    pub windows: usize,
+}
+
+impl DayQualityReport {
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Human readable table of the report.
+    pub fn to_table(&self) -> String {
+        let mut out = String::new();
+        let mut row = |name: &str, value: String| {
+            let _ = writeln!(out, "{:<24} {}", name, value);
+        };
+        row("date", format!("{} ({})", self.date, self.exchange));
+        row("events", self.events.to_string());
+        for (session, count) in &self.sessions {
+            row(&format!("  session {}", session), count.to_string());
+        }
+        row("accepted", self.accepted.to_string());
+        for (reason, count) in &self.invalid {
+            row(&format!("  invalid {}", reason), count.to_string());
+        }
+        for (reason, count) in &self.resets {
+            row(&format!("  reset {}", reason), count.to_string());
+        }
+        row("windows", self.windows.to_string());
+        row("gaps", self.gaps.to_string());
+        for gap in &self.largest_gaps {
+            row("  gap", format!("{} ms at {}", gap.millis, chrono_util::to_datetime(gap.timestamp)));
+        }
+        let s = &self.spread_bps;
+        row("spread bps", format!(
+            "n={} min={:.2} p50={:.2} p90={:.2} p99={:.2} max={:.2} mean={:.2}",
+            s.count, s.min, s.p50, s.p90, s.p99, s.max, s.mean,
+        ));
+        row("  non-positive spreads", s.non_positive.to_string());
+        row("out of order", self.out_of_order.to_string());
+        row("bid/ask date mismatch", self.date_mismatches.to_string());
+        row("max bid/ask skew ms", self.max_bid_ask_skew.to_string());
+        out
+    }
+}
+
+/// Feeds a day's events through a handler configured like the stream and collects a DayQualityReport.
+pub struct QualityReportBuilder {
+    handler: BaseHandler<QuoteValues, QuoteEvent, WindowCounter>,
+    events: usize,
+    sessions: BTreeMap<String, usize>,
+    accepted: usize,
+    invalid: BTreeMap<String, usize>,
+    resets: BTreeMap<String, usize>,
+    gaps: Vec<GapEntry>,
+    spreads: Vec<f32>,
+    non_positive: usize,
+    out_of_order: usize,
+    date_mismatches: usize,
+    max_bid_ask_skew: Timestamp,
+    prev_ts: Option<Timestamp>,
+}
+
+impl QualityReportBuilder {
+    pub fn new(spec: &QuoteStreamSpec) -> Self {
+        Self {
+            handler: BaseHandler::for_quote_stream(WindowCounter::default(), spec),
+            events: 0,
+            sessions: BTreeMap::new(),
+            accepted: 0,
+            invalid: BTreeMap::new(),
+            resets: BTreeMap::new(),
+            gaps: Vec::new(),
+            spreads: Vec::new(),
+            non_positive: 0,
+            out_of_order: 0,
+            date_mismatches: 0,
+            max_bid_ask_skew: 0,
+            prev_ts: None,
+        }
+    }
+
+    /// Detects gaps with the config instead of the default one.
+    pub fn with_gaps(mut self, config: GapConfig) -> Self {
+        self.handler.gaps = GapDetector::new(config);
+        self
+    }
+
+    pub fn exchange(&self) -> &Exchange {
+        &self.handler.exchange
+    }
+
+    pub fn add(&mut self, event: QuoteEvent) {
+        let exchange = &self.handler.exchange;
+        let ts = event.timestamp();
+        self.events += 1;
+        *self.sessions.entry(exchange.session_of(ts).name().to_string()).or_default() += 1;
+        if self.prev_ts.is_some_and(|prev| ts < prev) {
+            self.out_of_order += 1;
+        }
+        self.prev_ts = Some(ts);
+        if exchange.session_date(event.biddate) != exchange.session_date(event.askdate) {
+            self.date_mismatches += 1;
+        }
+        self.max_bid_ask_skew = self.max_bid_ask_skew.max((event.askdate - event.biddate).abs());
+        let mid = (event.bid + event.ask) / 2.0;
+        if event.ask <= event.bid {
+            self.non_positive += 1;
+        }
+        if mid > 0.0 {
+            self.spreads.push((event.ask - event.bid) / mid * 10_000.0);
+        }
+
+        // The first event after an empty handler always starts it over, which isn't a reset worth reporting
+        let started = !self.handler.events.is_empty();
+        match self.handler.handle_with_outcome(event).1 {
+            HandleOutcome::Accepted => self.accepted += 1,
+            HandleOutcome::AcceptedAfterGap(millis) => {
+                self.accepted += 1;
+                if self.handler.gaps.policy() == GapPolicy::Reset {
+                    *self.resets.entry("gap".to_string()).or_default() += 1;
+                }
+                self.gaps.push(GapEntry { timestamp: ts, millis });
+            },
+            HandleOutcome::Reset(source) => {
+                self.accepted += 1;
+                if started {
+                    *self.resets.entry(source.name().to_string()).or_default() += 1;
+                }
+            },
+            HandleOutcome::Invalid(source) => *self.invalid.entry(source.name().to_string()).or_default() += 1,
+        }
+    }
+
+    pub fn finish(self, date: NaiveDate) -> DayQualityReport {
+        let gap_count = self.gaps.len();
+        let mut largest_gaps = self.gaps;
+        largest_gaps.sort_by_key(|g| std::cmp::Reverse(g.millis));
+        largest_gaps.truncate(LARGEST_GAPS);
+        DayQualityReport {
+            date,
+            exchange: self.handler.exchange.name.clone(),
+            events: self.events,
+            sessions: self.sessions,
+            accepted: self.accepted,
+            invalid: self.invalid,
+            resets: self.resets,
+            gaps: gap_count,
+            largest_gaps,
+            spread_bps: SpreadStats::from_bps(self.spreads, self.non_positive),
+            out_of_order: self.out_of_order,
+            date_mismatches: self.date_mismatches,
+            max_bid_ask_skew: self.max_bid_ask_skew,
+            windows: self.handler.proc.windows,
+        }
+    }
+}
+
+/// One report per session date of the stream's exchange, in date order. Each day starts with a fresh handler.
+pub fn report_days(spec: &QuoteStreamSpec, gaps: GapConfig, events: impl IntoIterator<Item = QuoteEvent>) -> Vec<DayQualityReport> {
+    let mut days: BTreeMap<NaiveDate, QualityReportBuilder> = BTreeMap::new();
+    for event in events {
+        let date = spec.exchange.session_date(event.timestamp());
+        days.entry(date).or_insert_with(|| QualityReportBuilder::new(spec).with_gaps(gaps.clone())).add(event);
+    }
+    days.into_iter().map(|(date, builder)| builder.finish(date)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use series_proc::EventHandler;
+    use session::{Session, SessionConfig};
+
+    // 2024-01-02 09:00 and 10:00 New York
+    const PRE: Timestamp = 1_704_204_000_000;
+    const TEN: Timestamp = 1_704_207_600_000;
+    const DAY: Timestamp = 86_400_000;
+
+    fn spec(accepted: Vec<Session>) -> QuoteStreamSpec {
+        QuoteStreamSpec {
+            topic_name: "test".to_string(),
+            feature_size: 2,
+            time_embedding_size: 4,
+            features: Vec::new(),
+            volatility_window: None,
+            sessions: SessionConfig { accepted, ..SessionConfig::default() },
+            exchange: Exchange::default(),
+        }
+    }
+
+    fn quotes(timestamps: impl IntoIterator<Item = Timestamp>) -> Vec<QuoteEvent> {
+        timestamps.into_iter().map(|ts| QuoteEvent::at(ts, 100.0, 100.02)).collect()
+    }
+
+    /// Day one: pre-market then regular, an event with bid and ask in different sessions, one out of order
+    /// and one after the close. Day two: regular only with a 5 minute gap, then an event with the ask
+    /// still on day one's session date.
+    fn two_days() -> Vec<QuoteEvent> {
+        let mut mixed = QuoteEvent::at(TEN + 2_000, 100.0, 100.02);
+        mixed.askdate = TEN - 1_800_001;
+        let mut events = quotes([PRE, PRE + 1_000, TEN, TEN + 1_000, TEN + 500]);
+        events.push(mixed);
+        events.extend(quotes([TEN + 3_000, TEN + 7 * 3_600_000]));
+        events.extend(quotes([DAY + TEN, DAY + TEN + 1_000, DAY + TEN + 301_000]));
+        let mut stale = QuoteEvent::at(DAY + TEN + 302_000, 100.0, 100.02);
+        stale.askdate = TEN + 35_940_000;
+        events.push(stale);
+        events
+    }
+
+    fn counts(entries: &[(&str, usize)]) -> BTreeMap<String, usize> {
+        entries.iter().map(|&(name, count)| (name.to_string(), count)).collect()
+    }
+
+    #[test]
+    fn counts_sessions_invalid_and_resets() {
+        let gaps = GapConfig { policy: GapPolicy::Reset, ..GapConfig::default() };
+        let reports = report_days(&spec(vec![Session::PreMarket, Session::Regular]), gaps, two_days());
+        assert_eq!(reports.len(), 2);
+
+        let day = &reports[0];
+        assert_eq!(day.date, NaiveDate::from_ymd_opt(2024, 1, 2).unwrap());
+        assert_eq!(day.events, 8);
+        assert_eq!(day.sessions, counts(&[("pre_market", 2), ("regular", 5), ("post_market", 1)]));
+        // The first event starts the handler and isn't a reset, the switch to the regular session is.
+        assert_eq!(day.resets, counts(&[("session", 1)]));
+        assert_eq!(day.invalid, counts(&[("base_values", 1), ("session", 1)]));
+        assert_eq!(day.accepted, 6);
+        assert_eq!(day.out_of_order, 1);
+        assert_eq!(day.max_bid_ask_skew, 1_800_001 + 2_000);
+        assert_eq!(day.date_mismatches, 0);
+        assert_eq!(day.gaps, 0);
+
+        let day = &reports[1];
+        assert_eq!(day.date, NaiveDate::from_ymd_opt(2024, 1, 3).unwrap());
+        assert_eq!(day.sessions, counts(&[("regular", 4)]));
+        assert_eq!(day.resets, counts(&[("gap", 1)]));
+        // The ask at 19:59 the day before is post-market on the previous session date.
+        assert_eq!(day.invalid, counts(&[("base_values", 1)]));
+        assert_eq!(day.date_mismatches, 1);
+        assert_eq!(day.max_bid_ask_skew, DAY + 302_000 - 35_940_000);
+        assert_eq!(day.out_of_order, 0);
+        assert_eq!(day.gaps, 1);
+        assert_eq!(day.largest_gaps[0].millis, 300_000);
+        assert_eq!(day.largest_gaps[0].timestamp, DAY + TEN + 301_000);
+    }
+
+    #[test]
+    fn ignored_gaps_are_not_resets() {
+        let reports = report_days(&spec(vec![Session::Regular]), GapConfig::default(), two_days());
+        assert_eq!(reports[1].gaps, 1);
+        assert!(reports[1].resets.is_empty());
+        // Pre-market isn't accepted, so there's no session switch to reset on.
+        assert_eq!(reports[0].invalid, counts(&[("base_values", 1), ("session", 3)]));
+        assert!(reports[0].resets.is_empty());
+    }
+
+    #[test]
+    fn counts_full_windows() {
+        let spec = spec(vec![Session::Regular]);
+        let one = report_days(&spec, GapConfig::default(), quotes((0..SERIES1_SIZE as Timestamp).map(|i| TEN + i * 100)));
+        assert_eq!(one[0].windows, 1);
+        let two = report_days(&spec, GapConfig::default(), quotes((0..=SERIES1_SIZE as Timestamp).map(|i| TEN + i * 100)));
+        assert_eq!(two[0].windows, 2);
+        assert_eq!(report_days(&spec, GapConfig::default(), quotes((1..SERIES1_SIZE as Timestamp).map(|i| TEN + i * 100)))[0].windows, 0);
+    }
+
+    struct Refuse;
+
+    impl Processor<VecDeque<QuoteEvent>, QuoteValues> for Refuse {
+        fn process(&mut self, _start_values: &QuoteValues, _events: &mut VecDeque<QuoteEvent>) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn handle_returns_processor_result_only_when_valid() {
+        let spec = spec(vec![Session::PreMarket, Session::Regular]);
+        let mut handler = BaseHandler::for_quote_stream(Refuse, &spec);
+        let mut with_outcome = BaseHandler::for_quote_stream(Refuse, &spec);
+        // Resets and dropped events return true, accepted events what the processor returned.
+        let expected = [true, false, true, false, false, true, false, true];
+        for (event, expected) in two_days().into_iter().take(8).zip(expected) {
+            let (handled, _) = with_outcome.handle_with_outcome(event.clone());
+            assert_eq!(handler.handle(event), expected);
+            assert_eq!(handled, expected);
+        }
+    }
+}
@@ -1,10 +1,12 @@
 use crate::*;
 use chrono_util::*;
+use exchange::Exchange;
 use series::*;
 use series_proc::BaseValues;
 
 /// Published to series by ingest and read by label, train...
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct QuoteEvent {
     #[serde(default)]
     pub event_id: EventId,
@@ -12,20 +14,37 @@ pub struct QuoteEvent {
     pub offset: OffsetId,
     pub bid: f32,
     #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[cfg_attr(feature = "schema", schemars(with = "schema::StringOrNumber"))]
     pub biddate: Timestamp,
     pub ask: f32,
     #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[cfg_attr(feature = "schema", schemars(with = "schema::StringOrNumber"))]
     pub askdate: Timestamp,
+    /// Sizes are optional in the feed, 0 when missing.
+    #[serde(default)]
+    pub bidsz: SeriesFloat,
+    #[serde(default)]
+    pub asksz: SeriesFloat,
+    /// Millis since the previous event when a gap was detected before this one, otherwise 0.
+    /// Not part of the published event, handler checkpoints save it separately.
+    #[serde(skip)]
+    pub gap_before: Timestamp,
 }
 
 impl QuoteEvent {
-    fn event_in_trading_time(&self) -> bool {
-        ts_in_trading_time(self.biddate) && ts_in_trading_time(self.askdate)
+    /// Quote with bid and ask at the same timestamp, no ids and no sizes.
+    pub fn at(ts: Timestamp, bid: f32, ask: f32) -> Self {
+        Self { event_id: 0, offset: 0, bid, biddate: ts, ask, askdate: ts, bidsz: 0.0, asksz: 0.0, gap_before: 0 }
     }
 
-    fn to_date_or_0(&self) -> NaiveDate {
-        let bid_date = to_market_datetime(self.biddate).date_naive();
-        let ask_date = to_market_datetime(self.askdate).date_naive();
+    /// Which sessions are accepted is checked by the handler's SessionFilter, here only that bid and ask agree.
+    fn sessions_match(&self, exchange: &Exchange) -> bool {
+        exchange.session_of(self.biddate) == exchange.session_of(self.askdate)
+    }
+
+    fn to_date_or_0(&self, exchange: &Exchange) -> NaiveDate {
+        let bid_date = exchange.session_date(self.biddate);
+        let ask_date = exchange.session_date(self.askdate);
         // TODO: if they're very near each other, could choose one, probably latter
         // arbitrary 10 seconds?
         if bid_date == ask_date || (self.askdate - self.biddate) < 10 {
@@ -63,23 +82,26 @@ impl SeriesEvent for QuoteEvent {
         self.offset = offset;
     }
 
+    fn mark_gap(&mut self, gap: Timestamp) {
+        self.gap_before = gap;
+    }
+
+    fn gap_before(&self) -> Timestamp {
+        self.gap_before
+    }
+
     fn timestamp(&self) -> Timestamp {
         // TODO: validate the timestamps are similar
         self.biddate
     }
 
-    fn validity(&self, base: &Self::BV) -> Validity {
-        if !self.event_in_trading_time() {
-            Validity::Invalid
-        } else if !same_date(self.to_date_or_0(), base.date_or_0) {
-            Validity::CauseReset
-        } else {
-            Validity::Valid
-        }
+    fn validity(&self, base: &Self::BV, exchange: &Exchange) -> Validity {
+        base.validity(self, exchange)
     }
 }
 
-#[derive(Default)]
+#[derive(Default, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct QuoteValues {
     pub date_or_0: NaiveDate,
     pub bid: SeriesFloat,
@@ -99,17 +121,99 @@ pub struct QuoteValues {
 // }
 
 impl BaseValues<QuoteEvent> for QuoteValues {
-    fn convert_from(event: &QuoteEvent) -> Self {
-        Self { date_or_0: event.to_date_or_0(), bid: event.bid, ask: event.ask }
+    fn convert_from(event: &QuoteEvent, exchange: &Exchange) -> Self {
+        Self { date_or_0: event.to_date_or_0(exchange), bid: event.bid, ask: event.ask }
     }
 
-    fn validity(&self, event: &QuoteEvent) -> Validity {
-        if !event.event_in_trading_time() {
+    /// Trading time isn't checked here, BaseHandler's SessionFilter first drops events outside the stream's
+    /// accepted sessions (the regular session by default), and an ask in another session than the bid is invalid.
+    fn validity(&self, event: &QuoteEvent, exchange: &Exchange) -> Validity {
+        if !event.sessions_match(exchange) {
             Validity::Invalid
-        } else if !same_date(event.to_date_or_0(), self.date_or_0) {
+        } else if !same_date(event.to_date_or_0(exchange), self.date_or_0) {
             Validity::CauseReset
         } else {
             Validity::Valid
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use series_proc::{BaseHandler, HandleOutcome, OutcomeSource, Processor};
+    use session::{Session, SessionConfig, SessionFilter};
+
+    struct Keep;
+
+    impl Processor<VecDeque<QuoteEvent>, QuoteValues> for Keep {
+        fn process(&mut self, _start_values: &QuoteValues, _events: &mut VecDeque<QuoteEvent>) -> bool {
+            true
+        }
+    }
+
+    fn handler(accepted: Vec<Session>) -> BaseHandler<QuoteValues, QuoteEvent, Keep> {
+        let sessions = SessionFilter::new(SessionConfig { accepted, ..SessionConfig::default() });
+        BaseHandler::new_with_exchange(Keep, Exchange::us_equities(), sessions)
+    }
+
+    // Millis at hh:mm New York time (UTC-5 in winter) on the day.
+    fn ts(date: (i32, u32, u32), hour: u32, minute: u32) -> Timestamp {
+        NaiveDate::from_ymd_opt(date.0, date.1, date.2).unwrap().and_hms_opt(hour, minute, 0).unwrap().and_utc().timestamp_millis() + 5 * 3_600_000
+    }
+
+    fn is_invalid(outcome: HandleOutcome) -> bool {
+        matches!(outcome, HandleOutcome::Invalid(_))
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn default_sessions_accept_the_old_trading_time() {
+        let mut handler = handler(SessionConfig::default().accepted);
+        for minutes in (0..24 * 60).step_by(5) {
+            let t = ts((2024, 1, 2), minutes / 60, minutes % 60);
+            let (_, outcome) = handler.handle_with_outcome(QuoteEvent::at(t, 100.0, 100.1));
+            assert_eq!(!is_invalid(outcome), ts_in_trading_time(t), "{}", to_market_datetime(t));
+        }
+        // Unlike the old check, holidays are closed.
+        let (_, outcome) = handler.handle_with_outcome(QuoteEvent::at(ts((2024, 1, 15), 10, 0), 100.0, 100.1));
+        assert_eq!(outcome, HandleOutcome::Invalid(OutcomeSource::Session));
+    }
+
+    #[test]
+    fn ask_outside_the_bid_session_is_invalid() {
+        let mut handler = handler(SessionConfig::default().accepted);
+        let mut event = QuoteEvent::at(ts((2024, 1, 2), 9, 30), 100.0, 100.1);
+        event.askdate = ts((2024, 1, 2), 9, 29);
+        assert_eq!(handler.handle_with_outcome(event).1, HandleOutcome::Invalid(OutcomeSource::BaseValues));
+    }
+
+    #[test]
+    fn accepted_sessions_come_from_the_config() {
+        let pre_market = QuoteEvent::at(ts((2024, 1, 2), 8, 0), 100.0, 100.1);
+        assert!(is_invalid(handler(vec![Session::Regular]).handle_with_outcome(pre_market.clone()).1));
+        let mut handler = handler(vec![Session::PreMarket, Session::Regular]);
+        // The first event always starts the handler over.
+        assert_eq!(handler.handle_with_outcome(pre_market).1, HandleOutcome::Reset(OutcomeSource::BaseValues));
+        let open = QuoteEvent::at(ts((2024, 1, 2), 9, 30), 100.0, 100.1);
+        assert_eq!(handler.handle_with_outcome(open).1, HandleOutcome::Reset(OutcomeSource::Session));
+    }
+
+    #[test]
+    fn mixed_event_on_session_switch_is_invalid() {
+        let mut handler = handler(vec![Session::PreMarket, Session::Regular]);
+        handler.handle_with_outcome(QuoteEvent::at(ts((2024, 1, 2), 9, 0), 100.0, 100.1));
+        assert_eq!(handler.handle_with_outcome(QuoteEvent::at(ts((2024, 1, 2), 9, 0) + 1_000, 100.0, 100.1)).1, HandleOutcome::Accepted);
+        // The first regular session event has its ask still in the pre-market.
+        let mut mixed = QuoteEvent::at(ts((2024, 1, 2), 9, 30), 101.0, 101.1);
+        mixed.askdate = ts((2024, 1, 2), 9, 29);
+        assert_eq!(handler.handle_with_outcome(mixed).1, HandleOutcome::Invalid(OutcomeSource::BaseValues));
+        assert!(handler.events.is_empty());
+        let next = QuoteEvent::at(ts((2024, 1, 2), 9, 30) + 1_000, 102.0, 102.1);
+        assert_eq!(handler.handle_with_outcome(next).1, HandleOutcome::Accepted);
+        assert_eq!(handler.events.len(), 1);
+        assert_eq!(handler.start_values.bid, 102.0);
+    }
+}
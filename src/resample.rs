@@ -0,0 +1,215 @@
+use std::collections::VecDeque;
+
+use anyhow::bail;
+
+use crate::*;
+use chrono_util::make_chrono_features_on;
+use exchange::{default_exchange, Exchange};
+use convert::{adjust, new_series, TimeEmbedder};
+use data_info::*;
+use quote::QuoteEvent;
+use series::SeriesEvent;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ResampleMethod {
+    /// Value of the most recent event at or before the grid point.
+    #[default]
+    LastValue,
+    /// Mean over the interval ending at the grid point, weighted by how long each value was in effect.
+    TimeWeightedMean,
+    /// Linear interpolation between the events on either side of the grid point.
+    Linear,
+}
+
+/// Turns the irregular quote stream into a fixed interval grid ending at the most recent event.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Resampler {
+    /// Grid spacing in millis.
+    pub interval: Timestamp,
+    pub method: ResampleMethod,
+}
+
+/// Resampled bid and ask at a grid point.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct GridPoint {
+    pub timestamp: Timestamp,
+    pub bid: SeriesFloat,
+    pub ask: SeriesFloat,
+}
+
+impl Resampler {
+    pub fn new(interval: Timestamp, method: ResampleMethod) -> anyhow::Result<Self> {
+        let resampler = Self { interval, method };
+        resampler.validate()?;
+        Ok(resampler)
+    }
+
+    /// An interval of 0 would put every grid point on the last event and make the mean NaN.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.interval <= 0 {
+            bail!("Resample interval must be positive, got {}", self.interval);
+        }
+        Ok(())
+    }
+
+    /// Time span the events need to cover to fill a full series.
+    pub fn span(&self) -> Timestamp {
+        self.interval * (SERIES1_SIZE as Timestamp - 1)
+    }
+
+    /// Returns `count` grid points, oldest first, the last one at the timestamp of the last event.
+    /// Events must be in time order and cover the whole grid.
+    pub fn resample(&self, events: &VecDeque<QuoteEvent>, count: usize) -> anyhow::Result<Vec<GridPoint>> {
+        // Deserialized resamplers don't go through new
+        self.validate()?;
+        let (Some(first), Some(last)) = (events.front(), events.back()) else {
+            bail!("No events to resample");
+        };
+        let end = last.timestamp();
+        let start = end - self.interval * (count as Timestamp - 1);
+        // The mean needs the value in effect for the whole interval before the first grid point.
+        let needed = if self.method == ResampleMethod::TimeWeightedMean { start - self.interval } else { start };
+        if first.timestamp() > needed {
+            bail!("Events from {} do not cover grid starting at {}", first.timestamp(), needed);
+        }
+
+        let mut result = Vec::with_capacity(count);
+        // Index of the most recent event at or before the current grid point.
+        let mut i = 0;
+        for n in 0..count {
+            let t = start + self.interval * n as Timestamp;
+            while i + 1 < events.len() && events[i + 1].timestamp() <= t {
+                i += 1;
+            }
+            let (bid, ask) = match self.method {
+                ResampleMethod::LastValue => (events[i].bid, events[i].ask),
+                ResampleMethod::Linear => linear(events, i, t),
+                ResampleMethod::TimeWeightedMean => self.time_weighted_mean(events, i, t),
+            };
+            result.push(GridPoint { timestamp: t, bid, ask });
+        }
+        Ok(result)
+    }
+
+    fn time_weighted_mean(&self, events: &VecDeque<QuoteEvent>, at: usize, t: Timestamp) -> (SeriesFloat, SeriesFloat) {
+        let from = t - self.interval;
+        // Walk back from the event in effect at t to the one in effect at the start of the interval.
+        let mut j = at;
+        while j > 0 && events[j].timestamp() > from {
+            j -= 1;
+        }
+        let (mut bid_sum, mut ask_sum) = (0f64, 0f64);
+        let mut seg_end = t;
+        for k in (j..=at).rev() {
+            let seg_start = events[k].timestamp().max(from);
+            let weight = (seg_end - seg_start) as f64;
+            bid_sum += weight * events[k].bid as f64;
+            ask_sum += weight * events[k].ask as f64;
+            seg_end = seg_start;
+        }
+        let total = (t - from) as f64;
+        ((bid_sum / total) as SeriesFloat, (ask_sum / total) as SeriesFloat)
+    }
+
+    /// Same as series_to_input but over the resampled grid instead of the raw events.
+    pub fn series_to_input(&self, events: &VecDeque<QuoteEvent>) -> anyhow::Result<InputRaw> {
+        self.series_to_input_on(default_exchange(), events)
+    }
+
+    /// With the chrono features on the stream's exchange.
+    pub fn series_to_input_on(&self, exchange: &Exchange, events: &VecDeque<QuoteEvent>) -> anyhow::Result<InputRaw> {
+        let points = self.resample(events, SERIES1_SIZE)?;
+        let mut input = new_series();
+        let embedder = TimeEmbedder::<TIME_EMBEDDING_SIZE>::new();
+
+        // indexing ok because resample returns exactly SERIES1_SIZE points
+        let base = points[SERIES1_SIZE - 1];
+        for (point, input_column) in points.iter().zip(input.iter_mut()) {
+            input_column[0] = adjust(base.bid / point.bid);
+            input_column[1] = adjust(base.ask / point.ask);
+            input_column[2..(2 + TIME_EMBEDDING_SIZE)].copy_from_slice(&embedder.embed(base.timestamp - point.timestamp));
+        }
+
+        Ok((make_chrono_features_on(exchange, base.timestamp), input))
+    }
+}
+
+fn linear(events: &VecDeque<QuoteEvent>, at: usize, t: Timestamp) -> (SeriesFloat, SeriesFloat) {
+    let prev = &events[at];
+    match events.get(at + 1) {
+        Some(next) if next.timestamp() > prev.timestamp() => {
+            let w = (t - prev.timestamp()) as SeriesFloat / (next.timestamp() - prev.timestamp()) as SeriesFloat;
+            (prev.bid + w * (next.bid - prev.bid), prev.ask + w * (next.ask - prev.ask))
+        },
+        _ => (prev.bid, prev.ask),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events() -> VecDeque<QuoteEvent> {
+        [(0, 10.0), (1_000, 20.0), (2_500, 30.0), (4_000, 40.0)].into_iter().map(|(t, bid)| QuoteEvent::at(t, bid, bid + 1.0)).collect()
+    }
+
+    fn bids(method: ResampleMethod, count: usize) -> Vec<SeriesFloat> {
+        Resampler::new(1_000, method).unwrap().resample(&events(), count).unwrap().iter().map(|p| p.bid).collect()
+    }
+
+    #[test]
+    fn grid_ends_at_last_event() {
+        let points = Resampler::new(1_000, ResampleMethod::LastValue).unwrap().resample(&events(), 4).unwrap();
+        let times: Vec<Timestamp> = points.iter().map(|p| p.timestamp).collect();
+        assert_eq!(times, vec![1_000, 2_000, 3_000, 4_000]);
+    }
+
+    #[test]
+    fn last_value_includes_event_at_grid_point() {
+        assert_eq!(bids(ResampleMethod::LastValue, 4), vec![20.0, 20.0, 30.0, 40.0]);
+    }
+
+    #[test]
+    fn linear_interpolates_between_neighbours() {
+        let bids = bids(ResampleMethod::Linear, 4);
+        let expected = [20.0, 20.0 + 10.0 * 1_000.0 / 1_500.0, 30.0 + 10.0 * 500.0 / 1_500.0, 40.0];
+        for (b, e) in bids.iter().zip(expected) {
+            assert!((b - e).abs() < 1e-4, "{} != {}", b, e);
+        }
+    }
+
+    #[test]
+    fn time_weighted_mean_over_interval_ending_at_point() {
+        // An event exactly at the grid point has no weight in the interval ending there.
+        assert_eq!(bids(ResampleMethod::TimeWeightedMean, 4), vec![10.0, 20.0, 25.0, 30.0]);
+    }
+
+    #[test]
+    fn errors_when_events_do_not_cover_grid() {
+        assert!(Resampler::new(1_000, ResampleMethod::LastValue).unwrap().resample(&VecDeque::new(), 4).is_err());
+        assert!(Resampler::new(1_000, ResampleMethod::LastValue).unwrap().resample(&events(), 5).is_ok());
+        assert!(Resampler::new(1_000, ResampleMethod::LastValue).unwrap().resample(&events(), 6).is_err());
+        // The mean also needs the interval before the first point.
+        assert!(Resampler::new(1_000, ResampleMethod::TimeWeightedMean).unwrap().resample(&events(), 5).is_err());
+    }
+
+    #[test]
+    fn series_to_input_covers_span() {
+        let resampler = Resampler::new(100, ResampleMethod::LastValue).unwrap();
+        let events: VecDeque<QuoteEvent> = (0..=resampler.span() / 50).map(|i| QuoteEvent::at(i * 50, 10.0, 10.1)).collect();
+        let (_, input) = resampler.series_to_input(&events).unwrap();
+        assert!(input.iter().all(|row| row[0] == adjust(1.0) && row[1] == adjust(1.0)));
+        assert!(resampler.series_to_input(&events.iter().skip(1).cloned().collect()).is_err());
+    }
+
+    #[test]
+    fn rejects_non_positive_interval() {
+        assert!(Resampler::new(0, ResampleMethod::TimeWeightedMean).is_err());
+        assert!(Resampler::new(-1_000, ResampleMethod::LastValue).is_err());
+        let resampler: Resampler = serde_json::from_str(r#"{"interval": 0, "method": "TimeWeightedMean"}"#).unwrap();
+        assert!(resampler.validate().is_err());
+        assert!(resampler.resample(&events(), 1).is_err());
+    }
+}
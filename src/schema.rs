@@ -0,0 +1,127 @@
+// JSON Schema for the public serde types, so non-Rust consumers have a contract for the topics and configs.
+// Enabled by the schema feature.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{RootSchema, Schema};
+use schemars::{schema_for, JsonSchema};
+use serde_json::json;
+
+use crate::*;
+use data_info::CURRENT_VERSION;
+
+/// Timestamps in the raw quote feeds can be a number or a string of digits, see deserialize_number_from_string.
+pub struct StringOrNumber;
+
+impl JsonSchema for StringOrNumber {
+    fn schema_name() -> String {
+        "StringOrNumber".to_string()
+    }
+
+    fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+        let value = json!({
+            "description": "Millis since the epoch as an integer or a string of digits",
+            "anyOf": [
+                { "type": "integer", "format": "int64" },
+                { "type": "string", "pattern": "^-?[0-9]+$" }
+            ]
+        });
+        // unwrap ok because valid schema
+        serde_json::from_value(value).unwrap()
+    }
+}
+
+macro_rules! schemas {
+    ($($name:literal => $t:ty),* $(,)?) => {
+        vec![$(($name, schema_for!($t))),*]
+    };
+}
+
+/// Schema for every public serde type by type name.
+pub fn all_schemas() -> Vec<(&'static str, RootSchema)> {
+    schemas![
+        // Topics
+        "QuoteEvent" => quote::QuoteEvent,
+        "LabelEvent" => label::LabelEvent,
+        "OptionQuoteEvent" => options::OptionQuoteEvent,
+        "InferStored" => stored::InferStored,
+        "RealizedRow" => stored::RealizedRow,
+        // Config
+        "DataConfig" => data_info::DataConfig,
+        "QuoteStreamSpec" => data_info::QuoteStreamSpec,
+        "TradeStreamSpec" => data_info::TradeStreamSpec,
+        "LabelSpec" => label::LabelSpec,
+        "Exchange" => exchange::Exchange,
+        "SessionConfig" => session::SessionConfig,
+        "GapConfig" => gap::GapConfig,
+        "FeatureSet" => features::FeatureSet,
+        "Resampler" => resample::Resampler,
+        "EmbeddingMode" => convert::EmbeddingMode,
+        "SeriesEncoding" => encoding::SeriesEncoding,
+        "TripleBarrierConfig" => labeling::TripleBarrierConfig,
+        "DirectionConfig" => labeling::DirectionConfig,
+        "MacroEventSpec" => macro_events::MacroEventSpec,
+        "MacroFeatures" => macro_events::MacroFeatures,
+        "SyntheticConfig" => synthetic::SyntheticConfig,
+        "BacktestConfig" => backtest::BacktestConfig,
+        "EvalConfig" => eval::EvalConfig,
+        // Reports and state
+        "GapStats" => gap::GapStats,
+        "EvalReport" => eval::EvalReport,
+        "BacktestResult" => backtest::BacktestResult,
+        "AccuracyReport" => encoding::AccuracyReport,
+        "DayQualityReport" => quality::DayQualityReport,
+        "FeatureLayout" => layout::FeatureLayout,
+        "OutputHead" => prediction::OutputHead,
+        "OptionContract" => options::OptionContract,
+        "Greeks" => options::Greeks,
+        "ChronoFeature" => chrono_util::ChronoFeature,
+        "Session" => session::Session,
+        "QuoteHandlerSnapshot" => checkpoint::HandlerSnapshot<quote::QuoteEvent, quote::QuoteValues>,
+        "OptionQuoteHandlerSnapshot" => checkpoint::HandlerSnapshot<options::OptionQuoteEvent, options::OptionQuoteValues>,
+    ]
+}
+
+/// Writes {name}.schema.json for all_schemas into dir/v{version} with the version in each schema as x-version,
+/// and an index.json of the names. Returns the schema files written.
+/// The version is passed in like everywhere else but must be CURRENT_VERSION, the schemas are of the current types.
+pub fn write_schemas(dir: &Path, version: VersionType) -> anyhow::Result<Vec<PathBuf>> {
+    if version != CURRENT_VERSION {
+        bail!("Schemas are for version {} but {} was given", CURRENT_VERSION, version);
+    }
+    let dir = dir.join(format!("v{}", version));
+    std::fs::create_dir_all(&dir).with_context(|| format!("Could not create schema directory {:?}", dir))?;
+    let mut paths = Vec::new();
+    let mut names = Vec::new();
+    for (name, schema) in all_schemas() {
+        let mut value = serde_json::to_value(&schema)?;
+        value["x-version"] = json!(version);
+        let path = dir.join(format!("{}.schema.json", name));
+        std::fs::write(&path, serde_json::to_string_pretty(&value)?).with_context(|| format!("Could not write schema {:?}", path))?;
+        paths.push(path);
+        names.push(name);
+    }
+    let index = json!({ "version": version, "schemas": names });
+    std::fs::write(dir.join("index.json"), serde_json::to_string_pretty(&index)?)?;
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_current_version_only() {
+        let dir = std::env::temp_dir().join(format!("schemas-{}", std::process::id()));
+        assert!(write_schemas(&dir, CURRENT_VERSION + 1).is_err());
+        let paths = write_schemas(&dir, CURRENT_VERSION).unwrap();
+        assert_eq!(paths.len(), all_schemas().len());
+        let index: serde_json::Value = serde_json::from_slice(&std::fs::read(dir.join(format!("v{}/index.json", CURRENT_VERSION))).unwrap()).unwrap();
+        assert_eq!(index["version"], CURRENT_VERSION);
+        let schema: serde_json::Value = serde_json::from_slice(&std::fs::read(&paths[0]).unwrap()).unwrap();
+        assert_eq!(schema["x-version"], CURRENT_VERSION);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
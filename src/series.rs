@@ -1,4 +1,5 @@
 use crate::*;
+use exchange::Exchange;
 
 pub trait EventType = SeriesEvent + DeserializeOwned;
 
@@ -13,7 +14,18 @@ pub trait SeriesEvent {
 
     fn set_ids(&mut self, event_id: EventId, offset: OffsetId);
     fn timestamp(&self) -> Timestamp;
-    fn validity(&self, base: &Self::BV) -> Validity;
+    /// The exchange is the stream's, for session dates and trading time.
+    fn validity(&self, base: &Self::BV, exchange: &Exchange) -> Validity;
+
+    /// Called with the gap size in millis when the handler's gap policy is GapPolicy::Marker.
+    fn mark_gap(&mut self, _gap: Timestamp) {
+        // default do nothing
+    }
+
+    /// Gap set by mark_gap, 0 if none.
+    fn gap_before(&self) -> Timestamp {
+        0
+    }
 
     // fn event_in_trading_time(&self) -> bool {
     //     ts_in_trading_time(self.timestamp())
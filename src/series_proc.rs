@@ -1,6 +1,10 @@
 use std::collections::VecDeque;
 
+use data_info::QuoteStreamSpec;
+use exchange::Exchange;
+use gap::{GapDetector, GapPolicy};
 use series::{EventType, Validity};
+use session::SessionFilter;
 
 use crate::*;
 
@@ -18,9 +22,10 @@ use crate::*;
 //     proc: P
 // }
 
+/// The exchange is the stream's, for session dates and trading time.
 pub trait BaseValues<T> {
-    fn convert_from(event: &T) -> Self;
-    fn validity(&self, event: &T) -> Validity;
+    fn convert_from(event: &T, exchange: &Exchange) -> Self;
+    fn validity(&self, event: &T, exchange: &Exchange) -> Validity;
 }
 
 pub trait EventHandler<T: EventType> {
@@ -32,6 +37,16 @@ pub trait Processor<T,S> {
     fn reset(&mut self) {
         // default do nothing
     }
+
+    /// State to include in handler checkpoints, see checkpoint.rs.
+    fn snapshot(&self) -> anyhow::Result<serde_json::Value> {
+        Ok(serde_json::Value::Null)
+    }
+
+    fn restore(&mut self, _state: serde_json::Value) -> anyhow::Result<()> {
+        // default nothing to restore
+        Ok(())
+    }
 }
 
 // pub struct BaseHandler<S: Default + BaseValues<T>, T: EventType, P>
@@ -40,17 +55,35 @@ pub struct BaseHandler<S: Default + BaseValues<T>, T: EventType, P>
 where P: Processor<VecDeque<T>,S> {
     pub events: VecDeque<T>,
     pub start_values: S,
-    pub proc: P
+    pub proc: P,
+    pub gaps: GapDetector,
+    /// Accepts only the regular session by default.
+    pub sessions: SessionFilter,
+    /// US equities by default, see QuoteStreamSpec::exchange.
+    pub exchange: Exchange,
 }
 
 impl<S: Default + BaseValues<T>, T: EventType, P: Processor<VecDeque<T>,S>> BaseHandler<S,T,P> {
 // impl<S: Default + BaseValues<T>, T: EventType, P: Fn(&mut VecDeque<T>) -> bool> BaseHandler<S,T,P> {
     pub fn new(proc: P) -> Self {
-        Self { events: VecDeque::new(), start_values: S::default(), proc }
+        Self::new_with_gaps(proc, GapDetector::default())
+    }
+
+    pub fn new_with_gaps(proc: P, gaps: GapDetector) -> Self {
+        Self { events: VecDeque::new(), start_values: S::default(), proc, gaps, sessions: SessionFilter::default(), exchange: Exchange::default() }
+    }
+
+    pub fn new_with_exchange(proc: P, exchange: Exchange, sessions: SessionFilter) -> Self {
+        Self { exchange, sessions, ..Self::new(proc) }
+    }
+
+    /// Uses the exchange and sessions configured for the stream.
+    pub fn for_quote_stream(proc: P, spec: &QuoteStreamSpec) -> Self {
+        Self::new_with_exchange(proc, spec.exchange.clone(), SessionFilter::new(spec.sessions.clone()))
     }
 
     pub fn start_with(&mut self, event: &T) {
-        self.start_values = S::convert_from(event);
+        self.start_values = S::convert_from(event, &self.exchange);
     }
 
     fn reset(&mut self) {
@@ -66,7 +99,7 @@ impl<S: Default + BaseValues<T>, T: EventType, P: Processor<VecDeque<T>,S>> Base
 
         // TODO: replace unwrap?
         let nev = self.events.front().unwrap();
-        self.start_values = S::convert_from(nev);
+        self.start_values = S::convert_from(nev, &self.exchange);
         // self.start_with(&);
     }
 }
@@ -74,24 +107,79 @@ impl<S: Default + BaseValues<T>, T: EventType, P: Processor<VecDeque<T>,S>> Base
 impl<S: Default + BaseValues<T>,T: EventType,P: Processor<VecDeque<T>,S>> EventHandler<T> for BaseHandler<S,T,P> {
 // impl<S: Default + BaseValues<T>, T: EventType, P: Fn(&mut VecDeque<T>) -> bool> EventHandler<T> for BaseHandler<S,T,P> {
     fn handle(&mut self, event: T) -> bool {
-        match self.start_values.validity(&event) {
+        self.handle_with_outcome(event).0
+    }
+}
+
+/// Which check rejected or reset on an event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutcomeSource {
+    /// The SessionFilter: session not accepted or session changed.
+    Session,
+    /// The BaseValues validity, e.g. bid/ask sessions differ or a new session date.
+    BaseValues,
+}
+
+impl OutcomeSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutcomeSource::Session => "session",
+            OutcomeSource::BaseValues => "base_values",
+        }
+    }
+}
+
+/// What BaseHandler did with an event, for monitoring and data quality reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandleOutcome {
+    Accepted,
+    /// Accepted after a gap of the given millis, the handler was reset first if GapPolicy::Reset.
+    AcceptedAfterGap(Timestamp),
+    /// The handler was reset and started over with this event.
+    Reset(OutcomeSource),
+    /// The event was dropped and the handler reset.
+    Invalid(OutcomeSource),
+}
+
+impl<S: Default + BaseValues<T>, T: EventType, P: Processor<VecDeque<T>,S>> BaseHandler<S,T,P> {
+    /// Same as handle, also returning what was done with the event.
+    pub fn handle_with_outcome(&mut self, mut event: T) -> (bool, HandleOutcome) {
+        let (validity, source) = match self.sessions.check(&self.exchange, event.timestamp()) {
+            Validity::Invalid => (Validity::Invalid, OutcomeSource::Session),
+            // A session switch only starts over with the event if the event itself is valid
+            session => match (self.start_values.validity(&event, &self.exchange), session) {
+                (Validity::Invalid, _) => (Validity::Invalid, OutcomeSource::BaseValues),
+                (_, Validity::CauseReset) => (Validity::CauseReset, OutcomeSource::Session),
+                (base, _) => (base, OutcomeSource::BaseValues),
+            },
+        };
+        match validity {
             Validity::Valid => {
+                let gap = self.events.back().and_then(|prev| self.gaps.check(&self.exchange, prev.timestamp(), event.timestamp()));
+                if let Some(gap) = gap {
+                    match self.gaps.policy() {
+                        GapPolicy::Ignore => (),
+                        GapPolicy::Reset => self.reset(),
+                        GapPolicy::Marker => event.mark_gap(gap),
+                    }
+                }
                 if self.events.is_empty() {
                     // It's the first event ever or after reset
                     self.start_with(&event);
                 }
                 self.events.push_back(event);
-                self.proc.process(&self.start_values, &mut self.events)
+                let outcome = gap.map_or(HandleOutcome::Accepted, HandleOutcome::AcceptedAfterGap);
+                (self.proc.process(&self.start_values, &mut self.events), outcome)
             },
             Validity::CauseReset => {
                 self.reset();
                 self.start_with(&event);
                 self.events.push_back(event);
-                true
+                (true, HandleOutcome::Reset(source))
             },
             Validity::Invalid => {
                 self.reset();
-                true
+                (true, HandleOutcome::Invalid(source))
             },
         }
     }
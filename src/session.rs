@@ -0,0 +1,125 @@
+use chrono::NaiveTime;
+
+use crate::*;
+use exchange::{default_exchange, Exchange};
+use series::Validity;
+
+/// Times are for Exchange::us_equities, other exchanges have their own schedule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Session {
+    /// 4:00 to 9:30.
+    PreMarket,
+    /// 9:30 to the close, 16:00 or 13:00 on early close days.
+    Regular,
+    /// The close to 20:00.
+    PostMarket,
+    /// 20:00 to 4:00 before a trading day.
+    Overnight,
+    /// Weekends and holidays outside the overnight session.
+    Closed,
+}
+
+impl Session {
+    /// Index of the session for use as a feature, 0 to 4.
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Session::PreMarket => "pre_market",
+            Session::Regular => "regular",
+            Session::PostMarket => "post_market",
+            Session::Overnight => "overnight",
+            Session::Closed => "closed",
+        }
+    }
+}
+
+pub fn pre_market_open_time() -> NaiveTime {
+    // unwrap ok because valid time
+    NaiveTime::from_hms_opt(4, 0, 0).unwrap()
+}
+
+pub fn post_market_close_time() -> NaiveTime {
+    // unwrap ok because valid time
+    NaiveTime::from_hms_opt(20, 0, 0).unwrap()
+}
+
+/// The trading date a market time belongs to on the default exchange. Times from 20:00 belong to the next day's overnight session.
+pub fn session_date(dt: MarketTimestamp) -> NaiveDate {
+    default_exchange().session_date(dt.timestamp_millis())
+}
+
+/// Session on the default exchange, see Exchange::session_of for others.
+pub fn session_of(dt: MarketTimestamp) -> Session {
+    session_of_ts(dt.timestamp_millis())
+}
+
+pub fn session_of_ts(ts: Timestamp) -> Session {
+    default_exchange().session_of(ts)
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum SessionChange {
+    /// Start a new series when the session changes, e.g. at the open.
+    #[default]
+    Reset,
+    /// Keep the series going across accepted sessions of the same session date.
+    Continue,
+}
+
+/// Which sessions a stream accepts, configured per stream in the stream spec.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SessionConfig {
+    pub accepted: Vec<Session>,
+    #[serde(default)]
+    pub on_change: SessionChange,
+}
+
+impl Default for SessionConfig {
+    /// Regular session only, like ts_in_trading_time.
+    fn default() -> Self {
+        Self { accepted: vec![Session::Regular], on_change: SessionChange::Reset }
+    }
+}
+
+/// Checks events against the SessionConfig, used by BaseHandler before the base values validity.
+#[derive(Debug, Default)]
+pub struct SessionFilter {
+    pub config: SessionConfig,
+    current: Option<Session>,
+}
+
+impl SessionFilter {
+    pub fn new(config: SessionConfig) -> Self {
+        Self { config, current: None }
+    }
+
+    pub fn current(&self) -> Option<Session> {
+        self.current
+    }
+
+    /// Continues from a saved current session, see checkpoint.rs.
+    pub fn restore(&mut self, current: Option<Session>) {
+        self.current = current;
+    }
+
+    pub fn check(&mut self, exchange: &Exchange, ts: Timestamp) -> Validity {
+        let session = exchange.session_of(ts);
+        if !self.config.accepted.contains(&session) {
+            self.current = None;
+            return Validity::Invalid;
+        }
+        let previous = self.current.replace(session);
+        match previous {
+            Some(prev) if prev != session && self.config.on_change == SessionChange::Reset => Validity::CauseReset,
+            _ => Validity::Valid,
+        }
+    }
+}
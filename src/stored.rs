@@ -1,5 +1,8 @@
+use std::collections::{BTreeMap, HashMap};
+
 use crate::*;
 use data_info::{InputRaw, LabelType};
+use label::LabelEvent;
 
 
 pub type LabelTypeStored = LabelType; // [u8; std::mem::size_of::<LabelType>()];
@@ -37,18 +40,186 @@ pub struct TrainStoredWithLabel {
     pub label: LabelTypeStored,
 }
 
-// pub type InferType = LabelType;
+pub type InferType = LabelType;
+
+/// Identifies the model that produced an inference.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ModelId {
+    pub version: VersionType,
+    /// Name of the model manifest or artifact, if any.
+    #[serde(default)]
+    pub manifest: Option<String>,
+}
+
+/// The id is of the most recent event that was included in the inference.
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct InferStored {
+    pub event_id: EventId,
+    pub timestamp: Timestamp,
+    pub model: ModelId,
+    pub output: InferType,
+    /// Millis from receiving the event to producing the output.
+    pub latency: Timestamp,
+}
+
+impl InferStored {
+    pub fn new(event_id: EventId, timestamp: Timestamp, model: ModelId, output: InferType, latency: Timestamp) -> Self {
+        Self { event_id, timestamp, model, output, latency }
+    }
+}
+
+/// An inference joined with the label that arrived later for the same event_id.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct RealizedRow {
+    pub event_id: EventId,
+    pub timestamp: Timestamp,
+    pub model: ModelId,
+    pub latency: Timestamp,
+    pub predicted: InferType,
+    pub realized: LabelType,
+}
+
+impl RealizedRow {
+    pub fn new(infer: InferStored, label: &LabelEvent) -> Self {
+        Self::realized(infer, label.label)
+    }
+
+    fn realized(infer: InferStored, realized: LabelType) -> Self {
+        Self { event_id: infer.event_id, timestamp: infer.timestamp, model: infer.model, latency: infer.latency, predicted: infer.output, realized }
+    }
+}
+
+/// Joins all inferences that have a label, in the order of the inferences.
+pub fn join_labels(infers: impl IntoIterator<Item = InferStored>, labels: &[LabelEvent]) -> Vec<RealizedRow> {
+    let by_id: HashMap<EventId, &LabelEvent> = labels.iter().map(|l| (l.event_id, l)).collect();
+    infers.into_iter().filter_map(|infer| by_id.get(&infer.event_id).map(|label| RealizedRow::new(infer, label))).collect()
+}
+
+/// Streaming join: inferences wait until the label for their event_id arrives, and labels read
+/// before their inference wait for it.
+#[derive(Debug, Default)]
+pub struct InferLabelJoiner {
+    pending: BTreeMap<EventId, InferStored>,
+    early_labels: BTreeMap<EventId, LabelType>,
+}
+
+impl InferLabelJoiner {
+    /// Returns the joined row if the label for this inference already arrived.
+    pub fn add_infer(&mut self, infer: InferStored) -> Option<RealizedRow> {
+        match self.early_labels.remove(&infer.event_id) {
+            Some(label) => Some(RealizedRow::realized(infer, label)),
+            None => {
+                self.pending.insert(infer.event_id, infer);
+                None
+            },
+        }
+    }
 
-// /// The result of an inference.
-// #[derive(Debug,Default)]
-// pub struct Infer {
-//     pub value: InferType
-// }
+    /// Returns the joined row if an inference was waiting for this label.
+    pub fn add_label(&mut self, label: &LabelEvent) -> Option<RealizedRow> {
+        let row = self.pending.remove(&label.event_id).map(|infer| RealizedRow::new(infer, label));
+        if row.is_none() {
+            self.early_labels.insert(label.event_id, label.label);
+        }
+        row
+    }
+
+    /// Inferences waiting for their label.
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Labels waiting for their inference.
+    pub fn early_label_count(&self) -> usize {
+        self.early_labels.len()
+    }
+
+    /// Drops inferences and labels older than the given event_id, whose match will never arrive. Returns how many were dropped.
+    pub fn expire_before(&mut self, event_id: EventId) -> usize {
+        let keep = self.pending.split_off(&event_id);
+        let keep_labels = self.early_labels.split_off(&event_id);
+        let dropped = self.pending.len() + self.early_labels.len();
+        self.pending = keep;
+        self.early_labels = keep_labels;
+        dropped
+    }
+}
 
-// #[derive(Debug)]
-// /// The id is of the most recent event that was included in the inference.
-// pub struct InferStored {
-//     pub event_id: EventId,
-//     pub timestamp: Timestamp,
-//     pub inference: Infer,
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn infer(event_id: EventId) -> InferStored {
+        InferStored::new(event_id, event_id as Timestamp * 1_000, ModelId { version: 2, manifest: None }, [event_id as ModelFloat; 8], 5)
+    }
+
+    fn label(event_id: EventId) -> LabelEvent {
+        LabelEvent::new(event_id, event_id as Timestamp * 1_000, 1, 10, [-(event_id as ModelFloat); 8])
+    }
+
+    #[test]
+    fn joins_label_after_infer() {
+        let mut joiner = InferLabelJoiner::default();
+        assert!(joiner.add_infer(infer(3)).is_none());
+        assert_eq!(joiner.pending_count(), 1);
+        let row = joiner.add_label(&label(3)).unwrap();
+        assert_eq!((row.event_id, row.timestamp, row.latency), (3, 3_000, 5));
+        assert_eq!(row.predicted, [3.0; 8]);
+        assert_eq!(row.realized, [-3.0; 8]);
+        assert_eq!(joiner.pending_count(), 0);
+        assert_eq!(joiner.early_label_count(), 0);
+    }
+
+    #[test]
+    fn joins_label_before_infer() {
+        let mut joiner = InferLabelJoiner::default();
+        assert!(joiner.add_label(&label(4)).is_none());
+        assert_eq!(joiner.early_label_count(), 1);
+        let row = joiner.add_infer(infer(4)).unwrap();
+        assert_eq!(row.predicted, [4.0; 8]);
+        assert_eq!(row.realized, [-4.0; 8]);
+        assert_eq!(joiner.pending_count(), 0);
+        assert_eq!(joiner.early_label_count(), 0);
+    }
+
+    #[test]
+    fn unmatched_infer_stays_pending() {
+        let mut joiner = InferLabelJoiner::default();
+        joiner.add_infer(infer(1));
+        joiner.add_infer(infer(2));
+        assert!(joiner.add_label(&label(2)).is_some());
+        assert_eq!(joiner.pending_count(), 1);
+        assert!(joiner.add_label(&label(2)).is_none());
+        assert!(joiner.add_label(&label(1)).is_some());
+    }
+
+    #[test]
+    fn expire_before_drops_only_older() {
+        let mut joiner = InferLabelJoiner::default();
+        for id in [1, 2, 5, 6] {
+            joiner.add_infer(infer(id));
+        }
+        joiner.add_label(&label(3));
+        joiner.add_label(&label(7));
+        // The cutoff itself is kept.
+        assert_eq!(joiner.expire_before(5), 3);
+        assert_eq!(joiner.pending_count(), 2);
+        assert_eq!(joiner.early_label_count(), 1);
+        assert!(joiner.add_label(&label(5)).is_some());
+        assert!(joiner.add_infer(infer(7)).is_some());
+        assert!(joiner.add_infer(infer(3)).is_none());
+    }
+
+    #[test]
+    fn join_labels_out_of_order() {
+        let infers = [5, 1, 3, 9].map(infer);
+        let labels = [9, 3, 7, 5].map(label);
+        let rows = join_labels(infers, &labels);
+        // In the order of the inferences, without the ones that have no label.
+        assert_eq!(rows.iter().map(|r| r.event_id).collect::<Vec<_>>(), vec![5, 3, 9]);
+        assert!(rows.iter().all(|r| r.realized[0] == -r.predicted[0]));
+    }
+}
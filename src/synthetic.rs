@@ -0,0 +1,269 @@
+use chrono::Duration;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rand_distr::{Distribution, Exp, Normal, StandardNormal};
+
+use crate::*;
+use exchange::Exchange;
+use quote::QuoteEvent;
+
+const MILLIS_PER_DAY: Timestamp = 24 * 60 * 60 * 1000;
+const TRADING_DAYS_PER_YEAR: f64 = 252.0;
+
+/// Faults to inject, each as a probability per event.
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FaultConfig {
+    /// Skip gap_millis of the session before the event.
+    pub gap: f64,
+    pub gap_millis: Timestamp,
+    /// Swap bid and ask.
+    pub crossed: f64,
+    /// Move the event's timestamps before the previous event.
+    pub out_of_order: f64,
+    /// Move the biddate back a day so it doesn't match the askdate.
+    pub date_mismatch: f64,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SyntheticConfig {
+    pub seed: u64,
+    /// First day to generate, days the exchange doesn't trade are skipped.
+    pub start: NaiveDate,
+    pub days: usize,
+    pub initial_price: f64,
+    /// Annualized drift and volatility of the GBM.
+    pub drift: f64,
+    pub volatility: f64,
+    /// Expected jumps per day and std dev of the jump log return.
+    pub jumps_per_day: f64,
+    pub jump_std: f64,
+    /// Mean quotes per second at midday.
+    pub base_rate: f64,
+    /// How much higher volatility and arrival rate are at the open and close than midday.
+    pub intraday_multiplier: f64,
+    pub spread_bps: f64,
+    /// Extra spread at the open that decays over open_spread_minutes.
+    pub open_spread_multiplier: f64,
+    pub open_spread_minutes: f64,
+    pub faults: FaultConfig,
+    /// Sessions and holidays of the generated days, US equities if missing.
+    #[serde(default)]
+    pub exchange: Exchange,
+}
+
+impl Default for SyntheticConfig {
+    fn default() -> Self {
+        // unwrap ok because the date is valid
+        let start = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        Self {
+            seed: 0, start, days: 1, initial_price: 500.0,
+            drift: 0.05, volatility: 0.15, jumps_per_day: 0.5, jump_std: 0.002,
+            base_rate: 5.0, intraday_multiplier: 2.0,
+            spread_bps: 0.2, open_spread_multiplier: 4.0, open_spread_minutes: 10.0,
+            faults: FaultConfig::default(), exchange: Exchange::default(),
+        }
+    }
+}
+
+/// Seeded generator of QuoteEvents within regular sessions. The same config always produces the same events.
+pub struct SyntheticMarket {
+    config: SyntheticConfig,
+    rng: ChaCha8Rng,
+    date: NaiveDate,
+    days_left: usize,
+    open: Timestamp,
+    /// Earlier than open + session_millis on early close days.
+    close: Timestamp,
+    /// Length of a full regular session.
+    session_millis: Timestamp,
+    /// Millis since the open of the next event.
+    elapsed: Timestamp,
+    log_price: f64,
+    event_id: EventId,
+    last_timestamp: Timestamp,
+}
+
+impl SyntheticMarket {
+    pub fn new(config: SyntheticConfig) -> Self {
+        let exchange = &config.exchange;
+        let date = exchange.next_trading_day(config.start);
+        let (open, close) = (exchange.open_timestamp(date), exchange.close_timestamp(date));
+        let session_millis = (exchange.schedule.close - exchange.schedule.open) as Timestamp * 60_000;
+        let rng = ChaCha8Rng::seed_from_u64(config.seed);
+        let log_price = config.initial_price.ln();
+        Self {
+            days_left: config.days, config, rng, date, open, close, session_millis, elapsed: 0, log_price, event_id: 0, last_timestamp: 0,
+        }
+    }
+
+    /// Rate or volatility multiplier over the session, U shaped with the given multiplier at the ends and 1 midday.
+    fn seasonality(&self, elapsed: Timestamp) -> f64 {
+        let u = 2.0 * elapsed as f64 / self.session_millis as f64 - 1.0;
+        1.0 + (self.config.intraday_multiplier - 1.0) * u * u
+    }
+
+    fn next_day(&mut self) {
+        self.days_left -= 1;
+        let exchange = &self.config.exchange;
+        self.date = exchange.next_trading_day(self.date + Duration::days(1));
+        self.open = exchange.open_timestamp(self.date);
+        self.close = exchange.close_timestamp(self.date);
+        self.elapsed = 0;
+    }
+
+    fn advance_price(&mut self, dt_millis: Timestamp) {
+        let c = &self.config;
+        let dt = dt_millis as f64 / (self.session_millis as f64 * TRADING_DAYS_PER_YEAR);
+        let vol = c.volatility * self.seasonality(self.elapsed);
+        let z: f64 = StandardNormal.sample(&mut self.rng);
+        self.log_price += (c.drift - 0.5 * vol * vol) * dt + vol * dt.sqrt() * z;
+
+        let jump_prob = c.jumps_per_day * dt_millis as f64 / self.session_millis as f64;
+        if c.jump_std > 0.0 && self.rng.gen_bool(jump_prob.clamp(0.0, 1.0)) {
+            // unwrap ok because jump_std > 0
+            self.log_price += Normal::new(0.0, c.jump_std).unwrap().sample(&mut self.rng);
+        }
+    }
+
+    fn make_quote(&mut self, ts: Timestamp) -> QuoteEvent {
+        let c = &self.config;
+        let minutes = self.elapsed as f64 / 60_000.0;
+        let spread_bps = c.spread_bps * (1.0 + c.open_spread_multiplier * (-minutes / c.open_spread_minutes).exp());
+        let mid = self.log_price.exp();
+        let half = (mid * spread_bps / 10_000.0 / 2.0).max(0.005);
+        let bid = ((mid - half) * 100.0).floor() / 100.0;
+        let ask = ((mid + half) * 100.0).ceil() / 100.0;
+        let bidsz = self.rng.gen_range(1..=20) as SeriesFloat * 100.0;
+        let asksz = self.rng.gen_range(1..=20) as SeriesFloat * 100.0;
+        self.event_id += 1;
+        QuoteEvent {
+            event_id: self.event_id, offset: self.event_id,
+            bid: bid as f32, biddate: ts, ask: ask as f32, askdate: ts, bidsz, asksz, gap_before: 0,
+        }
+    }
+
+    fn inject_faults(&mut self, quote: &mut QuoteEvent) {
+        let f = &self.config.faults;
+        let (crossed, out_of_order, date_mismatch) = (f.crossed, f.out_of_order, f.date_mismatch);
+        if crossed > 0.0 && self.rng.gen_bool(crossed) {
+            std::mem::swap(&mut quote.bid, &mut quote.ask);
+        }
+        if out_of_order > 0.0 && self.rng.gen_bool(out_of_order) && self.last_timestamp > self.open {
+            let back = self.rng.gen_range(1..=1000).min(self.last_timestamp - self.open);
+            quote.biddate = self.last_timestamp - back;
+            quote.askdate = quote.biddate;
+        }
+        if date_mismatch > 0.0 && self.rng.gen_bool(date_mismatch) {
+            quote.biddate -= MILLIS_PER_DAY;
+        }
+    }
+}
+
+impl Iterator for SyntheticMarket {
+    type Item = QuoteEvent;
+
+    fn next(&mut self) -> Option<QuoteEvent> {
+        loop {
+            if self.days_left == 0 {
+                return None;
+            }
+            let rate_per_milli = self.config.base_rate * self.seasonality(self.elapsed) / 1000.0;
+            // unwrap ok because rate is positive
+            let mut dt = Exp::new(rate_per_milli.max(1e-9)).unwrap().sample(&mut self.rng).ceil() as Timestamp;
+            let gap = self.config.faults.gap;
+            if gap > 0.0 && self.rng.gen_bool(gap) {
+                dt += self.config.faults.gap_millis;
+            }
+            if self.open + self.elapsed + dt >= self.close {
+                self.next_day();
+                continue;
+            }
+            self.advance_price(dt);
+            self.elapsed += dt;
+
+            let ts = self.open + self.elapsed;
+            let mut quote = self.make_quote(ts);
+            self.inject_faults(&mut quote);
+            self.last_timestamp = ts;
+            return Some(quote);
+        }
+    }
+}
+
+pub fn generate(config: SyntheticConfig) -> Vec<QuoteEvent> {
+    SyntheticMarket::new(config).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    fn config(start: (i32, u32, u32), days: usize) -> SyntheticConfig {
+        SyntheticConfig { start: NaiveDate::from_ymd_opt(start.0, start.1, start.2).unwrap(), days, ..SyntheticConfig::default() }
+    }
+
+    fn dates(events: &[QuoteEvent]) -> BTreeSet<NaiveDate> {
+        events.iter().map(|e| Exchange::default().session_date(e.biddate)).collect()
+    }
+
+    #[test]
+    fn same_seed_same_events() {
+        let a = generate(config((2024, 1, 2), 1));
+        let b = generate(config((2024, 1, 2), 1));
+        assert!(!a.is_empty());
+        assert!(a.iter().zip(&b).all(|(x, y)| (x.biddate, x.bid, x.ask) == (y.biddate, y.bid, y.ask)));
+        let c = generate(SyntheticConfig { seed: 1, ..config((2024, 1, 2), 1) });
+        assert!(a.iter().zip(&c).any(|(x, y)| (x.biddate, x.bid) != (y.biddate, y.bid)));
+    }
+
+    #[test]
+    fn skips_holidays_and_stops_at_early_close() {
+        // July 3rd closes early, the 4th is a holiday, the 6th and 7th are the weekend.
+        let events = generate(config((2024, 7, 3), 3));
+        let d = |m, d| NaiveDate::from_ymd_opt(2024, m, d).unwrap();
+        assert_eq!(dates(&events), BTreeSet::from([d(7, 3), d(7, 5), d(7, 8)]));
+        let exchange = Exchange::default();
+        assert!(events.iter().all(|e| exchange.in_regular_session(e.biddate)));
+        let early_close = exchange.close_timestamp(d(7, 3));
+        let last_on_3rd = events.iter().filter(|e| exchange.session_date(e.biddate) == d(7, 3)).map(|e| e.biddate).max().unwrap();
+        assert!(last_on_3rd < early_close && early_close - last_on_3rd < 60_000);
+        // Starting on a holiday moves to the next trading day.
+        assert_eq!(dates(&generate(config((2024, 1, 1), 1))), BTreeSet::from([d(1, 2)]));
+    }
+
+    #[test]
+    fn follows_the_exchange_sessions() {
+        let exchange = Exchange::xetra();
+        // Good Friday is a trading day on the weekends only calendar.
+        let events = generate(SyntheticConfig { exchange: exchange.clone(), ..config((2024, 3, 29), 1) });
+        assert_eq!(dates(&events), BTreeSet::from([NaiveDate::from_ymd_opt(2024, 3, 29).unwrap()]));
+        assert!(events.iter().all(|e| exchange.in_regular_session(e.biddate)));
+    }
+
+    #[test]
+    fn events_are_ordered_and_valid_without_faults() {
+        let events = generate(config((2024, 1, 2), 1));
+        assert!(events.windows(2).all(|w| w[0].biddate < w[1].biddate && w[0].event_id + 1 == w[1].event_id));
+        assert!(events.iter().all(|e| e.bid < e.ask && e.biddate == e.askdate));
+    }
+
+    #[test]
+    fn injects_faults() {
+        let faults = FaultConfig { gap: 0.001, gap_millis: 120_000, crossed: 0.01, out_of_order: 0.01, date_mismatch: 0.01 };
+        let events = generate(SyntheticConfig { faults, ..config((2024, 1, 2), 1) });
+        let n = events.len() as f64;
+        let crossed = events.iter().filter(|e| e.bid > e.ask).count() as f64;
+        let mismatched = events.iter().filter(|e| e.askdate - e.biddate == MILLIS_PER_DAY).count() as f64;
+        let out_of_order = events.windows(2).filter(|w| w[1].askdate < w[0].askdate).count() as f64;
+        let gaps = events.windows(2).filter(|w| w[1].askdate - w[0].askdate >= 120_000).count();
+        for (count, p) in [(crossed, 0.01), (mismatched, 0.01), (out_of_order, 0.01)] {
+            assert!((count / n - p).abs() < 0.005, "{} of {}", count, n);
+        }
+        assert!(gaps > 0);
+    }
+}
@@ -26,6 +26,7 @@ pub fn divrem(a: u32, b: u32) -> (u32, u32) {
     (a / b, a % b)
 }
 
+#[allow(clippy::manual_slice_size_calculation)]
 pub fn convert_slice<T,U>(v: &[T]) -> &[U] {
     // println!("convert slice {} -> {}, {} -> {}", std::any::type_name::<T>(), std::any::type_name::<T>(), std::mem::size_of::<U>(), std::mem::size_of::<U>());
     let size_from = std::mem::size_of::<T>();
@@ -38,3 +39,52 @@ pub fn convert_slice<T,U>(v: &[T]) -> &[U] {
 // pub fn convert_slice_sized<T,U, const N: usize>>(v: &[T]) -> &[U; N] {
 //     unsafe { std::slice::from_raw_parts(v.as_ptr() as *const U, v.len() * 4) }
 // }
+
+// ---- Normal distribution ---- //
+
+pub fn norm_pdf(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2.0 * std::f64::consts::PI).sqrt()
+}
+
+/// Standard normal CDF, accurate to about 1e-7.
+pub fn norm_cdf(x: f64) -> f64 {
+    0.5 * (1.0 + erf(x / std::f64::consts::SQRT_2))
+}
+
+pub fn erf(x: f64) -> f64 {
+    1.0 - erfc(x)
+}
+
+// Numerical Recipes erfc with fractional error below 1.2e-7
+pub fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let r = t * (-z * z - 1.26551223 + t * (1.00002368 + t * (0.37409196 + t * (0.09678418 + t * (-0.18628806
+        + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))))).exp();
+    if x >= 0.0 { r } else { 2.0 - r }
+}
+
+/// Inverse of the standard normal CDF, Acklam's algorithm, relative error about 1e-9.
+pub fn norm_inv(p: f64) -> f64 {
+    const A: [f64; 6] = [-3.969683028665376e1, 2.209460984245205e2, -2.759285104469687e2, 1.38357751867269e2, -3.066479806614716e1, 2.506628277459239];
+    const B: [f64; 5] = [-5.447609879822406e1, 1.615858368580409e2, -1.556989798598866e2, 6.680131188771972e1, -1.328068155288572e1];
+    const C: [f64; 6] = [-7.784894002430293e-3, -3.223964580411365e-1, -2.400758277161838, -2.549732539343734, 4.374664141464968, 2.938163982698783];
+    const D: [f64; 4] = [7.784695709041462e-3, 3.224671290700398e-1, 2.445134137142996, 3.754408661907416];
+    const P_LOW: f64 = 0.02425;
+    if p <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    if p >= 1.0 {
+        return f64::INFINITY;
+    }
+    if p < P_LOW {
+        let q = (-2.0 * p.ln()).sqrt();
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5]) / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.0)
+    } else if p <= 1.0 - P_LOW {
+        let q = p - 0.5;
+        let r = q * q;
+        (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.0)
+    } else {
+        -norm_inv(1.0 - p)
+    }
+}